@@ -0,0 +1,776 @@
+use eframe::egui;
+use reqwest::Client;
+use std::future::Future;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+use crate::ai_provider::{AIConfig, AIProvider};
+
+/// Give up on a request once the server has sent nothing for this long.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Inline completions are cut short; a suggestion is only worth it if it comes quickly.
+const COMPLETION_MAX_TOKENS: u32 = 64;
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(10);
+/// How much code either side of the cursor a completion request sees.
+const COMPLETION_PREFIX_CHARS: usize = 4000;
+const COMPLETION_SUFFIX_CHARS: usize = 1000;
+
+/// Progress of the running request.
+pub enum AiUpdate {
+    /// More of the reply arrived.
+    Text(String),
+    /// The reply is complete, or the request failed partway.
+    Finished(Result<(), String>),
+}
+
+/// Why an inline completion failed.
+#[derive(Debug, PartialEq)]
+pub enum CompletionError {
+    /// The request timed out or the network let it down; the next one may well work.
+    Transient(String),
+    /// The settings are wrong, like a rejected key or an endpoint that isn't there.
+    Config(String),
+}
+
+struct Pending {
+    generation: u64,
+    task: JoinHandle<()>,
+    started: Instant,
+}
+
+/// Runs AI requests on a background runtime so the UI never waits on the network. Prompts
+/// and inline completions run independently; of each, only the latest request counts and
+/// an older one that finishes late is ignored.
+pub struct AiTasks {
+    /// Started on the first request, so the editor doesn't pay for it otherwise.
+    runtime: Option<(Runtime, Client)>,
+    pending: Option<Pending>,
+    completion: Option<Pending>,
+    generation: u64,
+    tx: Sender<(u64, AiUpdate)>,
+    rx: Receiver<(u64, AiUpdate)>,
+    completion_tx: Sender<(u64, Result<String, CompletionError>)>,
+    completion_rx: Receiver<(u64, Result<String, CompletionError>)>,
+    ctx: egui::Context,
+}
+
+impl AiTasks {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let (completion_tx, completion_rx) = mpsc::channel();
+        Self {
+            runtime: None,
+            pending: None,
+            completion: None,
+            generation: 0,
+            tx,
+            rx,
+            completion_tx,
+            completion_rx,
+            ctx: ctx.clone(),
+        }
+    }
+
+    fn runtime(&mut self, config: &AIConfig) -> Result<(&Runtime, &Client), String> {
+        if config.provider.needs_api_key() && config.api_key.trim().is_empty() {
+            return Err(format!("{} needs an API key", config.provider.label()));
+        }
+        if self.runtime.is_none() {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .thread_name("ai-requests")
+                .enable_all()
+                .build()
+                .map_err(|e| format!("Failed to start the request runtime: {}", e))?;
+            // No overall timeout: a long reply can keep streaming for minutes
+            let client = Client::builder()
+                .connect_timeout(REQUEST_TIMEOUT)
+                .build()
+                .map_err(|e| format!("Failed to create the HTTP client: {}", e))?;
+            self.runtime = Some((runtime, client));
+        }
+        let (runtime, client) = self.runtime.as_ref().ok_or_else(|| "No request runtime".to_string())?;
+        Ok((runtime, client))
+    }
+
+    /// Sends `prompt` to the provider in `config` in the background, cancelling any
+    /// request still running. The reply streams in through `poll`.
+    pub fn start(&mut self, config: &AIConfig, prompt: &str) -> Result<(), String> {
+        self.cancel();
+        self.generation += 1;
+        let generation = self.generation;
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let (runtime, client) = self.runtime(config)?;
+        let (client, config, prompt) = (client.clone(), config.clone(), prompt.to_string());
+        let task = runtime.spawn(async move {
+            let send = |update: AiUpdate| {
+                if tx.send((generation, update)).is_ok() {
+                    ctx.request_repaint();
+                }
+            };
+            let result = stream_ai_response(&client, &config, &prompt, |text| send(AiUpdate::Text(text))).await;
+            send(AiUpdate::Finished(result));
+        });
+        self.pending = Some(Pending { generation, task, started: Instant::now() });
+        Ok(())
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.task.abort();
+        }
+    }
+
+    /// Asks for the code that belongs at `cursor` (a character index) in `content`,
+    /// replacing any completion still running.
+    pub fn start_completion(&mut self, config: &AIConfig, content: &str, cursor: usize) -> Result<(), String> {
+        self.cancel_completion();
+        self.generation += 1;
+        let generation = self.generation;
+        let tx = self.completion_tx.clone();
+        let ctx = self.ctx.clone();
+        let (runtime, client) = self.runtime(config)?;
+
+        let byte = content.char_indices().nth(cursor).map_or(content.len(), |(i, _)| i);
+        let (before, after) = content.split_at(byte);
+        let prefix_start = before.char_indices().rev().nth(COMPLETION_PREFIX_CHARS - 1).map_or(0, |(i, _)| i);
+        let suffix_end = after.char_indices().nth(COMPLETION_SUFFIX_CHARS).map_or(after.len(), |(i, _)| i);
+        let (prefix, suffix) = (before[prefix_start..].to_string(), after[..suffix_end].to_string());
+        let config = AIConfig { max_tokens: COMPLETION_MAX_TOKENS.min(config.max_tokens), ..config.clone() };
+        let client = client.clone();
+        let task = runtime.spawn(async move {
+            let provider = config.provider.provider();
+            let request = provider.completion_request(&client, &config, &prefix, &suffix).send();
+            let result = match tokio::time::timeout(COMPLETION_TIMEOUT, read_json(request)).await {
+                Ok(Ok(body)) => provider
+                    .parse_completion(&body)
+                    .map(|text| clean_completion(&text, &suffix))
+                    .ok_or_else(|| CompletionError::Transient("The response has no completion in it".to_string())),
+                Ok(Err(e)) if is_config_error(&e) => Err(CompletionError::Config(provider_error(provider, e))),
+                Ok(Err(e)) => Err(CompletionError::Transient(provider_error(provider, e))),
+                Err(_) => Err(CompletionError::Transient("The completion took too long".to_string())),
+            };
+            if tx.send((generation, result)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        self.completion = Some(Pending { generation, task, started: Instant::now() });
+        Ok(())
+    }
+
+    pub fn cancel_completion(&mut self) {
+        if let Some(pending) = self.completion.take() {
+            pending.task.abort();
+        }
+    }
+
+    /// The latest completion, once it has finished.
+    pub fn poll_completion(&mut self) -> Option<Result<String, CompletionError>> {
+        let mut finished = None;
+        for (generation, result) in self.completion_rx.try_iter() {
+            if self.completion.as_ref().is_some_and(|p| p.generation == generation) {
+                self.completion = None;
+                finished = Some(result);
+            }
+        }
+        finished
+    }
+
+    /// How long the running request has been going, if there is one.
+    pub fn running_for(&self) -> Option<Duration> {
+        self.pending.as_ref().map(|p| p.started.elapsed())
+    }
+
+    /// What the latest request sent since the last call.
+    pub fn poll(&mut self) -> Vec<AiUpdate> {
+        let mut updates = Vec::new();
+        for (generation, update) in self.rx.try_iter() {
+            if self.pending.as_ref().is_some_and(|p| p.generation == generation) {
+                if let AiUpdate::Finished(_) = update {
+                    self.pending = None;
+                }
+                updates.push(update);
+            }
+        }
+        updates
+    }
+}
+
+impl Drop for AiTasks {
+    fn drop(&mut self) {
+        // Don't hold up closing the editor for a request nobody will read
+        if let Some((runtime, _)) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// `future`, unless the server goes quiet for longer than `REQUEST_TIMEOUT` first.
+async fn idle_timeout<T>(future: impl Future<Output = T>) -> Result<T, String> {
+    tokio::time::timeout(REQUEST_TIMEOUT, future)
+        .await
+        .map_err(|_| format!("No response after {} seconds", REQUEST_TIMEOUT.as_secs()))
+}
+
+/// Why a request failed: the HTTP status and whatever the body says about it.
+enum RequestError {
+    Network(String),
+    Status(reqwest::StatusCode, String),
+}
+
+fn provider_error(provider: &dyn AIProvider, error: RequestError) -> String {
+    match error {
+        RequestError::Network(e) => e,
+        RequestError::Status(status, text) => {
+            let message = serde_json::from_str(&text)
+                .ok()
+                .and_then(|body| provider.error_message(&body))
+                .unwrap_or_else(|| text.trim().chars().take(200).collect());
+            format!("{}: {}", status, message)
+        }
+    }
+}
+
+/// Whether a request failed because of the settings rather than the moment: the key was
+/// refused or the endpoint doesn't exist.
+fn is_config_error(error: &RequestError) -> bool {
+    matches!(error, RequestError::Status(status, _) if [401, 403, 404].contains(&status.as_u16()))
+}
+
+/// The JSON body of a successful response.
+async fn read_json(request: impl Future<Output = reqwest::Result<reqwest::Response>>) -> Result<serde_json::Value, RequestError> {
+    let response = request.await.map_err(|e| RequestError::Network(format!("Request failed: {}", e)))?;
+    let status = response.status();
+    let text = response
+        .text()
+        .await
+        .map_err(|e| RequestError::Network(format!("Failed to read the response: {}", e)))?;
+    if !status.is_success() {
+        return Err(RequestError::Status(status, text));
+    }
+    serde_json::from_str(&text).map_err(|e| RequestError::Network(format!("Unreadable response: {}", e)))
+}
+
+/// A completion as it should be shown: without code fences a chat model may add anyway,
+/// and without repeating the code that already follows the cursor.
+fn clean_completion(text: &str, suffix: &str) -> String {
+    let mut text = text;
+    if let Some(rest) = text.trim_start().strip_prefix("```") {
+        // Drop the opening fence with its language tag, and the closing fence
+        text = rest.split_once('\n').map_or("", |(_, code)| code);
+        text = text.trim_end().strip_suffix("```").unwrap_or(text);
+    }
+    let mut text = text.trim_end();
+    // A completion that runs on into the line after the cursor's ends with a copy of it; a
+    // suffix on the cursor's own line, like the `)` of `foo(|)`, is never cut off the end
+    if suffix.trim_start_matches([' ', '\t']).starts_with(['\n', '\r']) {
+        let next_line = suffix.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+        if let Some((before, last)) = text.rsplit_once('\n') {
+            if !next_line.is_empty() && last.trim() == next_line {
+                text = before;
+            }
+        }
+    }
+    text.trim_end().to_string()
+}
+
+/// What one line of a streamed reply means.
+enum StreamLine {
+    Text(String),
+    Done,
+    Skip,
+}
+
+fn parse_stream_line(provider: &dyn AIProvider, line: &str) -> Result<StreamLine, String> {
+    let data = if provider.line_delimited() {
+        line
+    } else {
+        // Server-sent events; `event:` lines and comments carry nothing the data doesn't
+        match line.strip_prefix("data:") {
+            Some(data) => data.trim_start(),
+            None => return Ok(StreamLine::Skip),
+        }
+    };
+    if data.is_empty() {
+        return Ok(StreamLine::Skip);
+    }
+    if data == "[DONE]" {
+        return Ok(StreamLine::Done);
+    }
+    let event: serde_json::Value = serde_json::from_str(data).map_err(|e| format!("Unreadable reply: {}", e))?;
+    if let Some(message) = provider.error_message(&event) {
+        return Err(message);
+    }
+    if let Some(text) = provider.stream_delta(&event).filter(|t| !t.is_empty()) {
+        return Ok(StreamLine::Text(text));
+    }
+    Ok(if provider.stream_done(&event) { StreamLine::Done } else { StreamLine::Skip })
+}
+
+/// Asks for a streamed reply and hands each piece of text to `on_text` as it arrives.
+/// A server that answers with one complete JSON body instead is handled too.
+async fn stream_ai_response(
+    client: &Client,
+    config: &AIConfig,
+    prompt: &str,
+    mut on_text: impl FnMut(String),
+) -> Result<(), String> {
+    let provider = config.provider.provider();
+    let mut response = idle_timeout(provider.request(client, config, prompt, true).send())
+        .await?
+        .map_err(|e| format!("Request to {} failed: {}", config.endpoint(), e))?;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !status.is_success() || is_json {
+        let text = idle_timeout(response.text())
+            .await?
+            .map_err(|e| format!("Failed to read the response: {}", e))?;
+        if !status.is_success() {
+            return Err(provider_error(provider, RequestError::Status(status, text)));
+        }
+        let reply = serde_json::from_str(&text)
+            .ok()
+            .and_then(|body| provider.parse_response(&body))
+            .ok_or_else(|| "The response has no reply in it".to_string())?;
+        on_text(reply);
+        return Ok(());
+    }
+
+    // Returns true once the reply is complete
+    let mut handle_line = |line: &[u8]| -> Result<bool, String> {
+        match parse_stream_line(provider, String::from_utf8_lossy(line).trim())? {
+            StreamLine::Text(text) => on_text(text),
+            StreamLine::Done => return Ok(true),
+            StreamLine::Skip => {}
+        }
+        Ok(false)
+    };
+    // Split on bytes so a character cut in half between chunks is decoded whole
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = idle_timeout(response.chunk())
+        .await?
+        .map_err(|e| format!("Failed to read the response: {}", e))?
+    {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if handle_line(&line)? {
+                return Ok(());
+            }
+        }
+    }
+    // Whatever followed the last newline
+    handle_line(&buffer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_provider::ProviderKind;
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// What the mock server was sent, and whether each part of its reply waited for the
+    /// client to take in the one before.
+    struct Received {
+        head: String,
+        body: Value,
+        incremental: bool,
+    }
+
+    /// Answers one request on a local port with `status`, `content_type` and then `parts`
+    /// of the body. Every part after the first goes out only once the gate is opened, so
+    /// a reply that is read all at once shows up as not incremental.
+    fn serve(status: &'static str, content_type: &'static str, parts: &[&str]) -> (String, Sender<()>, Receiver<Received>) {
+        let parts: Vec<&[u8]> = parts.iter().map(|p| p.as_bytes()).collect();
+        serve_bytes(status, content_type, &parts)
+    }
+
+    fn serve_bytes(status: &'static str, content_type: &'static str, parts: &[&[u8]]) -> (String, Sender<()>, Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let parts: Vec<Vec<u8>> = parts.iter().map(|p| p.to_vec()).collect();
+        let (gate_tx, gate) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (head, body) = read_request(&stream);
+            write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n", status, content_type).unwrap();
+            let mut incremental = true;
+            for (i, part) in parts.iter().enumerate() {
+                if i > 0 {
+                    incremental &= gate.recv_timeout(Duration::from_secs(5)).is_ok();
+                }
+                stream.write_all(part).unwrap();
+                stream.flush().unwrap();
+            }
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            let _ = tx.send(Received { head, body, incremental });
+        });
+        (address, gate_tx, rx)
+    }
+
+    /// The lowercased head and the body of the request coming in on `stream`.
+    fn read_request(stream: &std::net::TcpStream) -> (String, Vec<u8>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                break;
+            }
+            head.push_str(&line.to_lowercase());
+        }
+        let length = head
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length:"))
+            .map_or(0, |v| v.trim().parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        (head, body)
+    }
+
+    /// Answers one request with the start of an event stream and then holds the connection
+    /// open, reporting whether the client hung up within a few seconds.
+    fn serve_until_closed(first: &'static str) -> (String, Receiver<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&stream);
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n{}", first).unwrap();
+            stream.flush().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let closed = match stream.read(&mut [0; 64]) {
+                Ok(n) => n == 0,
+                Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+            };
+            let _ = tx.send(closed);
+        });
+        (address, rx)
+    }
+
+    /// Polls `tasks` until the latest request finishes, returning everything it sent.
+    fn finish(tasks: &mut AiTasks) -> Vec<AiUpdate> {
+        let mut updates = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !matches!(updates.last(), Some(AiUpdate::Finished(_))) {
+            assert!(Instant::now() < deadline, "the request never finished");
+            updates.extend(tasks.poll());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        updates
+    }
+
+    fn texts(updates: &[AiUpdate]) -> Vec<&str> {
+        updates.iter().filter_map(|u| if let AiUpdate::Text(t) = u { Some(t.as_str()) } else { None }).collect()
+    }
+
+    #[test]
+    fn cancelling_hangs_up_on_the_server() {
+        let (address, closed) = serve_until_closed("data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n");
+        let config = config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", address), "sk-test");
+        let mut tasks = AiTasks::new(&egui::Context::default());
+        tasks.start(&config, "hi").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut updates = Vec::new();
+        while updates.is_empty() {
+            assert!(Instant::now() < deadline, "nothing arrived");
+            updates.extend(tasks.poll());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(texts(&updates), ["Hel"]);
+
+        tasks.cancel();
+        assert!(tasks.running_for().is_none());
+        assert!(closed.recv().unwrap(), "the request was left running");
+        assert!(tasks.poll().is_empty());
+    }
+
+    #[test]
+    fn late_replies_to_replaced_requests_are_ignored() {
+        let (first, _first_gate, _) = serve("200 OK", "text/event-stream", &["data: {\"choices\":[{\"delta\":{\"content\":\"old\"}}]}\n\n"]);
+        let (second, _second_gate, _) = serve(
+            "200 OK",
+            "text/event-stream",
+            &["data: {\"choices\":[{\"delta\":{\"content\":\"new\"}}]}\n\ndata: [DONE]\n\n"],
+        );
+        let mut tasks = AiTasks::new(&egui::Context::default());
+        tasks.start(&config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", first), "sk-test"), "one").unwrap();
+        let replaced = tasks.generation;
+        tasks.start(&config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", second), "sk-test"), "two").unwrap();
+        // What the first request sent before it was stopped is still on its way
+        tasks.tx.send((replaced, AiUpdate::Text("late".to_string()))).unwrap();
+        tasks.tx.send((replaced, AiUpdate::Finished(Err("late".to_string())))).unwrap();
+
+        let updates = finish(&mut tasks);
+        assert_eq!(texts(&updates), ["new"]);
+        assert!(matches!(updates.last(), Some(AiUpdate::Finished(Ok(())))));
+    }
+
+    #[test]
+    fn a_server_that_goes_quiet_times_out() {
+        let (address, _gate, _received) = serve(
+            "200 OK",
+            "text/event-stream",
+            &["data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n", "data: [DONE]\n\n"],
+        );
+        let config = config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", address), "sk-test");
+        let mut pieces = Vec::new();
+        // With the clock paused, the wait for the gate that never opens skips straight to the timeout
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().start_paused(true).build().unwrap();
+        let result = runtime.block_on(stream_ai_response(&Client::new(), &config, "hi", |text| pieces.push(text)));
+        assert_eq!(result, Err(format!("No response after {} seconds", REQUEST_TIMEOUT.as_secs())));
+        assert_eq!(pieces, ["Hel"]);
+    }
+
+    fn config(provider: ProviderKind, endpoint: String, api_key: &str) -> AIConfig {
+        AIConfig { provider, endpoint, model: "test-model".to_string(), max_tokens: 7, api_key: api_key.to_string(), ..AIConfig::default() }
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+    }
+
+    /// Streams a reply to "hi", opening the gate each time a piece of text arrives.
+    fn stream(config: &AIConfig, gate: &Sender<()>) -> (Result<(), String>, Vec<String>) {
+        let mut pieces = Vec::new();
+        let result = block_on(stream_ai_response(&Client::new(), config, "hi", |text| {
+            pieces.push(text);
+            let _ = gate.send(());
+        }));
+        (result, pieces)
+    }
+
+    #[test]
+    fn openai_streams_server_sent_events() {
+        let (address, gate, received) = serve(
+            "200 OK",
+            "text/event-stream",
+            &[
+                ": keep-alive\n\ndata: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+                "data: [DONE]\n\n",
+            ],
+        );
+        let config = config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", address), "sk-test");
+        let (result, pieces) = stream(&config, &gate);
+        assert_eq!(result, Ok(()));
+        assert_eq!(pieces, ["Hel", "lo"]);
+
+        let received = received.recv().unwrap();
+        assert!(received.head.starts_with("post /v1/chat/completions http/1.1"));
+        assert!(received.head.contains("authorization: bearer sk-test\r\n"));
+        assert_eq!(
+            received.body,
+            json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "hi" }],
+                "max_tokens": 7,
+                "stream": true,
+            })
+        );
+        assert!(received.incremental);
+    }
+
+    #[test]
+    fn anthropic_streams_server_sent_events() {
+        let (address, gate, received) = serve(
+            "200 OK",
+            "text/event-stream",
+            &[
+                "event: message_start\ndata: {\"type\":\"message_start\"}\n\nevent: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\" there\"}}\n\n",
+                "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\ndata: {\"type\":\"content_block_delta\",\"delta\":{\"text\":\"late\"}}\n\n",
+            ],
+        );
+        let config = config(ProviderKind::Anthropic, format!("{}/v1/messages", address), "key");
+        let (result, pieces) = stream(&config, &gate);
+        assert_eq!(result, Ok(()));
+        assert_eq!(pieces, ["Hi", " there"]);
+
+        let received = received.recv().unwrap();
+        assert!(received.head.starts_with("post /v1/messages http/1.1"));
+        assert!(received.head.contains("x-api-key: key\r\n"));
+        assert!(received.head.contains("anthropic-version: 2023-06-01\r\n"));
+        assert!(!received.head.contains("authorization:"));
+        assert_eq!(received.body["messages"], json!([{ "role": "user", "content": "hi" }]));
+        assert_eq!(received.body["max_tokens"], 7);
+        assert_eq!(received.body["stream"], true);
+        assert!(received.incremental);
+    }
+
+    #[test]
+    fn ollama_streams_json_lines() {
+        let (address, gate, received) = serve(
+            "200 OK",
+            "application/x-ndjson",
+            &[
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"fn \"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"main\"},\"done\":false}\n",
+                "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}\n",
+            ],
+        );
+        let config = config(ProviderKind::Ollama, format!("{}/api/chat", address), "");
+        let (result, pieces) = stream(&config, &gate);
+        assert_eq!(result, Ok(()));
+        assert_eq!(pieces, ["fn ", "main"]);
+
+        let received = received.recv().unwrap();
+        assert!(received.head.starts_with("post /api/chat http/1.1"));
+        assert!(!received.head.contains("authorization:"));
+        assert_eq!(
+            received.body,
+            json!({
+                "model": "test-model",
+                "messages": [{ "role": "user", "content": "hi" }],
+                "stream": true,
+                "options": { "num_predict": 7 },
+            })
+        );
+        assert!(received.incremental);
+    }
+
+    #[test]
+    fn characters_split_between_chunks_stay_whole() {
+        let event = "data: {\"choices\":[{\"delta\":{\"content\":\"café\"}}]}\n\n".as_bytes();
+        // Cut between the two bytes of the é
+        let split = event.iter().position(|&b| b == 0xc3).unwrap() + 1;
+        let (address, gate, _received) = serve_bytes("200 OK", "text/event-stream", &[&event[..split], &event[split..]]);
+        // The first part has no complete line, so let the second go out straight away
+        gate.send(()).unwrap();
+        let config = config(ProviderKind::OpenAi, format!("{}/v1/chat/completions", address), "key");
+        let (result, pieces) = stream(&config, &gate);
+        assert_eq!(result, Ok(()));
+        assert_eq!(pieces, ["café"]);
+    }
+
+    #[test]
+    fn a_complete_json_body_is_read_whole() {
+        let (address, gate, _received) =
+            serve("200 OK", "application/json", &["{\"choices\":[{\"message\":{\"content\":\"whole reply\"}}]}"]);
+        let config = config(ProviderKind::LlamaCpp, format!("{}/v1/chat/completions", address), "");
+        assert_eq!(stream(&config, &gate), (Ok(()), vec!["whole reply".to_string()]));
+    }
+
+    #[test]
+    fn error_bodies_become_the_message() {
+        let cases = [
+            (ProviderKind::OpenAi, "401 Unauthorized", "{\"error\":{\"message\":\"Invalid API key\"}}", "401 Unauthorized: Invalid API key"),
+            (ProviderKind::Ollama, "404 Not Found", "{\"error\":\"model \\\"x\\\" not found\"}", "404 Not Found: model \"x\" not found"),
+            (ProviderKind::Anthropic, "500 Internal Server Error", "  upstream broke\n", "500 Internal Server Error: upstream broke"),
+        ];
+        for (provider, status, body, expected) in cases {
+            let (address, gate, _received) = serve(status, "application/json", &[body]);
+            let config = config(provider, address, "key");
+            assert_eq!(stream(&config, &gate), (Err(expected.to_string()), Vec::new()));
+        }
+    }
+
+    #[test]
+    fn an_error_event_ends_the_stream() {
+        let (address, gate, _received) = serve(
+            "200 OK",
+            "text/event-stream",
+            &["data: {\"choices\":[{\"delta\":{\"content\":\"par\"}}]}\n\n", "data: {\"error\":{\"message\":\"overloaded\"}}\n\n"],
+        );
+        let config = config(ProviderKind::OpenAi, address, "key");
+        assert_eq!(stream(&config, &gate), (Err("overloaded".to_string()), vec!["par".to_string()]));
+    }
+
+    /// Asks for the code inside `fn main() {}` the way `start_completion` does.
+    fn complete(config: &AIConfig) -> Result<String, String> {
+        let provider = config.provider.provider();
+        block_on(async {
+            let client = Client::new();
+            let body = read_json(provider.completion_request(&client, config, "fn main() {", "}").send())
+                .await
+                .map_err(|e| provider_error(provider, e))?;
+            provider.parse_completion(&body).ok_or_else(|| "no completion".to_string())
+        })
+    }
+
+    #[test]
+    fn ollama_completes_through_generate() {
+        let (address, _gate, received) = serve("200 OK", "application/json", &["{\"response\":\" run(); \",\"done\":true}"]);
+        let config = config(ProviderKind::Ollama, format!("{}/api/chat", address), "");
+        assert_eq!(complete(&config), Ok(" run(); ".to_string()));
+        let received = received.recv().unwrap();
+        assert!(received.head.starts_with("post /api/generate http/1.1"));
+        assert_eq!(
+            received.body,
+            json!({
+                "model": "test-model",
+                "prompt": "fn main() {",
+                "suffix": "}",
+                "stream": false,
+                "options": { "num_predict": 7 },
+            })
+        );
+    }
+
+    #[test]
+    fn llama_cpp_completes_through_infill() {
+        let (address, _gate, received) = serve("200 OK", "application/json", &["{\"content\":\"run();\"}"]);
+        let config = config(ProviderKind::LlamaCpp, format!("{}/v1/chat/completions", address), "");
+        assert_eq!(complete(&config), Ok("run();".to_string()));
+        let received = received.recv().unwrap();
+        assert!(received.head.starts_with("post /infill http/1.1"));
+        assert_eq!(received.body, json!({ "input_prefix": "fn main() {", "input_suffix": "}", "n_predict": 7 }));
+    }
+
+    #[test]
+    fn completion_errors_use_the_provider_message() {
+        let (address, _gate, _received) = serve("400 Bad Request", "application/json", &["{\"error\":\"context too long\"}"]);
+        let config = config(ProviderKind::LlamaCpp, format!("{}/v1/chat/completions", address), "");
+        assert_eq!(complete(&config), Err("400 Bad Request: context too long".to_string()));
+    }
+
+    #[test]
+    fn only_settings_errors_are_config_errors() {
+        for (status, config_error) in [("401 Unauthorized", true), ("404 Not Found", true), ("503 Service Unavailable", false)] {
+            let (address, _gate, _received) = serve(status, "application/json", &["{\"error\":\"no\"}"]);
+            let config = config(ProviderKind::LlamaCpp, format!("{}/v1/chat/completions", address), "");
+            let mut tasks = AiTasks::new(&egui::Context::default());
+            tasks.start_completion(&config, "fn main() {}", 11).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let result = loop {
+                if let Some(result) = tasks.poll_completion() {
+                    break result;
+                }
+                assert!(Instant::now() < deadline, "no reply for {}", status);
+                std::thread::sleep(Duration::from_millis(10));
+            };
+            let message = format!("{}: no", status);
+            let expected = if config_error { CompletionError::Config(message) } else { CompletionError::Transient(message) };
+            assert_eq!(result, Err(expected));
+        }
+    }
+
+    #[test]
+    fn completions_lose_fences_and_keep_indentation() {
+        assert_eq!(clean_completion("```rust\nfoo(1, 2)\n```\n", ""), "foo(1, 2)");
+        assert_eq!(clean_completion("  \n```\nbar()\n```", ""), "bar()");
+        assert_eq!(clean_completion("    x + 1\n\n", ""), "    x + 1");
+        assert_eq!(clean_completion("```", ""), "");
+    }
+
+    #[test]
+    fn completions_dont_repeat_what_follows_the_cursor() {
+        assert_eq!(clean_completion("    x + 1\n}", "\n\n  }\n"), "    x + 1");
+        assert_eq!(clean_completion("let a = 1;", "\nreturn a;\n"), "let a = 1;");
+        assert_eq!(clean_completion("return a;", "return a;"), "return a;");
+        assert_eq!(clean_completion("a.len()", ")"), "a.len()");
+        assert_eq!(clean_completion("a.len()\nreturn a;", " \nreturn a;"), "a.len()");
+    }
+}
@@ -0,0 +1,308 @@
+use eframe::egui;
+use std::ops::Range;
+
+use crate::diff_view::DiffView;
+
+/// Lines of code on either side of the selection sent along for context.
+const CONTEXT_LINES: usize = 40;
+/// Without a selection the whole file is sent, up to this many characters.
+const MAX_DOCUMENT_CHARS: usize = 60_000;
+
+/// An editor command that asks the AI about the selected code.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CodeAction {
+    Explain,
+    Refactor,
+    WriteTests,
+    DocComments,
+    FixDiagnostic,
+}
+
+impl CodeAction {
+    pub const ALL: [CodeAction; 5] = [
+        CodeAction::Explain,
+        CodeAction::Refactor,
+        CodeAction::WriteTests,
+        CodeAction::DocComments,
+        CodeAction::FixDiagnostic,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CodeAction::Explain => "Explain Selection",
+            CodeAction::Refactor => "Refactor Selection",
+            CodeAction::WriteTests => "Write Tests",
+            CodeAction::DocComments => "Add Doc Comments",
+            CodeAction::FixDiagnostic => "Fix Diagnostic",
+        }
+    }
+
+    /// Whether the reply is code to apply rather than prose to read.
+    pub fn edits_code(self) -> bool {
+        self != CodeAction::Explain
+    }
+
+    fn instruction(self) -> &'static str {
+        match self {
+            CodeAction::Explain => "Explain what the selected code does, step by step, and point out anything surprising.",
+            CodeAction::Refactor => {
+                "Refactor the selected code to be clearer and more idiomatic without changing its behavior. \
+                 Reply with the complete replacement for the selection in one code block."
+            }
+            CodeAction::WriteTests => {
+                "Write unit tests for the selected code using the usual test framework for the language. \
+                 Reply with only the tests in one code block, ready to be added after the selection."
+            }
+            CodeAction::DocComments => {
+                "Add documentation comments in the language's usual style to the selected code, changing nothing else. \
+                 Reply with the complete replacement for the selection in one code block."
+            }
+            CodeAction::FixDiagnostic => {
+                "Fix the selected code so the diagnostic below goes away, changing as little as possible. \
+                 Reply with the complete replacement for the selection in one code block."
+            }
+        }
+    }
+}
+
+/// The code an action works on and what surrounds it.
+pub struct CodeContext {
+    /// The whole buffer as it was when the action started.
+    pub content: String,
+    language: String,
+    file_name: Option<String>,
+    /// The characters of `content` the action works on.
+    range: Range<usize>,
+    selection: String,
+    before: String,
+    after: String,
+    pub diagnostic: Option<String>,
+}
+
+impl CodeContext {
+    /// The selected characters of `content` with a few dozen lines either side; the whole
+    /// file when nothing is selected.
+    pub fn new(content: &str, selection: Range<usize>, language: String, file_name: Option<String>) -> Result<Self, String> {
+        let selection = if selection.is_empty() {
+            let chars = content.chars().count();
+            if chars > MAX_DOCUMENT_CHARS {
+                return Err("The file is too large to send whole; select the code to work on".to_string());
+            }
+            0..chars
+        } else {
+            selection
+        };
+        let byte = |index: usize| content.char_indices().nth(index).map_or(content.len(), |(i, _)| i);
+        let (start, end) = (byte(selection.start), byte(selection.end));
+        let before = &content[..start];
+        let after = &content[end..];
+        let before_start = before.rmatch_indices('\n').nth(CONTEXT_LINES).map_or(0, |(i, _)| i + 1);
+        let after_end = after.match_indices('\n').nth(CONTEXT_LINES).map_or(after.len(), |(i, _)| i + 1);
+        Ok(Self {
+            content: content.to_string(),
+            language,
+            file_name,
+            range: selection,
+            selection: content[start..end].to_string(),
+            before: before[before_start..].to_string(),
+            after: after[..after_end].to_string(),
+            diagnostic: None,
+        })
+    }
+
+    pub fn prompt(&self, action: CodeAction) -> String {
+        let lang = self.language.to_lowercase();
+        let fenced = |code: &str| format!("```{}\n{}\n```\n", lang, code.trim_end_matches('\n'));
+        let mut prompt = format!("You are helping edit a {} file", self.language);
+        if let Some(name) = &self.file_name {
+            prompt.push_str(&format!(" named {}", name));
+        }
+        prompt.push_str(".\n\n");
+        prompt.push_str(action.instruction());
+        prompt.push_str("\n\n");
+        if !self.before.is_empty() {
+            prompt.push_str(&format!("Code before the selection:\n{}\n", fenced(&self.before)));
+        }
+        prompt.push_str(&format!("Selected code:\n{}", fenced(&self.selection)));
+        if !self.after.is_empty() {
+            prompt.push_str(&format!("\nCode after the selection:\n{}", fenced(&self.after)));
+        }
+        if let Some(diagnostic) = &self.diagnostic {
+            prompt.push_str(&format!("\nDiagnostic:\n```\n{}\n```\n", diagnostic.trim()));
+        }
+        prompt
+    }
+
+    /// The buffer with the code in `reply` applied the way `action` calls for: in place of
+    /// the selection, or after it for new tests. A reply without code in a fenced block is
+    /// refused rather than pasted in as it is.
+    pub fn apply(&self, action: CodeAction, reply: &str) -> Result<String, String> {
+        let content = self.content.as_str();
+        let mut code = extract_code(reply).ok_or_else(|| "The reply has no code block to apply".to_string())?;
+        // Keep the selection's line structure so the lines around it stay put
+        if self.selection.ends_with('\n') && !code.ends_with('\n') {
+            code.push('\n');
+        }
+        let byte = |index: usize| content.char_indices().nth(index).map_or(content.len(), |(i, _)| i);
+        let (start, end) = (byte(self.range.start), byte(self.range.end));
+        let mut text = String::with_capacity(content.len() + code.len() + 2);
+        if action == CodeAction::WriteTests {
+            text.push_str(&content[..end]);
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push('\n');
+            text.push_str(&code);
+            if !code.ends_with('\n') {
+                text.push('\n');
+            }
+            if end < content.len() {
+                text.push('\n');
+            }
+        } else {
+            text.push_str(&content[..start]);
+            text.push_str(&code);
+        }
+        text.push_str(&content[end..]);
+        Ok(text)
+    }
+}
+
+/// The first fenced code block in `reply`; `None` if there is none or it is empty.
+fn extract_code(reply: &str) -> Option<String> {
+    let mut lines = reply.lines();
+    if !lines.by_ref().any(|line| line.trim_start().starts_with("```")) {
+        return None;
+    }
+    let mut code: String = lines
+        .take_while(|line| !line.trim_start().starts_with("```"))
+        .flat_map(|line| [line, "\n"])
+        .collect();
+    code.pop();
+    Some(code).filter(|code| !code.trim().is_empty())
+}
+
+pub enum ProposalOutcome {
+    Apply,
+    Discard,
+}
+
+/// A suggested edit to the buffer, shown as a diff until it is applied or discarded.
+pub struct Proposal {
+    /// The buffer text the suggestion was made against.
+    pub base: String,
+    pub text: String,
+    diff: DiffView,
+}
+
+impl Proposal {
+    pub fn new(title: String, base: String, text: String) -> Self {
+        let diff = DiffView::new(title, "Current".to_string(), &base, "Suggested".to_string(), &text);
+        Self { base, text, diff }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<ProposalOutcome> {
+        let mut outcome = None;
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                outcome = Some(ProposalOutcome::Apply);
+            }
+            if ui.button("Discard").clicked() {
+                outcome = Some(ProposalOutcome::Discard);
+            }
+        });
+        if self.diff.show(ui) {
+            outcome = Some(ProposalOutcome::Discard);
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(content: &str, selection: Range<usize>) -> CodeContext {
+        CodeContext::new(content, selection, "Rust".to_string(), Some("lib.rs".to_string())).unwrap()
+    }
+
+    #[test]
+    fn extracts_the_first_code_block() {
+        let reply = "Here you go:\n\n```rust\nfn a() {\n    b();\n}\n```\n\nAnd another:\n```\nfn c() {}\n```";
+        assert_eq!(extract_code(reply).unwrap(), "fn a() {\n    b();\n}");
+        // A reply cut off before the closing fence keeps what it has
+        assert_eq!(extract_code("```rust\nfn a() {}\n").unwrap(), "fn a() {}");
+    }
+
+    #[test]
+    fn replies_without_code_are_refused() {
+        assert_eq!(extract_code("  fn a() {}\n\n"), None);
+        assert_eq!(extract_code("Sorry, I can't help with that."), None);
+        assert_eq!(extract_code("```rust\n  \n```"), None);
+        let ctx = context("fn a() {}\nfn b() {}\n", 10..20);
+        assert!(ctx.apply(CodeAction::Refactor, "").is_err());
+        assert!(ctx.apply(CodeAction::DocComments, "fn b() {}").is_err());
+        assert!(ctx.apply(CodeAction::WriteTests, "```\n```").is_err());
+    }
+
+    #[test]
+    fn no_selection_sends_the_whole_file() {
+        let ctx = context("fn a() {}\n", 0..0);
+        assert_eq!(ctx.selection, "fn a() {}\n");
+        assert!(ctx.before.is_empty() && ctx.after.is_empty());
+        let large = "x".repeat(MAX_DOCUMENT_CHARS + 1);
+        assert!(CodeContext::new(&large, 0..0, "Text".to_string(), None).is_err());
+        assert!(CodeContext::new(&large, 0..1, "Text".to_string(), None).is_ok());
+    }
+
+    #[test]
+    fn context_is_limited_to_nearby_lines() {
+        let content: String = (0..100).map(|i| format!("line{}\n", i)).collect();
+        let start = content.find("line50").unwrap();
+        let ctx = context(&content, start..start + "line50".len());
+        assert_eq!(ctx.selection, "line50");
+        assert!(ctx.before.starts_with("line10\n"));
+        assert_eq!(ctx.before.lines().count(), 40);
+        assert!(ctx.after.ends_with("line90\n"));
+        assert!(!ctx.after.contains("line91"));
+    }
+
+    #[test]
+    fn selection_counts_characters() {
+        let ctx = context("// é\nlet x = 1;\n", 5..15);
+        assert_eq!(ctx.selection, "let x = 1;");
+        assert_eq!(ctx.before, "// é\n");
+        assert_eq!(ctx.apply(CodeAction::Refactor, "```\nlet x: u8 = 1;\n```").unwrap(), "// é\nlet x: u8 = 1;\n");
+    }
+
+    #[test]
+    fn prompt_describes_the_file_and_selection() {
+        let mut ctx = context("fn a() {}\nfn b() {}\nfn c() {}\n", 10..20);
+        ctx.diagnostic = Some("  unused function `b`\n".to_string());
+        let prompt = ctx.prompt(CodeAction::FixDiagnostic);
+        assert!(prompt.starts_with("You are helping edit a Rust file named lib.rs.\n\n"));
+        assert!(prompt.contains(CodeAction::FixDiagnostic.instruction()));
+        assert!(prompt.contains("Code before the selection:\n```rust\nfn a() {}\n```\n"));
+        assert!(prompt.contains("Selected code:\n```rust\nfn b() {}\n```\n"));
+        assert!(prompt.contains("Code after the selection:\n```rust\nfn c() {}\n```\n"));
+        assert!(prompt.ends_with("Diagnostic:\n```\nunused function `b`\n```\n"));
+    }
+
+    #[test]
+    fn replacing_keeps_the_selection_line_ending() {
+        let ctx = context("fn a() {}\nfn b() {}\nfn c() {}\n", 10..20);
+        let reply = "```rust\nfn b() -> u8 { 0 }\n```";
+        assert_eq!(ctx.apply(CodeAction::Refactor, reply).unwrap(), "fn a() {}\nfn b() -> u8 { 0 }\nfn c() {}\n");
+    }
+
+    #[test]
+    fn tests_go_after_the_selection() {
+        let ctx = context("fn a() {}\nfn b() {}\n", 0..10);
+        let reply = "```rust\n#[test]\nfn t() {}\n```";
+        assert_eq!(ctx.apply(CodeAction::WriteTests, reply).unwrap(), "fn a() {}\n\n#[test]\nfn t() {}\n\nfn b() {}\n");
+
+        // At the end of a file without a final newline
+        let ctx = context("fn a() {}", 0..0);
+        assert_eq!(ctx.apply(CodeAction::WriteTests, reply).unwrap(), "fn a() {}\n\n#[test]\nfn t() {}\n");
+    }
+}
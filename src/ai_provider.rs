@@ -0,0 +1,328 @@
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Which kind of server requests go to.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ProviderKind {
+    #[default]
+    OpenAi,
+    Anthropic,
+    Ollama,
+    LlamaCpp,
+}
+
+impl ProviderKind {
+    pub const ALL: [ProviderKind; 4] = [ProviderKind::OpenAi, ProviderKind::Anthropic, ProviderKind::Ollama, ProviderKind::LlamaCpp];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "OpenAI-compatible",
+            ProviderKind::Anthropic => "Anthropic",
+            ProviderKind::Ollama => "Ollama (local)",
+            ProviderKind::LlamaCpp => "llama.cpp (local)",
+        }
+    }
+
+    pub fn default_endpoint(self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "https://api.openai.com/v1/chat/completions",
+            ProviderKind::Anthropic => "https://api.anthropic.com/v1/messages",
+            ProviderKind::Ollama => "http://localhost:11434/api/chat",
+            ProviderKind::LlamaCpp => "http://localhost:8080/v1/chat/completions",
+        }
+    }
+
+    pub fn default_model(self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "gpt-4o-mini",
+            ProviderKind::Anthropic => "claude-3-5-haiku-latest",
+            ProviderKind::Ollama => "llama3.2",
+            // The server answers with whatever model it was started with
+            ProviderKind::LlamaCpp => "default",
+        }
+    }
+
+    /// Local servers usually run without authentication.
+    pub fn needs_api_key(self) -> bool {
+        matches!(self, ProviderKind::OpenAi | ProviderKind::Anthropic)
+    }
+
+    pub fn provider(self) -> &'static dyn AIProvider {
+        match self {
+            ProviderKind::OpenAi => &OpenAiChat,
+            ProviderKind::Anthropic => &AnthropicMessages,
+            ProviderKind::Ollama => &OllamaChat,
+            ProviderKind::LlamaCpp => &LlamaCppServer,
+        }
+    }
+}
+
+/// Where and how to send AI requests. An empty endpoint or model means the provider's default.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AIConfig {
+    pub provider: ProviderKind,
+    pub endpoint: String,
+    pub model: String,
+    pub max_tokens: u32,
+    pub api_key: String,
+    pub prompt: String,
+}
+
+impl Default for AIConfig {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::default(),
+            endpoint: String::new(),
+            model: String::new(),
+            max_tokens: 1024,
+            api_key: String::new(),
+            prompt: String::new(),
+        }
+    }
+}
+
+impl AIConfig {
+    pub fn endpoint(&self) -> &str {
+        match self.endpoint.trim() {
+            "" => self.provider.default_endpoint(),
+            endpoint => endpoint,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        match self.model.trim() {
+            "" => self.provider.default_model(),
+            model => model,
+        }
+    }
+}
+
+/// One HTTP API for asking a model to reply to a prompt.
+pub trait AIProvider: Send + Sync {
+    /// The request asking for a reply to `prompt`, sent a piece at a time if `stream` is set.
+    fn request(&self, client: &Client, config: &AIConfig, prompt: &str, stream: bool) -> RequestBuilder;
+
+    /// The reply text in a complete, non-streamed response.
+    fn parse_response(&self, body: &Value) -> Option<String>;
+
+    /// Whether a streamed reply is one JSON object per line rather than server-sent events.
+    fn line_delimited(&self) -> bool {
+        false
+    }
+
+    /// The text a streamed event adds to the reply, if any.
+    fn stream_delta(&self, event: &Value) -> Option<String>;
+
+    /// Whether a streamed event marks the end of the reply.
+    fn stream_done(&self, _event: &Value) -> bool {
+        false
+    }
+
+    /// A request for the code that belongs between `prefix` and `suffix`. Chat APIs are
+    /// asked for it in words; servers with a fill-in-the-middle endpoint use that instead.
+    fn completion_request(&self, client: &Client, config: &AIConfig, prefix: &str, suffix: &str) -> RequestBuilder {
+        self.request(client, config, &fill_in_prompt(prefix, suffix), false)
+    }
+
+    /// The inserted code in the response to `completion_request`.
+    fn parse_completion(&self, body: &Value) -> Option<String> {
+        self.parse_response(body)
+    }
+
+    /// The explanation in an error response.
+    fn error_message(&self, body: &Value) -> Option<String> {
+        match &body["error"] {
+            Value::String(message) => Some(message.clone()),
+            error => error["message"].as_str().map(str::to_string),
+        }
+    }
+}
+
+fn fill_in_prompt(prefix: &str, suffix: &str) -> String {
+    format!(
+        "Fill in the code at <CURSOR>. Reply with only the text to insert there, \
+         with no explanation and no code fences.\n\n{}<CURSOR>{}",
+        prefix, suffix
+    )
+}
+
+/// `endpoint` with its chat path swapped for `path`, e.g. `/api/chat` for `/api/generate`.
+fn sibling_endpoint(endpoint: &str, chat_path: &str, path: &str) -> String {
+    let base = endpoint.strip_suffix(chat_path).unwrap_or(endpoint.trim_end_matches('/'));
+    format!("{}{}", base, path)
+}
+
+/// `/v1/chat/completions` as served by OpenAI and the many servers that copy its API.
+pub struct OpenAiChat;
+
+impl AIProvider for OpenAiChat {
+    fn request(&self, client: &Client, config: &AIConfig, prompt: &str, stream: bool) -> RequestBuilder {
+        let request = client.post(config.endpoint()).json(&json!({
+            "model": config.model(),
+            "messages": [{ "role": "user", "content": prompt }],
+            "max_tokens": config.max_tokens,
+            "stream": stream,
+        }));
+        if config.api_key.is_empty() {
+            request
+        } else {
+            request.bearer_auth(&config.api_key)
+        }
+    }
+
+    fn parse_response(&self, body: &Value) -> Option<String> {
+        body["choices"][0]["message"]["content"].as_str().map(str::to_string)
+    }
+
+    // The end of the stream is a bare `[DONE]` rather than an event
+    fn stream_delta(&self, event: &Value) -> Option<String> {
+        event["choices"][0]["delta"]["content"].as_str().map(str::to_string)
+    }
+}
+
+/// Anthropic's Messages API.
+pub struct AnthropicMessages;
+
+impl AIProvider for AnthropicMessages {
+    fn request(&self, client: &Client, config: &AIConfig, prompt: &str, stream: bool) -> RequestBuilder {
+        client
+            .post(config.endpoint())
+            .header("x-api-key", &config.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&json!({
+                "model": config.model(),
+                "messages": [{ "role": "user", "content": prompt }],
+                "max_tokens": config.max_tokens,
+                "stream": stream,
+            }))
+    }
+
+    fn parse_response(&self, body: &Value) -> Option<String> {
+        let blocks = body["content"].as_array()?;
+        Some(blocks.iter().filter(|b| b["type"] == "text").filter_map(|b| b["text"].as_str()).collect())
+    }
+
+    fn stream_delta(&self, event: &Value) -> Option<String> {
+        if event["type"] != "content_block_delta" {
+            return None;
+        }
+        event["delta"]["text"].as_str().map(str::to_string)
+    }
+
+    fn stream_done(&self, event: &Value) -> bool {
+        event["type"] == "message_stop"
+    }
+}
+
+/// Ollama's native `/api/chat`.
+pub struct OllamaChat;
+
+impl AIProvider for OllamaChat {
+    fn request(&self, client: &Client, config: &AIConfig, prompt: &str, stream: bool) -> RequestBuilder {
+        client.post(config.endpoint()).json(&json!({
+            "model": config.model(),
+            "messages": [{ "role": "user", "content": prompt }],
+            "stream": stream,
+            "options": { "num_predict": config.max_tokens },
+        }))
+    }
+
+    fn parse_response(&self, body: &Value) -> Option<String> {
+        body["message"]["content"].as_str().map(str::to_string)
+    }
+
+    fn line_delimited(&self) -> bool {
+        true
+    }
+
+    fn stream_delta(&self, event: &Value) -> Option<String> {
+        self.parse_response(event)
+    }
+
+    fn stream_done(&self, event: &Value) -> bool {
+        event["done"] == true
+    }
+
+    // `/api/generate` fills in the middle for models trained to
+    fn completion_request(&self, client: &Client, config: &AIConfig, prefix: &str, suffix: &str) -> RequestBuilder {
+        client.post(sibling_endpoint(config.endpoint(), "/api/chat", "/api/generate")).json(&json!({
+            "model": config.model(),
+            "prompt": prefix,
+            "suffix": suffix,
+            "stream": false,
+            "options": { "num_predict": config.max_tokens },
+        }))
+    }
+
+    fn parse_completion(&self, body: &Value) -> Option<String> {
+        body["response"].as_str().map(str::to_string)
+    }
+}
+
+/// llama.cpp's server: the OpenAI chat API, plus its own `/infill` for completions.
+pub struct LlamaCppServer;
+
+impl AIProvider for LlamaCppServer {
+    fn request(&self, client: &Client, config: &AIConfig, prompt: &str, stream: bool) -> RequestBuilder {
+        OpenAiChat.request(client, config, prompt, stream)
+    }
+
+    fn parse_response(&self, body: &Value) -> Option<String> {
+        OpenAiChat.parse_response(body)
+    }
+
+    fn stream_delta(&self, event: &Value) -> Option<String> {
+        OpenAiChat.stream_delta(event)
+    }
+
+    fn completion_request(&self, client: &Client, config: &AIConfig, prefix: &str, suffix: &str) -> RequestBuilder {
+        client.post(sibling_endpoint(config.endpoint(), "/v1/chat/completions", "/infill")).json(&json!({
+            "input_prefix": prefix,
+            "input_suffix": suffix,
+            "n_predict": config.max_tokens,
+        }))
+    }
+
+    fn parse_completion(&self, body: &Value) -> Option<String> {
+        body["content"].as_str().map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blank_settings_fall_back_to_the_provider_defaults() {
+        let mut config = AIConfig { provider: ProviderKind::Ollama, endpoint: "  ".to_string(), ..AIConfig::default() };
+        assert_eq!(config.endpoint(), "http://localhost:11434/api/chat");
+        assert_eq!(config.model(), "llama3.2");
+        config.model = " codellama ".to_string();
+        assert_eq!(config.model(), "codellama");
+    }
+
+    #[test]
+    fn completion_endpoints_sit_next_to_the_chat_endpoint() {
+        assert_eq!(sibling_endpoint("http://host:11434/api/chat", "/api/chat", "/api/generate"), "http://host:11434/api/generate");
+        assert_eq!(sibling_endpoint("http://host:8080/", "/v1/chat/completions", "/infill"), "http://host:8080/infill");
+    }
+
+    #[test]
+    fn anthropic_replies_join_their_text_blocks() {
+        let body = json!({ "content": [
+            { "type": "text", "text": "one " },
+            { "type": "tool_use", "name": "x" },
+            { "type": "text", "text": "two" },
+        ]});
+        assert_eq!(AnthropicMessages.parse_response(&body).as_deref(), Some("one two"));
+    }
+
+    #[test]
+    fn error_messages_come_as_strings_or_objects() {
+        assert_eq!(OllamaChat.error_message(&json!({ "error": "no model" })).as_deref(), Some("no model"));
+        assert_eq!(OpenAiChat.error_message(&json!({ "error": { "message": "bad key" } })).as_deref(), Some("bad key"));
+        assert_eq!(OpenAiChat.error_message(&json!({ "choices": [] })), None);
+    }
+}
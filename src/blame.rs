@@ -0,0 +1,112 @@
+use eframe::egui;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git::{self, BlameCommit};
+
+/// What a blame was computed for: the file, the repository state and the buffer's edit count.
+#[derive(Clone, PartialEq)]
+struct BlameKey {
+    path: PathBuf,
+    revision: u64,
+    edits: u64,
+}
+
+type BlameResult = (BlameKey, Result<Vec<Arc<BlameCommit>>, String>);
+
+/// Per-line blame of the open buffer, computed in the background. Unsaved text is
+/// blamed as it is, so annotations stay lined up with what is on screen.
+pub struct Blame {
+    pub enabled: bool,
+    lines: Vec<Arc<BlameCommit>>,
+    key: Option<BlameKey>,
+    /// The key of the blame in flight, if any.
+    pending: Option<BlameKey>,
+    tx: Sender<BlameResult>,
+    rx: Receiver<BlameResult>,
+    ctx: egui::Context,
+}
+
+impl Blame {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { enabled: false, lines: Vec::new(), key: None, pending: None, tx, rx, ctx: ctx.clone() }
+    }
+
+    /// Picks up a finished blame and starts a new one if the file, repository or text
+    /// changed; `edits` counts the changes to the text. `encoded` is only called when a blame is started. While `idle` is false
+    /// (the user is typing) the previous annotations are kept instead.
+    pub fn update(
+        &mut self,
+        path: Option<&Path>,
+        repo_root: Option<&Path>,
+        revision: u64,
+        edits: u64,
+        idle: bool,
+        encoded: impl FnOnce() -> Result<Vec<u8>, String>,
+    ) -> Option<String> {
+        let mut error = None;
+        for (key, result) in self.rx.try_iter() {
+            if self.pending.as_ref() == Some(&key) {
+                self.pending = None;
+                match result {
+                    Ok(lines) => self.lines = lines,
+                    Err(e) => error = Some(e),
+                }
+                self.key = Some(key);
+            }
+        }
+
+        let (true, Some(path), Some(repo_root)) = (self.enabled, path, repo_root) else {
+            self.lines.clear();
+            self.key = None;
+            return error;
+        };
+        let key = BlameKey { path: path.to_path_buf(), revision, edits };
+        let other_file = self.key.as_ref().is_some_and(|k| k.path != key.path);
+        if other_file {
+            self.lines.clear();
+        }
+        let fresh = self.key.as_ref() == Some(&key) || self.pending.as_ref() == Some(&key);
+        if fresh || self.pending.is_some() || !(idle || self.key.is_none() || other_file) {
+            return error;
+        }
+
+        let contents = match encoded() {
+            Ok(contents) => contents,
+            Err(e) => return Some(e),
+        };
+        self.pending = Some(key.clone());
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let repo_root = repo_root.to_path_buf();
+        std::thread::spawn(move || {
+            let result = git::blame(&repo_root, &key.path, &contents);
+            if tx.send((key, result)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        error
+    }
+
+    pub fn line(&self, line: usize) -> Option<&Arc<BlameCommit>> {
+        self.lines.get(line)
+    }
+}
+
+/// "3 days ago", "2 years ago" and so on.
+pub fn age(time: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let secs = now.saturating_sub(time);
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        86400..=2_591_999 => (secs / 86400, "day"),
+        2_592_000..=31_535_999 => (secs / 2_592_000, "month"),
+        _ => (secs / 31_536_000, "year"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
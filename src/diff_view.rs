@@ -0,0 +1,250 @@
+use eframe::egui;
+use similar::{ChangeTag, TextDiff};
+use std::time::Duration;
+
+/// Give up on an exact diff after this long; the result is still correct, just coarser.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Part of a line, and whether it is one of the words that changed.
+type Segment = (bool, String);
+
+struct Line {
+    number: usize,
+    segments: Vec<Segment>,
+}
+
+/// One row of the side-by-side view. A side is `None` where the other side has lines
+/// this one doesn't.
+struct Row {
+    changed: bool,
+    left: Option<Line>,
+    right: Option<Line>,
+}
+
+/// One row of the inline view.
+struct InlineRow {
+    tag: ChangeTag,
+    line: Line,
+}
+
+/// A read-only comparison of two texts, side by side or inline, with changed words
+/// highlighted within changed lines.
+pub struct DiffView {
+    title: String,
+    left_label: String,
+    right_label: String,
+    rows: Vec<Row>,
+    inline_rows: Vec<InlineRow>,
+    inline: bool,
+    added: usize,
+    removed: usize,
+}
+
+impl DiffView {
+    pub fn new(title: String, left_label: String, left: &str, right_label: String, right: &str) -> Self {
+        let diff = TextDiff::configure().timeout(DIFF_TIMEOUT).diff_lines(left, right);
+        let mut rows = Vec::new();
+        let mut inline_rows = Vec::new();
+        let (mut added, mut removed) = (0, 0);
+
+        for op in diff.ops() {
+            let mut deleted = Vec::new();
+            let mut inserted = Vec::new();
+            for change in diff.iter_inline_changes(op) {
+                let segments: Vec<Segment> = change
+                    .iter_strings_lossy()
+                    .map(|(emphasized, text)| (emphasized, text.trim_end_matches(['\n', '\r']).to_string()))
+                    .collect();
+                let number = change.new_index().or(change.old_index()).unwrap_or(0) + 1;
+                let old_number = change.old_index().map_or(number, |i| i + 1);
+                match change.tag() {
+                    ChangeTag::Equal => rows.push(Row {
+                        changed: false,
+                        left: Some(Line { number: old_number, segments: segments.clone() }),
+                        right: Some(Line { number, segments: segments.clone() }),
+                    }),
+                    ChangeTag::Delete => {
+                        removed += 1;
+                        deleted.push(Line { number: old_number, segments: segments.clone() });
+                    }
+                    ChangeTag::Insert => {
+                        added += 1;
+                        inserted.push(Line { number, segments: segments.clone() });
+                    }
+                }
+                inline_rows.push(InlineRow { tag: change.tag(), line: Line { number, segments } });
+            }
+            // Pair removed lines with the lines that replaced them
+            let pairs = deleted.len().max(inserted.len());
+            let mut deleted = deleted.into_iter();
+            let mut inserted = inserted.into_iter();
+            for _ in 0..pairs {
+                rows.push(Row { changed: true, left: deleted.next(), right: inserted.next() });
+            }
+        }
+
+        Self { title, left_label, right_label, rows, inline_rows, inline: false, added, removed }
+    }
+
+    /// Shows the comparison; returns true when the user closed it.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut closed = false;
+        ui.horizontal(|ui| {
+            ui.strong(&self.title);
+            ui.colored_label(egui::Color32::from_rgb(0x73, 0xc9, 0x91), format!("+{}", self.added));
+            ui.colored_label(ui.visuals().error_fg_color, format!("−{}", self.removed));
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("✖").on_hover_text("Close Comparison").clicked() {
+                    closed = true;
+                }
+                ui.selectable_value(&mut self.inline, true, "Inline");
+                ui.selectable_value(&mut self.inline, false, "Side by Side");
+            });
+        });
+        if !self.inline {
+            ui.columns(2, |columns| {
+                columns[0].weak(&self.left_label);
+                columns[1].weak(&self.right_label);
+            });
+        } else {
+            ui.weak(format!("{} → {}", self.left_label, self.right_label));
+        }
+        ui.separator();
+
+        if self.added == 0 && self.removed == 0 {
+            ui.label("The files are identical");
+            return closed;
+        }
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let total_rows = if self.inline { self.inline_rows.len() } else { self.rows.len() };
+        // Both sides are rows of one scroll area, so they always scroll together
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, total_rows, |ui, range| {
+                ui.spacing_mut().item_spacing.y = 0.0;
+                for index in range {
+                    let width = ui.available_width();
+                    let (rect, _) = ui.allocate_exact_size(egui::vec2(width, row_height), egui::Sense::hover());
+                    if self.inline {
+                        let row = &self.inline_rows[index];
+                        paint_line(ui, rect, Some(&row.line), row.tag);
+                    } else {
+                        let row = &self.rows[index];
+                        let (left, right) = rect.split_left_right_at_fraction(0.5);
+                        let (left_tag, right_tag) = if row.changed {
+                            (ChangeTag::Delete, ChangeTag::Insert)
+                        } else {
+                            (ChangeTag::Equal, ChangeTag::Equal)
+                        };
+                        paint_line(ui, left.shrink2(egui::vec2(2.0, 0.0)), row.left.as_ref(), left_tag);
+                        paint_line(ui, right.shrink2(egui::vec2(2.0, 0.0)), row.right.as_ref(), right_tag);
+                    }
+                }
+            });
+        closed
+    }
+}
+
+fn side_color(tag: ChangeTag, emphasized: bool, visuals: &egui::Visuals) -> egui::Color32 {
+    let alpha = if emphasized { 90 } else { 40 };
+    match tag {
+        ChangeTag::Delete => egui::Color32::from_rgba_unmultiplied(0xe0, 0x50, 0x50, alpha),
+        ChangeTag::Insert => egui::Color32::from_rgba_unmultiplied(0x50, 0xc0, 0x70, alpha),
+        ChangeTag::Equal => visuals.text_color(),
+    }
+}
+
+/// Paints one line clipped to `rect`, tinted for its change and with changed words
+/// highlighted more strongly. A missing line is left as faint filler.
+fn paint_line(ui: &egui::Ui, rect: egui::Rect, line: Option<&Line>, tag: ChangeTag) {
+    let painter = ui.painter().with_clip_rect(rect);
+    let Some(line) = line else {
+        painter.rect_filled(rect, 0.0, ui.visuals().faint_bg_color);
+        return;
+    };
+    if tag != ChangeTag::Equal {
+        painter.rect_filled(rect, 0.0, side_color(tag, false, ui.visuals()));
+    }
+
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let text_color = ui.visuals().text_color();
+    let mut job = egui::text::LayoutJob::default();
+    let marker = match tag {
+        ChangeTag::Delete => "-",
+        ChangeTag::Insert => "+",
+        ChangeTag::Equal => " ",
+    };
+    job.append(
+        &format!("{:>5} {} ", line.number, marker),
+        0.0,
+        egui::TextFormat::simple(font.clone(), ui.visuals().weak_text_color()),
+    );
+    for (emphasized, text) in &line.segments {
+        let mut format = egui::TextFormat::simple(font.clone(), text_color);
+        if *emphasized && tag != ChangeTag::Equal {
+            format.background = side_color(tag, true, ui.visuals());
+        }
+        job.append(text, 0.0, format);
+    }
+    let galley = ui.fonts(|fonts| fonts.layout_job(job));
+    painter.galley(rect.left_top(), galley, text_color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(left: &str, right: &str) -> DiffView {
+        DiffView::new(String::new(), String::new(), left, String::new(), right)
+    }
+
+    fn text(line: &Line) -> String {
+        line.segments.iter().map(|(_, s)| s.as_str()).collect()
+    }
+
+    /// Each side-by-side row as (changed, left line, right line), lines as "number:text".
+    fn rows(view: &DiffView) -> Vec<(bool, Option<String>, Option<String>)> {
+        let side = |line: &Option<Line>| line.as_ref().map(|l| format!("{}:{}", l.number, text(l)));
+        view.rows.iter().map(|row| (row.changed, side(&row.left), side(&row.right))).collect()
+    }
+
+    #[test]
+    fn uneven_replacements_pair_up_and_leave_gaps() {
+        let view = view("a\nold 1\nold 2\nz\n", "a\nnew 1\nnew 2\nnew 3\nz\n");
+        let s = |s: &str| Some(s.to_string());
+        assert_eq!(
+            rows(&view),
+            [
+                (false, s("1:a"), s("1:a")),
+                (true, s("2:old 1"), s("2:new 1")),
+                (true, s("3:old 2"), s("3:new 2")),
+                (true, None, s("4:new 3")),
+                (false, s("4:z"), s("5:z")),
+            ]
+        );
+        assert_eq!((view.added, view.removed), (3, 2));
+    }
+
+    #[test]
+    fn line_numbers_follow_each_side() {
+        let view = view("gone\nkept\n", "kept\nnew\n");
+        let s = |s: &str| Some(s.to_string());
+        assert_eq!(
+            rows(&view),
+            [(true, s("1:gone"), None), (false, s("2:kept"), s("1:kept")), (true, None, s("2:new"))]
+        );
+        // The inline view numbers removed lines by the old text and the rest by the new
+        let inline: Vec<(ChangeTag, usize)> = view.inline_rows.iter().map(|r| (r.tag, r.line.number)).collect();
+        assert_eq!(inline, [(ChangeTag::Delete, 1), (ChangeTag::Equal, 1), (ChangeTag::Insert, 2)]);
+        assert_eq!((view.added, view.removed), (1, 1));
+    }
+
+    #[test]
+    fn identical_texts_have_nothing_changed() {
+        let view = view("same\r\ntext\r\n", "same\r\ntext\r\n");
+        assert_eq!((view.added, view.removed), (0, 0));
+        assert!(view.rows.iter().all(|row| !row.changed));
+        assert_eq!(text(view.rows[0].left.as_ref().unwrap()), "same");
+    }
+}
@@ -0,0 +1,228 @@
+use encoding_rs::Encoding;
+
+/// Encodings offered in the "Reopen with Encoding" and "Save with Encoding" menus.
+pub const COMMON_ENCODINGS: &[&Encoding] = &[
+    encoding_rs::UTF_8,
+    encoding_rs::UTF_16LE,
+    encoding_rs::UTF_16BE,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::WINDOWS_1250,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::GB18030,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Picks whichever line ending the text uses most, defaulting to LF.
+    pub fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self { encoding: encoding_rs::UTF_8, bom: false }
+    }
+}
+
+impl TextEncoding {
+    /// UTF-16 is only recognisable with a BOM, so always write one for it.
+    pub fn new(encoding: &'static Encoding) -> Self {
+        Self { encoding, bom: is_utf16(encoding) }
+    }
+
+    pub fn label(self) -> String {
+        if self.bom && !is_utf16(self.encoding) {
+            format!("{} with BOM", self.encoding.name())
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+}
+
+/// A file's text with line endings normalised to `\n`, plus what's needed to write it back.
+pub struct DecodedText {
+    pub text: String,
+    pub encoding: TextEncoding,
+    pub line_ending: LineEnding,
+    /// Some bytes were not valid in `encoding` and were replaced with U+FFFD.
+    pub had_errors: bool,
+}
+
+/// Decodes `bytes`, honouring a BOM, then trying UTF-8, then guessing a legacy encoding.
+pub fn decode(bytes: &[u8]) -> DecodedText {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        return decode_as(&bytes[bom_len..], TextEncoding { encoding, bom: true });
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return decode_as(bytes, TextEncoding::default());
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    decode_as(bytes, TextEncoding::new(detector.guess(None, true)))
+}
+
+/// Decodes `bytes` as `encoding`, stripping a matching BOM if there is one.
+pub fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> DecodedText {
+    match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_len)) if bom_encoding == encoding => {
+            decode_as(&bytes[bom_len..], TextEncoding { encoding, bom: true })
+        }
+        _ => decode_as(bytes, TextEncoding { encoding, bom: false }),
+    }
+}
+
+fn decode_as(bytes: &[u8], encoding: TextEncoding) -> DecodedText {
+    let (text, had_errors) = encoding.encoding.decode_without_bom_handling(bytes);
+    let line_ending = LineEnding::detect(&text);
+    DecodedText {
+        text: text.replace("\r\n", "\n"),
+        encoding,
+        line_ending,
+        had_errors,
+    }
+}
+
+/// Encodes `text` for writing to disk, failing rather than silently losing characters.
+pub fn encode(text: &str, encoding: TextEncoding, line_ending: LineEnding) -> Result<Vec<u8>, String> {
+    let text = match line_ending {
+        LineEnding::Lf => std::borrow::Cow::Borrowed(text),
+        LineEnding::CrLf => std::borrow::Cow::Owned(text.replace("\r\n", "\n").replace('\n', "\r\n")),
+    };
+
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    if encoding.encoding == encoding_rs::UTF_16LE || encoding.encoding == encoding_rs::UTF_16BE {
+        // encoding_rs only decodes UTF-16, so encode it by hand
+        let little_endian = encoding.encoding == encoding_rs::UTF_16LE;
+        if encoding.bom {
+            bytes.extend_from_slice(if little_endian { &[0xFF, 0xFE] } else { &[0xFE, 0xFF] });
+        }
+        for unit in text.encode_utf16() {
+            bytes.extend_from_slice(&if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() });
+        }
+        return Ok(bytes);
+    }
+
+    if encoding.bom && encoding.encoding == encoding_rs::UTF_8 {
+        bytes.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+    }
+    let (encoded, _, had_errors) = encoding.encoding.encode(&text);
+    if had_errors {
+        return Err(format!("the text contains characters that cannot be saved as {}", encoding.encoding.name()));
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_utf8_and_normalises_crlf() {
+        let decoded = decode("héllo\r\nworld\r\n".as_bytes());
+        assert_eq!(decoded.text, "héllo\nworld\n");
+        assert!(decoded.encoding == TextEncoding::default());
+        assert!(decoded.line_ending == LineEnding::CrLf);
+        assert!(!decoded.had_errors);
+    }
+
+    #[test]
+    fn honours_bom() {
+        let decoded = decode(&[0xEF, 0xBB, 0xBF, b'a']);
+        assert_eq!(decoded.text, "a");
+        assert!(decoded.encoding == TextEncoding { encoding: encoding_rs::UTF_8, bom: true });
+
+        let decoded = decode(&[0xFF, 0xFE, b'h', 0, b'i', 0]);
+        assert_eq!(decoded.text, "hi");
+        assert_eq!(decoded.encoding.encoding, encoding_rs::UTF_16LE);
+    }
+
+    #[test]
+    fn guesses_legacy_encoding() {
+        let (bytes, _, _) = encoding_rs::WINDOWS_1252.encode("Ça coûte très cher, déjà à côté");
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.text, "Ça coûte très cher, déjà à côté");
+        assert!(!decoded.had_errors);
+    }
+
+    #[test]
+    fn decode_with_strips_only_a_matching_bom() {
+        let decoded = decode_with(&[0xEF, 0xBB, 0xBF, b'a'], encoding_rs::UTF_8);
+        assert_eq!(decoded.text, "a");
+        assert!(decoded.encoding.bom);
+        let decoded = decode_with(&[0xE9], encoding_rs::UTF_8);
+        assert!(decoded.had_errors);
+    }
+
+    #[test]
+    fn round_trips_through_encode() {
+        // Plain ASCII, which every common encoding can represent
+        let text = "line one\nline two\n";
+        for &encoding in COMMON_ENCODINGS {
+            let encoding = TextEncoding::new(encoding);
+            for line_ending in [LineEnding::Lf, LineEnding::CrLf] {
+                let bytes = encode(text, encoding, line_ending).unwrap();
+                let decoded = decode_with(&bytes, encoding.encoding);
+                assert_eq!(decoded.text, text, "{}", encoding.label());
+                assert!(decoded.line_ending == line_ending);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_writes_boms() {
+        let utf8 = TextEncoding { encoding: encoding_rs::UTF_8, bom: true };
+        assert_eq!(encode("a", utf8, LineEnding::Lf).unwrap(), [0xEF, 0xBB, 0xBF, b'a']);
+        let utf16 = TextEncoding::new(encoding_rs::UTF_16BE);
+        assert_eq!(encode("a", utf16, LineEnding::Lf).unwrap(), [0xFE, 0xFF, 0, b'a']);
+    }
+
+    #[test]
+    fn encode_refuses_unrepresentable_text() {
+        let latin1 = TextEncoding::new(encoding_rs::WINDOWS_1252);
+        assert!(encode("snowman ☃", latin1, LineEnding::Lf).is_err());
+    }
+
+    #[test]
+    fn crlf_is_not_doubled() {
+        let bytes = encode("a\r\nb\n", TextEncoding::default(), LineEnding::CrLf).unwrap();
+        assert_eq!(bytes, b"a\r\nb\r\n");
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::trash::TrashedItem;
+
+/// A file tree operation that can be undone.
+pub enum FileOp {
+    Trashed(TrashedItem),
+    Moved { from: PathBuf, to: PathBuf },
+    Created(PathBuf),
+}
+
+impl FileOp {
+    pub fn description(&self) -> &'static str {
+        match self {
+            FileOp::Trashed(_) => "Delete",
+            FileOp::Moved { from, to } if from.parent() == to.parent() => "Rename",
+            FileOp::Moved { .. } => "Move",
+            FileOp::Created(_) => "Create",
+        }
+    }
+}
+
+/// Renames or moves `from` to `to`, refusing to overwrite anything already there.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    match fs::rename(from, to) {
+        Err(e) if crosses_devices(&e) => move_by_copy(from, to),
+        result => result,
+    }
+}
+
+/// Whether a rename failed only because `from` and `to` are on different filesystems.
+fn crosses_devices(e: &io::Error) -> bool {
+    // EXDEV on Unix, ERROR_NOT_SAME_DEVICE on Windows
+    let raw = if cfg!(windows) { 17 } else { 18 };
+    e.kind() == io::ErrorKind::CrossesDevices || e.raw_os_error() == Some(raw)
+}
+
+/// Moves `from` to `to` by copying it and then deleting the original. A copy that fails
+/// partway is removed again, leaving the original as it was.
+fn move_by_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if let Err(e) = copy_recursive(from, to) {
+        let _ = remove_recursive(to);
+        return Err(e);
+    }
+    remove_recursive(from)
+}
+
+/// Deletes `path` and everything in it for good. A symlink is removed rather than what it
+/// points to.
+pub fn remove_recursive(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Moves `path` into the folder `dir`, keeping its name, and returns where it ended up.
+pub fn move_into(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    if dir.starts_with(path) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot move a folder into itself"));
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let to = dir.join(name);
+    rename(path, &to)?;
+    Ok(to)
+}
+
+/// Copies `path` next to itself as "name copy.ext" (or "name copy 2.ext", ...).
+pub fn duplicate(path: &Path) -> io::Result<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    // A folder's name is kept whole, dots and all
+    let is_dir = path.is_dir();
+    let stem = if is_dir { path.file_name() } else { path.file_stem() }.unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .filter(|_| !is_dir)
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let copy = (1..)
+        .map(|n| match n {
+            1 => dir.join(format!("{} copy{}", stem, ext)),
+            n => dir.join(format!("{} copy {}{}", stem, n, ext)),
+        })
+        .find(|p| !p.exists())
+        .expect("some copy name is free");
+    copy_recursive(path, &copy)?;
+    Ok(copy)
+}
+
+/// Copies `from` to `to`, folders with everything in them. Symlinks are copied as links.
+pub fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.is_symlink() {
+        copy_symlink(from, to)
+    } else if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    // Windows links are made for files or folders; a dangling one is taken as a file link
+    let target = fs::read_link(from)?;
+    if fs::metadata(from).is_ok_and(|meta| meta.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+/// Shows `path` in the platform's file manager, selecting it where that is supported.
+pub fn reveal(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    };
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("explorer");
+        command.arg(format!("/select,{}", path.display()));
+        command
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = {
+        // xdg-open can't select a file, so open the folder containing it
+        let mut command = Command::new("xdg-open");
+        command.arg(if path.is_dir() { path } else { path.parent().unwrap_or(path) });
+        command
+    };
+    command.spawn().map(|_| ())
+}
+
+/// Checks a name typed for a new file or folder and returns the path it would create.
+/// Slash-separated names like `a/b/c.rs` create the intermediate folders too.
+pub fn validate_new_name(parent: &Path, name: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Enter a name".to_string());
+    }
+    if name.starts_with(['/', '\\']) {
+        return Err("Names must be relative to the folder".to_string());
+    }
+    let mut path = parent.to_path_buf();
+    for part in name.split(['/', '\\']).filter(|p| !p.is_empty()) {
+        if part == "." || part == ".." {
+            return Err(format!("'{}' is not allowed in a name", part));
+        }
+        if let Some(c) = part.chars().find(|&c| is_invalid_name_char(c)) {
+            return Err(format!("'{}' is not allowed in a name", c.escape_default()));
+        }
+        path.push(part);
+    }
+    if fs::symlink_metadata(&path).is_ok() {
+        return Err(format!("{} already exists", name));
+    }
+    Ok(path)
+}
+
+/// Checks a new name for `path` and returns where the renamed item would be. Unlike a new
+/// item's name it can't contain folders, so a rename never moves anything.
+pub fn validate_rename(path: &Path, name: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if path.file_name().is_some_and(|current| current == name) {
+        return Ok(path.to_path_buf());
+    }
+    if let Some(c) = name.chars().find(|&c| c == '/' || c == '\\') {
+        return Err(format!("'{}' is not allowed in a name", c));
+    }
+    validate_new_name(path.parent().unwrap_or(Path::new(".")), name)
+}
+
+fn is_invalid_name_char(c: char) -> bool {
+    // Windows forbids more, and those names would break the project for anyone on Windows
+    c == '\0' || c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_names_may_create_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(validate_new_name(dir.path(), " a/b\\c.rs ").unwrap(), dir.path().join("a").join("b").join("c.rs"));
+        assert_eq!(validate_new_name(dir.path(), "a//b").unwrap(), dir.path().join("a").join("b"));
+    }
+
+    #[test]
+    fn new_names_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("taken.txt"), "").unwrap();
+        assert_eq!(validate_new_name(dir.path(), "  ").unwrap_err(), "Enter a name");
+        assert_eq!(validate_new_name(dir.path(), "/etc/passwd").unwrap_err(), "Names must be relative to the folder");
+        assert_eq!(validate_new_name(dir.path(), "a/../b").unwrap_err(), "'..' is not allowed in a name");
+        assert_eq!(validate_new_name(dir.path(), "./b").unwrap_err(), "'.' is not allowed in a name");
+        assert_eq!(validate_new_name(dir.path(), "what?").unwrap_err(), "'?' is not allowed in a name");
+        assert_eq!(validate_new_name(dir.path(), "tab\there").unwrap_err(), "'\\t' is not allowed in a name");
+        assert_eq!(validate_new_name(dir.path(), "taken.txt").unwrap_err(), "taken.txt already exists");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_keep_links_as_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        fs::create_dir(&from).unwrap();
+        std::os::unix::fs::symlink("../elsewhere", from.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing", from.join("dangling")).unwrap();
+
+        move_by_copy(&from, &dir.path().join("to")).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_link(dir.path().join("to/link")).unwrap(), Path::new("../elsewhere"));
+        assert_eq!(fs::read_link(dir.path().join("to/dangling")).unwrap(), Path::new("missing"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_copies_are_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("a.txt"), "a").unwrap();
+        // A socket can't be copied, so the move fails partway
+        let _socket = std::os::unix::net::UnixListener::bind(from.join("z.sock")).unwrap();
+
+        let to = dir.path().join("to");
+        assert!(move_by_copy(&from, &to).is_err());
+        assert!(fs::symlink_metadata(&to).is_err());
+        assert_eq!(fs::read_to_string(from.join("a.txt")).unwrap(), "a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removing_a_link_leaves_its_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("a.txt"), "").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        remove_recursive(&link).unwrap();
+        assert!(target.join("a.txt").exists());
+        remove_recursive(&target).unwrap();
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn only_cross_device_renames_fall_back_to_copying() {
+        assert!(crosses_devices(&io::Error::from(io::ErrorKind::CrossesDevices)));
+        assert!(!crosses_devices(&io::Error::from(io::ErrorKind::PermissionDenied)));
+    }
+
+    #[test]
+    fn renames_stay_in_the_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old.rs");
+        fs::write(&path, "").unwrap();
+        assert_eq!(validate_rename(&path, "new.rs").unwrap(), dir.path().join("new.rs"));
+        assert_eq!(validate_rename(&path, "old.rs").unwrap(), path);
+        assert_eq!(validate_rename(&path, "sub/new.rs").unwrap_err(), "'/' is not allowed in a name");
+        assert_eq!(validate_rename(&path, "..\\new.rs").unwrap_err(), "'\\' is not allowed in a name");
+        assert_eq!(validate_rename(&path, "..").unwrap_err(), "'..' is not allowed in a name");
+        assert_eq!(validate_rename(&path, "").unwrap_err(), "Enter a name");
+        assert_eq!(validate_rename(&path, "bell\u{7}").unwrap_err(), "'\\u{7}' is not allowed in a name");
+    }
+
+    #[test]
+    fn rename_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        assert_eq!(rename(&a, &b).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+    }
+
+    #[test]
+    fn move_into_rejects_own_subfolder() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("folder");
+        fs::create_dir_all(folder.join("inner")).unwrap();
+        assert!(move_into(&folder, &folder.join("inner")).is_err());
+        let file = dir.path().join("f.txt");
+        fs::write(&file, "x").unwrap();
+        assert_eq!(move_into(&file, &folder).unwrap(), folder.join("f.txt"));
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn duplicates_get_free_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "hi").unwrap();
+        assert_eq!(duplicate(&file).unwrap(), dir.path().join("notes copy.txt"));
+        assert_eq!(duplicate(&file).unwrap(), dir.path().join("notes copy 2.txt"));
+        assert_eq!(fs::read_to_string(dir.path().join("notes copy 2.txt")).unwrap(), "hi");
+
+        let folder = dir.path().join("src.d");
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("main.rs"), "fn main() {}").unwrap();
+        let copy = duplicate(&folder).unwrap();
+        assert_eq!(copy, dir.path().join("src.d copy"));
+        assert!(copy.join("main.rs").is_file());
+    }
+}
@@ -0,0 +1,584 @@
+use eframe::egui;
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub enum EntryKind {
+    Dir,
+    File,
+    /// A symlink whose target is missing, or an entry that couldn't be read at all.
+    Broken(String),
+}
+
+#[derive(Clone)]
+pub struct TreeEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+impl TreeEntry {
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, EntryKind::Dir)
+    }
+}
+
+/// Stop a filter search after this many matches so a vague query can't run away.
+const MAX_SEARCH_MATCHES: usize = 10_000;
+/// Wait for typing in the filter box to pause before walking the folders.
+const SEARCH_DELAY: Duration = Duration::from_millis(150);
+
+/// Which entries the explorer leaves out.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeFilter {
+    pub show_hidden: bool,
+    /// Honour `.gitignore`, `.ignore` and git's exclude files.
+    pub respect_ignore_files: bool,
+    /// Gitignore-style globs, relative to the opened folder.
+    pub exclude_globs: Vec<String>,
+}
+
+impl Default for TreeFilter {
+    fn default() -> Self {
+        Self {
+            show_hidden: false,
+            respect_ignore_files: true,
+            exclude_globs: vec![".git".to_string(), ".DS_Store".to_string()],
+        }
+    }
+}
+
+impl TreeFilter {
+    fn walker(&self, root: &Path, dir: &Path) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(dir);
+        builder
+            .hidden(!self.show_hidden)
+            .parents(self.respect_ignore_files)
+            .ignore(self.respect_ignore_files)
+            .git_ignore(self.respect_ignore_files)
+            .git_global(self.respect_ignore_files)
+            .git_exclude(self.respect_ignore_files)
+            .require_git(false)
+            .overrides(self.overrides(root));
+        builder
+    }
+
+    fn overrides(&self, root: &Path) -> Override {
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.exclude_globs {
+            // A leading "!" turns an override into an exclusion
+            let _ = builder.add(&format!("!{}", glob.trim()));
+        }
+        builder.build().unwrap_or_else(|_| Override::empty())
+    }
+}
+
+/// Paths matching the filter box, plus every folder on the way to them.
+struct Search {
+    query: String,
+    generation: u64,
+    /// When the walk starts; `None` once it has.
+    due: Option<Instant>,
+    /// Set once the search is replaced, so its walk stops early.
+    cancelled: Arc<AtomicBool>,
+    /// `None` while the search is still running.
+    result: Option<SearchResult>,
+}
+
+impl Search {
+    fn new(query: String, generation: u64, due: Instant) -> Self {
+        Self { query, generation, due: Some(due), cancelled: Arc::new(AtomicBool::new(false)), result: None }
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+struct SearchResult {
+    matches: HashSet<PathBuf>,
+    ancestors: HashSet<PathBuf>,
+}
+
+/// One line of the flattened, currently visible tree.
+pub enum TreeRow {
+    /// A workspace folder, shown as a top-level node when there is more than one.
+    Root(TreeEntry),
+    Entry { depth: usize, entry: TreeEntry },
+    Loading { depth: usize },
+    Error { depth: usize, message: String },
+}
+
+type Listing = Result<Arc<Vec<TreeEntry>>, String>;
+
+#[derive(Default)]
+struct DirState {
+    listing: Option<Listing>,
+    /// Generation of the background read in flight, if any.
+    pending: Option<u64>,
+    /// The listing is out of date and should be re-read the next time it is shown.
+    stale: bool,
+}
+
+/// Cached directory listings for the file explorer. Directories are read on background
+/// threads the first time they are shown, and again after being invalidated, so a huge
+/// folder never blocks the UI.
+pub struct FileTree {
+    dirs: HashMap<PathBuf, DirState>,
+    generation: u64,
+    tx: Sender<(PathBuf, u64, Listing)>,
+    rx: Receiver<(PathBuf, u64, Listing)>,
+    ctx: egui::Context,
+    roots: Vec<TreeEntry>,
+    filter: TreeFilter,
+    search: Option<Search>,
+    search_tx: Sender<(u64, SearchResult)>,
+    search_rx: Receiver<(u64, SearchResult)>,
+}
+
+impl FileTree {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let (search_tx, search_rx) = mpsc::channel();
+        Self {
+            dirs: HashMap::new(),
+            generation: 0,
+            tx,
+            rx,
+            ctx: ctx.clone(),
+            roots: Vec::new(),
+            filter: TreeFilter::default(),
+            search: None,
+            search_tx,
+            search_rx,
+        }
+    }
+
+    pub fn filter(&self) -> &TreeFilter {
+        &self.filter
+    }
+
+    /// Changes what is hidden, re-reading every listing if anything changed.
+    pub fn set_filter(&mut self, filter: TreeFilter) {
+        if filter != self.filter {
+            self.filter = filter;
+            self.invalidate_all();
+        }
+    }
+
+    /// Narrows the tree to paths containing `query`, searched in the background once typing
+    /// pauses.
+    pub fn set_query(&mut self, query: &str) {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() || self.roots.is_empty() {
+            self.search = None;
+            return;
+        }
+        if self.search.as_ref().is_some_and(|s| s.query == query) {
+            return;
+        }
+        self.generation += 1;
+        self.search = Some(Search::new(query, self.generation, Instant::now() + SEARCH_DELAY));
+        self.ctx.request_repaint_after(SEARCH_DELAY);
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.as_ref().is_some_and(|s| s.result.is_none())
+    }
+
+    fn spawn_search(&mut self) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        search.due = None;
+        let tx = self.search_tx.clone();
+        let ctx = self.ctx.clone();
+        let walkers: Vec<(PathBuf, WalkBuilder)> = self
+            .roots
+            .iter()
+            .map(|root| (root.path.clone(), self.filter.walker(&root.path, &root.path)))
+            .collect();
+        let query = search.query.clone();
+        let generation = search.generation;
+        let cancelled = search.cancelled.clone();
+        std::thread::spawn(move || {
+            let mut result = SearchResult { matches: HashSet::new(), ancestors: HashSet::new() };
+            'roots: for (root, walker) in walkers {
+                for entry in walker.build().filter_map(|e| e.ok()).filter(|e| e.depth() > 0) {
+                    if cancelled.load(AtomicOrdering::Relaxed) {
+                        return;
+                    }
+                    // Match names, or whole relative paths once the query has a separator in it
+                    let haystack = if query.contains('/') {
+                        entry.path().strip_prefix(&root).unwrap_or(entry.path()).to_string_lossy()
+                    } else {
+                        entry.file_name().to_string_lossy()
+                    };
+                    if !haystack.to_lowercase().contains(&query) {
+                        continue;
+                    }
+                    // The root itself goes in too, so its node opens in a multi-root workspace
+                    for ancestor in entry.path().ancestors().skip(1) {
+                        if !result.ancestors.insert(ancestor.to_path_buf()) || ancestor == root {
+                            break;
+                        }
+                    }
+                    result.matches.insert(entry.into_path());
+                    if result.matches.len() >= MAX_SEARCH_MATCHES {
+                        break 'roots;
+                    }
+                }
+            }
+            if tx.send((generation, result)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Picks up listings and searches finished by the background threads, and starts a
+    /// search once its delay is up.
+    pub fn poll(&mut self) {
+        if let Some(due) = self.search.as_ref().and_then(|s| s.due) {
+            match due.checked_duration_since(Instant::now()) {
+                Some(wait) if !wait.is_zero() => self.ctx.request_repaint_after(wait),
+                _ => self.spawn_search(),
+            }
+        }
+        for (generation, result) in self.search_rx.try_iter() {
+            if let Some(search) = self.search.as_mut().filter(|s| s.generation == generation) {
+                search.result = Some(result);
+            }
+        }
+        for (dir, generation, listing) in self.rx.try_iter() {
+            if let Some(state) = self.dirs.get_mut(&dir) {
+                if state.pending == Some(generation) {
+                    state.listing = Some(listing);
+                    state.pending = None;
+                }
+            }
+        }
+    }
+
+    /// The cached listing of `dir`, starting a read if it is missing or stale.
+    /// `None` means it is still loading.
+    fn children(&mut self, dir: &Path) -> Option<Listing> {
+        let root = self.root_of(dir).unwrap_or(dir).to_path_buf();
+        let state = self.dirs.entry(dir.to_path_buf()).or_default();
+        if (state.listing.is_none() || state.stale) && state.pending.is_none() {
+            self.generation += 1;
+            state.pending = Some(self.generation);
+            state.stale = false;
+
+            let tx = self.tx.clone();
+            let ctx = self.ctx.clone();
+            let walker = self.filter.walker(&root, dir);
+            let dir = dir.to_path_buf();
+            let generation = self.generation;
+            std::thread::spawn(move || {
+                let listing = read_listing(&dir, walker).map(Arc::new);
+                if tx.send((dir, generation, listing)).is_ok() {
+                    ctx.request_repaint();
+                }
+            });
+        }
+        state.listing.clone()
+    }
+
+    /// Marks the listing of `path`, and of the directory containing it, as out of date.
+    pub fn invalidate(&mut self, path: &Path) {
+        for dir in [Some(path), path.parent()].into_iter().flatten() {
+            if let Some(state) = self.dirs.get_mut(dir) {
+                state.stale = true;
+            }
+        }
+        self.ctx.request_repaint();
+    }
+
+    pub fn invalidate_all(&mut self) {
+        for state in self.dirs.values_mut() {
+            state.stale = true;
+        }
+        // Rerun the filter search so it sees new files and the current filter settings
+        if let Some(query) = self.search.take().map(|s| s.query.clone()) {
+            self.generation += 1;
+            self.search = Some(Search::new(query, self.generation, Instant::now()));
+            self.spawn_search();
+        }
+    }
+
+    /// Replaces the folders shown, given as `(path, display name)` pairs. Listings of
+    /// folders that are still shown are kept.
+    pub fn set_roots(&mut self, roots: &[(PathBuf, String)]) {
+        let unchanged = self.roots.len() == roots.len()
+            && self.roots.iter().zip(roots).all(|(a, (path, name))| a.path == *path && a.name == *name);
+        if unchanged {
+            return;
+        }
+        self.roots = roots
+            .iter()
+            .map(|(path, name)| TreeEntry { path: path.clone(), name: name.clone(), kind: EntryKind::Dir })
+            .collect();
+        let roots = &self.roots;
+        self.dirs.retain(|dir, _| roots.iter().any(|root| dir.starts_with(&root.path)));
+        self.search = None;
+    }
+
+    /// The innermost shown folder containing `path`.
+    fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.roots
+            .iter()
+            .map(|root| root.path.as_path())
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+
+    /// Flattens the tree into rows, descending into folders `is_expanded` accepts. A single
+    /// folder is shown directly; several each get a [`TreeRow::Root`] node.
+    /// While a filter query is active, only matches and their ancestors are shown, with the
+    /// ancestors expanded; a matching folder shows all of its contents.
+    pub fn visible_rows(&mut self, is_expanded: &dyn Fn(&Path) -> bool) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+        let searching = self.search.is_some();
+        if searching && self.is_searching() {
+            rows.push(TreeRow::Loading { depth: 0 });
+            return rows;
+        }
+        let roots = self.roots.clone();
+        if let [root] = roots.as_slice() {
+            self.push_rows(&root.path, 0, is_expanded, !searching, &mut rows);
+            return rows;
+        }
+        for root in roots {
+            let on_path = match self.search.as_ref().and_then(|s| s.result.as_ref()) {
+                Some(result) => result.ancestors.contains(&root.path),
+                None => false,
+            };
+            if searching && !on_path {
+                continue;
+            }
+            let expand = on_path || is_expanded(&root.path);
+            let path = root.path.clone();
+            rows.push(TreeRow::Root(root));
+            if expand {
+                self.push_rows(&path, 1, is_expanded, !searching, &mut rows);
+            }
+        }
+        rows
+    }
+
+    fn push_rows(
+        &mut self,
+        dir: &Path,
+        depth: usize,
+        is_expanded: &dyn Fn(&Path) -> bool,
+        show_all: bool,
+        rows: &mut Vec<TreeRow>,
+    ) {
+        match self.children(dir) {
+            None => rows.push(TreeRow::Loading { depth }),
+            Some(Err(message)) => rows.push(TreeRow::Error { depth, message }),
+            Some(Ok(entries)) => {
+                for entry in entries.iter() {
+                    let (visible, is_match, on_path) = match self.search.as_ref().and_then(|s| s.result.as_ref()) {
+                        Some(result) if !show_all => {
+                            let is_match = result.matches.contains(&entry.path);
+                            let on_path = result.ancestors.contains(&entry.path);
+                            (is_match || on_path, is_match, on_path)
+                        }
+                        _ => (true, false, false),
+                    };
+                    if !visible {
+                        continue;
+                    }
+                    let expand = entry.is_dir() && (on_path || is_expanded(&entry.path));
+                    rows.push(TreeRow::Entry { depth, entry: entry.clone() });
+                    if expand {
+                        self.push_rows(&entry.path, depth + 1, is_expanded, show_all || is_match, rows);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read_listing(dir: &Path, mut walker: WalkBuilder) -> Result<Vec<TreeEntry>, String> {
+    // The walker swallows an unreadable root into its error stream; surface it directly
+    fs::read_dir(dir).map_err(|e| e.to_string())?;
+
+    let mut entries = Vec::new();
+    for entry in walker.max_depth(Some(1)).build() {
+        let entry = match entry {
+            Ok(entry) if entry.depth() == 0 => continue,
+            Ok(entry) => entry,
+            Err(e) => {
+                entries.push(TreeEntry {
+                    path: dir.to_path_buf(),
+                    name: "(unreadable entry)".to_string(),
+                    kind: EntryKind::Broken(e.to_string()),
+                });
+                continue;
+            }
+        };
+        let path = entry.path().to_path_buf();
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Follows symlinks, so a dangling one fails here
+        let kind = match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => EntryKind::Dir,
+            Ok(_) => EntryKind::File,
+            Err(e) if entry.path_is_symlink() => EntryKind::Broken(format!("broken link: {}", e)),
+            Err(e) => EntryKind::Broken(e.to_string()),
+        };
+        entries.push(TreeEntry { path, name, kind });
+    }
+    entries.sort_by(compare_entries);
+    Ok(entries)
+}
+
+/// Folders first, then names in natural order ("file2" before "file10"), ignoring case.
+fn compare_entries(a: &TreeEntry, b: &TreeEntry) -> Ordering {
+    b.is_dir().cmp(&a.is_dir()).then_with(|| natural_cmp(&a.name, &b.name))
+}
+
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_num = take_number(&mut a_chars);
+                let y_num = take_number(&mut b_chars);
+                // Compare by magnitude: strip leading zeros, then longer means bigger
+                let x_trim = x_num.trim_start_matches('0');
+                let y_trim = y_num.trim_start_matches('0');
+                let ord = x_trim.len().cmp(&y_trim.len()).then_with(|| x_trim.cmp(y_trim));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        number.push(c);
+        chars.next();
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn searches_wait_for_typing_to_pause_and_replace_the_last() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("alpha.rs"), "").unwrap();
+        fs::write(dir.path().join("beta.rs"), "").unwrap();
+        let mut tree = FileTree::new(&egui::Context::default());
+        tree.set_roots(&[(dir.path().to_path_buf(), "root".to_string())]);
+
+        tree.set_query("alp");
+        let first = tree.search.as_ref().unwrap().cancelled.clone();
+        tree.poll();
+        assert!(tree.search.as_ref().unwrap().due.is_some());
+        tree.set_query("Beta");
+        assert!(first.load(AtomicOrdering::Relaxed));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while tree.is_searching() && Instant::now() < deadline {
+            tree.poll();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let result = tree.search.as_ref().and_then(|s| s.result.as_ref()).unwrap();
+        assert_eq!(result.matches, HashSet::from([dir.path().join("beta.rs")]));
+    }
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(sorted(&["file10", "file2", "file1"]), ["file1", "file2", "file10"]);
+        assert_eq!(sorted(&["v1.10", "v1.9", "v1.2"]), ["v1.2", "v1.9", "v1.10"]);
+    }
+
+    #[test]
+    fn leading_zeros_and_huge_numbers() {
+        assert_eq!(natural_cmp("a007", "a7"), Ordering::Less);
+        assert_eq!(natural_cmp("a008", "a7"), Ordering::Greater);
+        assert_eq!(natural_cmp("99999999999999999999999", "100000000000000000000000"), Ordering::Less);
+    }
+
+    #[test]
+    fn case_is_ignored_but_breaks_ties() {
+        assert_eq!(sorted(&["beta", "Alpha", "alpha"]), ["Alpha", "alpha", "beta"]);
+        assert_eq!(natural_cmp("README", "readme"), Ordering::Less);
+    }
+
+    #[test]
+    fn prefixes_come_first() {
+        assert_eq!(natural_cmp("main", "main.rs"), Ordering::Less);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+        assert_eq!(natural_cmp("x", "x"), Ordering::Equal);
+    }
+
+    #[test]
+    fn listing_puts_folders_first() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b10.txt"), "").unwrap();
+        fs::write(dir.path().join("b9.txt"), "").unwrap();
+        fs::create_dir(dir.path().join("zeta")).unwrap();
+        let entries = read_listing(dir.path(), TreeFilter::default().walker(dir.path(), dir.path())).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["zeta", "b9.txt", "b10.txt"]);
+        assert!(entries[0].is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_links_are_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(dir.path().join("missing"), dir.path().join("dangling")).unwrap();
+        let entries = read_listing(dir.path(), TreeFilter::default().walker(dir.path(), dir.path())).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(matches!(&entries[0].kind, EntryKind::Broken(message) if message.starts_with("broken link")));
+    }
+
+    #[test]
+    fn unreadable_folder_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("gone");
+        assert!(read_listing(&missing, TreeFilter::default().walker(dir.path(), &missing)).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use eframe::egui;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Files bigger than this are opened read-only and never loaded into memory as a whole.
+pub const LARGE_FILE_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// How many leading bytes are inspected when deciding whether a file is binary.
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// Lines longer than this are cut off in the large-file view.
+const MAX_DISPLAYED_LINE: u64 = 4 * 1024;
+
+const HEX_ROW_LEN: u64 = 16;
+
+/// What the central panel shows for the current file.
+pub enum FileView {
+    Text,
+    Hex(HexView),
+    Image(ImageView),
+    Large(LargeFileView),
+}
+
+impl FileView {
+    pub fn is_text(&self) -> bool {
+        matches!(self, FileView::Text)
+    }
+
+    pub fn label(&self) -> Option<&'static str> {
+        match self {
+            FileView::Text => None,
+            FileView::Hex(_) => Some("Binary (read-only)"),
+            FileView::Image(_) => Some("Image"),
+            FileView::Large(_) => Some("Large file (read-only)"),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        match self {
+            FileView::Text => {}
+            FileView::Hex(view) => view.show(ui),
+            FileView::Image(view) => view.show(ui),
+            FileView::Large(view) => view.show(ui),
+        }
+    }
+}
+
+/// Text files contain no NUL bytes, except UTF-16 which is only recognised with a BOM.
+pub fn looks_binary(path: &Path) -> io::Result<bool> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    if encoding_rs::Encoding::for_bom(&head).is_some() {
+        return Ok(false);
+    }
+    Ok(head.contains(&0))
+}
+
+/// Opens `path` as an image if its extension is one the `image` crate can decode.
+pub fn open_image(ctx: &egui::Context, path: &Path) -> Option<Result<ImageView, String>> {
+    image::ImageFormat::from_path(path).ok()?;
+    Some(ImageView::open(ctx, path))
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if file.seek(SeekFrom::Start(offset)).is_ok() {
+        let _ = file.take(len).read_to_end(&mut buf);
+    }
+    buf
+}
+
+pub struct HexView {
+    file: File,
+    len: u64,
+}
+
+impl HexView {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { file, len })
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) {
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let rows = self.len.div_ceil(HEX_ROW_LEN) as usize;
+        egui::ScrollArea::both().auto_shrink([false, false]).show_rows(ui, row_height, rows, |ui, range| {
+            let start = range.start as u64 * HEX_ROW_LEN;
+            let bytes = read_at(&mut self.file, start, range.len() as u64 * HEX_ROW_LEN);
+            for (i, chunk) in bytes.chunks(HEX_ROW_LEN as usize).enumerate() {
+                ui.monospace(hex_row(start + i as u64 * HEX_ROW_LEN, chunk));
+            }
+        });
+    }
+}
+
+fn hex_row(offset: u64, bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(HEX_ROW_LEN as usize * 3 + 1);
+    for i in 0..HEX_ROW_LEN as usize {
+        if i == 8 {
+            hex.push(' ');
+        }
+        match bytes.get(i) {
+            Some(b) => hex.push_str(&format!("{:02x} ", b)),
+            None => hex.push_str("   "),
+        }
+    }
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    format!("{:08x}  {} |{}|", offset, hex, ascii)
+}
+
+pub struct ImageView {
+    texture: egui::TextureHandle,
+    size: [usize; 2],
+}
+
+impl ImageView {
+    fn open(ctx: &egui::Context, path: &Path) -> Result<Self, String> {
+        let image = image::open(path).map_err(|e| e.to_string())?;
+        let size = [image.width() as usize, image.height() as usize];
+        let rgba = image.to_rgba8();
+        let image_data = egui::ColorImage::from_rgba_unmultiplied(size, rgba.as_flat_samples().as_slice());
+        let texture = ctx.load_texture(path.to_string_lossy(), image_data, Default::default());
+        Ok(Self { texture, size })
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) {
+        ui.label(format!("{} × {} pixels", self.size[0], self.size[1]));
+        egui::ScrollArea::both().auto_shrink([false, false]).show(ui, |ui| {
+            ui.image(&self.texture);
+        });
+    }
+}
+
+/// A read-only view of a file too big to edit. Line starts are indexed on a background
+/// thread so the file can be scrolled while it is still being scanned.
+pub struct LargeFileView {
+    file: File,
+    line_starts: Arc<Mutex<Vec<u64>>>,
+    indexed: Arc<AtomicBool>,
+    len: u64,
+}
+
+impl LargeFileView {
+    pub fn open(ctx: &egui::Context, path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let line_starts = Arc::new(Mutex::new(vec![0]));
+        let indexed = Arc::new(AtomicBool::new(false));
+
+        let thread_path: PathBuf = path.to_path_buf();
+        let thread_starts = Arc::clone(&line_starts);
+        let thread_indexed = Arc::clone(&indexed);
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            let _ = index_lines(&thread_path, &thread_starts);
+            thread_indexed.store(true, Ordering::Relaxed);
+            ctx.request_repaint();
+        });
+
+        Ok(Self { file, line_starts, indexed, len })
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) {
+        let indexed = self.indexed.load(Ordering::Relaxed);
+        // Hold the lock only to copy numbers out; the indexing thread needs it too
+        let lines = {
+            let line_starts = self.line_starts.lock().unwrap();
+            // A trailing newline doesn't start another line
+            line_starts.len() - usize::from(line_starts.len() > 1 && line_starts.last() == Some(&self.len))
+        };
+        if !indexed {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Indexing… {} lines so far", lines));
+            });
+            ui.ctx().request_repaint();
+        }
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::both().auto_shrink([false, false]).show_rows(ui, row_height, lines, |ui, range| {
+            let starts: Vec<u64> = {
+                let line_starts = self.line_starts.lock().unwrap();
+                line_starts.get(range.start..(range.end + 1).min(line_starts.len())).unwrap_or_default().to_vec()
+            };
+            for (i, &start) in starts.iter().enumerate().take(range.len()) {
+                let end = starts.get(i + 1).copied().unwrap_or(self.len);
+                let bytes = read_at(&mut self.file, start, (end - start).min(MAX_DISPLAYED_LINE));
+                let line = String::from_utf8_lossy(&bytes);
+                ui.monospace(line.trim_end_matches(['\n', '\r']));
+            }
+        });
+    }
+}
+
+fn index_lines(path: &Path, line_starts: &Mutex<Vec<u64>>) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 1024 * 1024];
+    let mut offset = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        let found: Vec<u64> = buf[..n]
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'\n')
+            .map(|(i, _)| offset + i as u64 + 1)
+            .collect();
+        offset += n as u64;
+        line_starts.lock().unwrap().extend(found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("a.txt");
+        let binary = dir.path().join("a.bin");
+        let utf16 = dir.path().join("b.txt");
+        std::fs::write(&text, "plain text\n").unwrap();
+        std::fs::write(&binary, [0x7f, b'E', b'L', b'F', 0, 1]).unwrap();
+        std::fs::write(&utf16, [0xFF, 0xFE, b'a', 0]).unwrap();
+        assert!(!looks_binary(&text).unwrap());
+        assert!(looks_binary(&binary).unwrap());
+        assert!(!looks_binary(&utf16).unwrap());
+    }
+
+    #[test]
+    fn formats_hex_rows() {
+        assert_eq!(
+            hex_row(0x10, b"AB\0"),
+            "00000010  41 42 00                                          |AB.|"
+        );
+    }
+
+    #[test]
+    fn indexes_line_starts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        std::fs::write(&path, "one\ntwo\n\nfour").unwrap();
+        let line_starts = Mutex::new(vec![0]);
+        index_lines(&path, &line_starts).unwrap();
+        assert_eq!(*line_starts.lock().unwrap(), [0, 4, 8, 9]);
+    }
+}
@@ -0,0 +1,341 @@
+use eframe::egui;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use similar::{DiffOp, TextDiff};
+
+use crate::lsp::LanguageServer;
+
+/// An external program that reads source on stdin and writes the formatted source to stdout.
+/// `{file}` in `args` is replaced with the path of the file being formatted.
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FormatterCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl FormatterCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FormatterConfig {
+    pub by_extension: HashMap<String, FormatterCommand>,
+    /// Language servers to format with, tried before the formatter for the same extension.
+    #[serde(default)]
+    pub language_servers: HashMap<String, FormatterCommand>,
+}
+
+impl Default for FormatterConfig {
+    fn default() -> Self {
+        let mut by_extension = HashMap::new();
+        by_extension.insert("rs".to_string(), FormatterCommand::new("rustfmt", &["--edition", "2021", "--emit", "stdout"]));
+        by_extension.insert("py".to_string(), FormatterCommand::new("black", &["--quiet", "-"]));
+        for ext in ["js", "jsx", "ts", "tsx", "json", "css", "scss", "html", "md", "yaml", "yml"] {
+            by_extension.insert(ext.to_string(), FormatterCommand::new("prettier", &["--stdin-filepath", "{file}"]));
+        }
+        for ext in ["c", "h", "cc", "cpp", "hpp", "cxx"] {
+            by_extension.insert(ext.to_string(), FormatterCommand::new("clang-format", &["--assume-filename={file}"]));
+        }
+        let mut language_servers = HashMap::new();
+        for ext in ["c", "h", "cc", "cpp", "hpp", "cxx"] {
+            language_servers.insert(ext.to_string(), FormatterCommand::new("clangd", &[]));
+        }
+        language_servers.insert("go".to_string(), FormatterCommand::new("gopls", &[]));
+        Self { by_extension, language_servers }
+    }
+}
+
+impl FormatterConfig {
+    pub fn formatter_for(&self, path: &Path) -> Option<&FormatterCommand> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.by_extension.get(&ext)
+    }
+
+    pub fn language_server_for(&self, path: &Path) -> Option<&FormatterCommand> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.language_servers.get(&ext)
+    }
+}
+
+/// Pipes `input` through `formatter` and returns its stdout.
+pub fn run_formatter(formatter: &FormatterCommand, path: &Path, input: &str) -> Result<String, String> {
+    let file = path.to_string_lossy();
+    let mut child = Command::new(&formatter.program)
+        .args(formatter.args.iter().map(|a| a.replace("{file}", &file)))
+        .current_dir(path.parent().unwrap_or(Path::new(".")))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", formatter.program, e))?;
+
+    // Write from another thread so a formatter that streams output can't deadlock on a full pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", formatter.program, e))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            formatter.program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|_| format!("{} produced invalid UTF-8", formatter.program))
+}
+
+/// A request to format `input` as the contents of `path`.
+pub struct FormatJob {
+    pub path: PathBuf,
+    /// The folder a language server is started in.
+    pub root: PathBuf,
+    pub input: String,
+    pub server: Option<FormatterCommand>,
+    pub formatter: Option<FormatterCommand>,
+}
+
+type FormatResult = (u64, Result<String, String>);
+
+/// Formats documents on a worker thread so the editor never waits on a formatter. The
+/// worker keeps the language servers it starts running for the next format.
+pub struct FormatTasks {
+    /// Started on the first job, so the editor doesn't pay for it otherwise.
+    jobs: Option<Sender<(u64, FormatJob)>>,
+    generation: u64,
+    tx: Sender<FormatResult>,
+    rx: Receiver<FormatResult>,
+    ctx: egui::Context,
+}
+
+impl FormatTasks {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { jobs: None, generation: 0, tx, rx, ctx: ctx.clone() }
+    }
+
+    /// Queues `job` and returns the id its result comes back with.
+    pub fn start(&mut self, job: FormatJob) -> u64 {
+        self.generation += 1;
+        let id = self.generation;
+        let jobs = self.jobs.get_or_insert_with(|| {
+            let (jobs, queue) = mpsc::channel::<(u64, FormatJob)>();
+            let tx = self.tx.clone();
+            let ctx = self.ctx.clone();
+            std::thread::spawn(move || {
+                let mut servers = HashMap::new();
+                // Ends, shutting the servers down, once the editor drops its end of the queue
+                for (id, job) in queue {
+                    if tx.send((id, run_job(&mut servers, &job))).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+            });
+            jobs
+        });
+        if jobs.send((id, job)).is_err() {
+            let _ = self.tx.send((id, Err("The formatter thread has stopped".to_string())));
+        }
+        id
+    }
+
+    /// Jobs finished since the last call, by id.
+    pub fn poll(&mut self) -> Vec<FormatResult> {
+        self.rx.try_iter().collect()
+    }
+}
+
+/// Formats with the language server when there is one, and with the external formatter if
+/// there is none or it fails. When both fail, both errors are reported.
+fn run_job(servers: &mut HashMap<(FormatterCommand, PathBuf), LanguageServer>, job: &FormatJob) -> Result<String, String> {
+    let mut server_error = None;
+    if let Some(server) = &job.server {
+        let key = (server.clone(), job.root.clone());
+        let started = match servers.entry(key.clone()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => LanguageServer::start(server, &job.root).map(|s| entry.insert(s)),
+        };
+        match started.and_then(|s| s.format(&job.path, &job.input)) {
+            Ok(formatted) => return Ok(formatted),
+            Err(e) => server_error = Some(e),
+        }
+        // A server that died or hung is started afresh next time
+        if servers.get_mut(&key).is_some_and(|s| !s.is_healthy()) {
+            servers.remove(&key);
+        }
+    }
+    match (&job.formatter, server_error) {
+        (Some(formatter), None) => run_formatter(formatter, &job.path, &job.input),
+        (Some(formatter), Some(server_error)) => {
+            run_formatter(formatter, &job.path, &job.input).map_err(|e| format!("{}; {}", server_error, e))
+        }
+        (None, Some(server_error)) => Err(server_error),
+        (None, None) => Err(format!("No formatter configured for {}", job.path.display())),
+    }
+}
+
+/// Rewrites `text` into `formatted` by replacing only the lines that differ, and returns
+/// where the char cursor `cursor` ends up in the new text.
+pub fn apply_minimal_edit(text: &mut String, formatted: &str, cursor: usize) -> usize {
+    let old = text.clone();
+    let diff = TextDiff::from_lines(old.as_str(), formatted);
+    let old_offsets = line_offsets(diff.old_slices());
+    let new_offsets = line_offsets(diff.new_slices());
+    let cursor_byte = old.char_indices().nth(cursor).map_or(old.len(), |(i, _)| i);
+
+    let mut new_cursor_byte = formatted.len();
+    for op in diff.ops() {
+        let (old_start, old_end) = (old_offsets[op.old_range().start], old_offsets[op.old_range().end]);
+        let new_start = new_offsets[op.new_range().start];
+        if cursor_byte >= old_start && cursor_byte < old_end {
+            new_cursor_byte = match op {
+                DiffOp::Equal { .. } => new_start + (cursor_byte - old_start),
+                _ => new_start,
+            };
+            break;
+        }
+    }
+
+    // Apply back to front so earlier byte offsets stay valid
+    for op in diff.ops().iter().rev() {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+        let old_range = old_offsets[op.old_range().start]..old_offsets[op.old_range().end];
+        let new_range = new_offsets[op.new_range().start]..new_offsets[op.new_range().end];
+        text.replace_range(old_range, &formatted[new_range]);
+    }
+
+    formatted[..new_cursor_byte].chars().count()
+}
+
+fn line_offsets(lines: &[&str]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(lines.len() + 1);
+    let mut total = 0;
+    offsets.push(0);
+    for line in lines {
+        total += line.len();
+        offsets.push(total);
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_edit_replaces_only_changed_lines() {
+        let mut text = "fn main(){\nlet x=1;\n}\n".to_string();
+        let formatted = "fn main() {\n    let x = 1;\n}\n";
+        apply_minimal_edit(&mut text, formatted, 0);
+        assert_eq!(text, formatted);
+    }
+
+    #[test]
+    fn minimal_edit_keeps_cursor_on_unchanged_line() {
+        let mut text = "a\nb  \nccc\n".to_string();
+        // Cursor after the second `c`, on a line the formatter leaves alone
+        let cursor = apply_minimal_edit(&mut text, "a\nb\nccc\n", 8);
+        assert_eq!(text, "a\nb\nccc\n");
+        assert_eq!(cursor, 6);
+    }
+
+    #[test]
+    fn minimal_edit_moves_cursor_to_start_of_changed_block() {
+        let mut text = "x\nfoo( 1 )\ny\n".to_string();
+        let cursor = apply_minimal_edit(&mut text, "x\nfoo(1)\ny\n", 5);
+        assert_eq!(text, "x\nfoo(1)\ny\n");
+        assert_eq!(cursor, 2);
+    }
+
+    #[test]
+    fn minimal_edit_counts_cursor_in_chars() {
+        let mut text = "é\nzz \n".to_string();
+        let cursor = apply_minimal_edit(&mut text, "é\nzz\n", 1);
+        assert_eq!(cursor, 1);
+        assert_eq!(text, "é\nzz\n");
+    }
+
+    #[test]
+    fn minimal_edit_cursor_at_end() {
+        let mut text = "a \n".to_string();
+        let cursor = apply_minimal_edit(&mut text, "a\n", 3);
+        assert_eq!(cursor, 2);
+    }
+
+    #[test]
+    fn formatter_is_chosen_by_extension() {
+        let config = FormatterConfig::default();
+        assert_eq!(config.formatter_for(Path::new("src/main.RS")).unwrap().program, "rustfmt");
+        assert_eq!(config.language_server_for(Path::new("a.cpp")).unwrap().program, "clangd");
+        assert!(config.formatter_for(Path::new("Makefile")).is_none());
+    }
+
+    #[cfg(unix)]
+    fn job(server: Option<FormatterCommand>, formatter: Option<FormatterCommand>) -> FormatJob {
+        FormatJob {
+            path: PathBuf::from("/tmp/a.c"),
+            root: PathBuf::from("/tmp"),
+            input: "int x;\n".to_string(),
+            server,
+            formatter,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_both_errors_when_server_and_formatter_fail() {
+        let server = FormatterCommand::new("hydroxite-no-such-server", &[]);
+        let formatter = FormatterCommand::new("sh", &["-c", "echo broken >&2; exit 1"]);
+        let err = run_job(&mut HashMap::new(), &job(Some(server), Some(formatter))).unwrap_err();
+        assert!(err.contains("hydroxite-no-such-server"), "{}", err);
+        assert!(err.contains("broken"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn falls_back_to_the_formatter_when_the_server_fails() {
+        let server = FormatterCommand::new("hydroxite-no-such-server", &[]);
+        let formatter = FormatterCommand::new("tr", &["a-z", "A-Z"]);
+        assert_eq!(run_job(&mut HashMap::new(), &job(Some(server), Some(formatter))).unwrap(), "INT X;\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn formats_in_the_background() {
+        let mut tasks = FormatTasks::new(&egui::Context::default());
+        let first = tasks.start(job(None, Some(FormatterCommand::new("tr", &["a-z", "A-Z"]))));
+        let second = tasks.start(job(None, None));
+        assert_ne!(first, second);
+        let mut results = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while results.len() < 2 && std::time::Instant::now() < deadline {
+            results.extend(tasks.poll());
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(results[0], (first, Ok("INT X;\n".to_string())));
+        assert_eq!(results[1].0, second);
+        assert!(results[1].1.as_ref().unwrap_err().starts_with("No formatter configured"));
+    }
+
+    #[test]
+    fn settings_without_language_servers_still_load() {
+        let config: FormatterConfig = serde_json::from_str(r#"{"by_extension": {}}"#).unwrap();
+        assert!(config.language_servers.is_empty());
+    }
+}
@@ -0,0 +1,941 @@
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Ignored,
+    Conflicted,
+}
+
+impl FileStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            FileStatus::Modified => "Modified",
+            FileStatus::Added => "Added",
+            FileStatus::Deleted => "Deleted",
+            FileStatus::Renamed => "Renamed",
+            FileStatus::Untracked => "Untracked",
+            FileStatus::Ignored => "Ignored",
+            FileStatus::Conflicted => "Conflicted",
+        }
+    }
+
+    pub fn color(self, visuals: &egui::Visuals) -> egui::Color32 {
+        match self {
+            FileStatus::Modified | FileStatus::Renamed => egui::Color32::from_rgb(0xd7, 0xa6, 0x3c),
+            FileStatus::Added | FileStatus::Untracked => egui::Color32::from_rgb(0x73, 0xc9, 0x91),
+            FileStatus::Deleted | FileStatus::Conflicted => visuals.error_fg_color,
+            FileStatus::Ignored => visuals.weak_text_color(),
+        }
+    }
+
+    /// How strongly a status shows through on the folders above it; `None` for statuses
+    /// that don't propagate.
+    fn folder_rank(self) -> Option<(u8, FileStatus)> {
+        match self {
+            FileStatus::Conflicted => Some((3, FileStatus::Conflicted)),
+            FileStatus::Modified | FileStatus::Deleted | FileStatus::Renamed => Some((2, FileStatus::Modified)),
+            FileStatus::Added | FileStatus::Untracked => Some((1, FileStatus::Added)),
+            FileStatus::Ignored => None,
+        }
+    }
+}
+
+/// A changed path with its staged and unstaged state.
+pub struct Change {
+    pub path: PathBuf,
+    pub staged: Option<FileStatus>,
+    pub unstaged: Option<FileStatus>,
+}
+
+/// The state of one repository as reported by `git status`.
+pub struct RepoStatus {
+    pub root: PathBuf,
+    /// The checked out branch, or a short commit id when HEAD is detached.
+    pub branch: String,
+    pub ahead: usize,
+    pub behind: usize,
+    files: HashMap<PathBuf, FileStatus>,
+    /// The strongest status of anything inside each folder.
+    folders: HashMap<PathBuf, FileStatus>,
+    /// Untracked or ignored folders git reports as a whole instead of file by file.
+    whole_folders: Vec<(PathBuf, FileStatus)>,
+    /// Everything `git status` lists except ignored paths, in its order.
+    pub changes: Vec<Change>,
+}
+
+impl RepoStatus {
+    pub fn status_of(&self, path: &Path) -> Option<FileStatus> {
+        if let Some(status) = self.files.get(path).or_else(|| self.folders.get(path)) {
+            return Some(*status);
+        }
+        self.whole_folders
+            .iter()
+            .find(|(folder, _)| path.starts_with(folder))
+            .map(|(_, status)| *status)
+    }
+}
+
+/// Runs git in `dir` and returns its standard output.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    git_bytes(dir, args).map(|out| String::from_utf8_lossy(&out).into_owned())
+}
+
+/// Like [`git`], for output that may not be text, such as file contents.
+pub fn git_bytes(dir: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    git_with_input(dir, args, None)
+}
+
+/// Runs git in `dir` with `input` piped to its standard input.
+pub fn git_with_input(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        // Keeps git from rewriting the index, which the file watcher would report right back
+        .arg("--no-optional-locks")
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+
+    // Write from another thread so git can't deadlock on a full output pipe
+    let writer = input.map(|input| {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.to_vec();
+        std::thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output().map_err(|e| format!("failed to run git: {}", e))?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// `path` relative to the repository at `root`, with forward slashes as git expects.
+pub fn repo_relative(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    Some(parts.join("/"))
+}
+
+/// The top of the work tree containing `dir`, spelled from `dir` so it lines up with the
+/// paths shown in the explorer even through symlinks.
+pub fn find_repo_root(dir: &Path) -> Option<PathBuf> {
+    let cdup = git(dir, &["rev-parse", "--show-cdup"]).ok()?;
+    let mut root = dir.to_path_buf();
+    for _ in Path::new(cdup.trim()).components() {
+        root.pop();
+    }
+    Some(root)
+}
+
+pub fn read_status(root: &Path) -> Result<RepoStatus, String> {
+    let output = git(
+        root,
+        &["status", "--porcelain=v1", "-z", "--branch", "--untracked-files=normal", "--ignored=matching"],
+    )?;
+    let mut status = parse_status(root, &output);
+    if status.branch.is_empty() {
+        status.branch = git(root, &["rev-parse", "--short", "HEAD"])
+            .map_or_else(|_| "HEAD".to_string(), |s| s.trim().to_string());
+    }
+    Ok(status)
+}
+
+/// Parses `git status --porcelain=v1 -z --branch` output for the repository at `root`.
+fn parse_status(root: &Path, output: &str) -> RepoStatus {
+    let mut status = RepoStatus {
+        root: root.to_path_buf(),
+        branch: String::new(),
+        ahead: 0,
+        behind: 0,
+        files: HashMap::new(),
+        folders: HashMap::new(),
+        whole_folders: Vec::new(),
+        changes: Vec::new(),
+    };
+    let mut fields = output.split('\0').filter(|f| !f.is_empty());
+    while let Some(field) = fields.next() {
+        if let Some(header) = field.strip_prefix("## ") {
+            parse_branch(header, &mut status);
+            continue;
+        }
+        if field.len() < 4 {
+            continue;
+        }
+        let (xy, path) = field.split_at(3);
+        let xy = &xy[..2];
+        let file_status = match xy {
+            "??" => FileStatus::Untracked,
+            "!!" => FileStatus::Ignored,
+            "DD" | "AU" | "UD" | "UA" | "DU" | "AA" | "UU" => FileStatus::Conflicted,
+            _ if xy.contains('R') || xy.contains('C') => FileStatus::Renamed,
+            _ if xy.contains('A') => FileStatus::Added,
+            _ if xy.contains('D') => FileStatus::Deleted,
+            _ => FileStatus::Modified,
+        };
+        if file_status == FileStatus::Renamed {
+            // The original path follows as its own field
+            fields.next();
+        }
+
+        let full = root.join(path.trim_end_matches('/'));
+        let (staged, unstaged) = match file_status {
+            FileStatus::Untracked | FileStatus::Conflicted => (None, Some(file_status)),
+            _ => (side_status(xy.as_bytes()[0]), side_status(xy.as_bytes()[1])),
+        };
+        if file_status != FileStatus::Ignored {
+            status.changes.push(Change { path: full.clone(), staged, unstaged });
+        }
+        if let Some((rank, folder_status)) = file_status.folder_rank() {
+            for folder in full.ancestors().skip(1).take_while(|a| a.starts_with(root)) {
+                let current = status.folders.get(folder).and_then(|s| s.folder_rank()).map_or(0, |(r, _)| r);
+                if current >= rank {
+                    break;
+                }
+                status.folders.insert(folder.to_path_buf(), folder_status);
+            }
+        }
+        if path.ends_with('/') {
+            status.whole_folders.push((full, file_status));
+        } else {
+            status.files.insert(full, file_status);
+        }
+    }
+    status
+}
+
+/// The status of one column of porcelain output, the index or the work tree.
+fn side_status(code: u8) -> Option<FileStatus> {
+    match code {
+        b'M' | b'T' => Some(FileStatus::Modified),
+        b'A' => Some(FileStatus::Added),
+        b'D' => Some(FileStatus::Deleted),
+        b'R' | b'C' => Some(FileStatus::Renamed),
+        _ => None,
+    }
+}
+
+/// Reads "main...origin/main [ahead 1, behind 2]", "No commits yet on main" or
+/// "HEAD (no branch)" into `status`.
+fn parse_branch(header: &str, status: &mut RepoStatus) {
+    if let Some(branch) = header.strip_prefix("No commits yet on ") {
+        status.branch = branch.to_string();
+        return;
+    }
+    if header.starts_with("HEAD (no branch)") {
+        return;
+    }
+    let (names, counts) = header.split_once(" [").unwrap_or((header, ""));
+    status.branch = names.split("...").next().unwrap_or(names).to_string();
+    for count in counts.trim_end_matches(']').split(", ") {
+        if let Some(n) = count.strip_prefix("ahead ") {
+            status.ahead = n.parse().unwrap_or(0);
+        } else if let Some(n) = count.strip_prefix("behind ") {
+            status.behind = n.parse().unwrap_or(0);
+        }
+    }
+}
+
+/// One `@@` section of a unified diff.
+#[derive(Clone)]
+pub struct DiffHunk {
+    pub header: String,
+    /// The lines for display, without line endings.
+    pub lines: Vec<String>,
+    /// The hunk exactly as git wrote it, carriage returns and all, for applying it.
+    raw: Vec<u8>,
+}
+
+/// The diff of a single file, split so hunks can be applied one at a time.
+#[derive(Clone)]
+pub struct FileDiff {
+    /// The `diff --git`, `index`, `---` and `+++` lines.
+    header: Vec<u8>,
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// The unstaged changes to `path`, or the staged ones if `staged` is set.
+pub fn file_diff(root: &Path, path: &Path, staged: bool) -> Result<FileDiff, String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff"];
+    if staged {
+        args.push("--cached");
+    }
+    args.extend(["--", relative.as_str()]);
+    Ok(parse_diff(&git_bytes(root, &args)?))
+}
+
+// Works on bytes so the patch rebuilt from a hunk matches the file byte for byte, whatever
+// its line endings or encoding
+fn parse_diff(output: &[u8]) -> FileDiff {
+    let mut diff = FileDiff { header: Vec::new(), hunks: Vec::new() };
+    for line in output.split_inclusive(|&b| b == b'\n') {
+        let text = String::from_utf8_lossy(line).trim_end_matches(['\n', '\r']).to_string();
+        if line.starts_with(b"@@") {
+            diff.hunks.push(DiffHunk { header: text, lines: Vec::new(), raw: line.to_vec() });
+        } else if let Some(hunk) = diff.hunks.last_mut() {
+            hunk.lines.push(text);
+            hunk.raw.extend_from_slice(line);
+        } else {
+            diff.header.extend_from_slice(line);
+        }
+    }
+    diff
+}
+
+/// Applies hunk `index` of `diff` to the index, or takes it back out of the index if
+/// `reverse` is set, like `git add -p` / `git reset -p` do.
+pub fn apply_hunk(root: &Path, diff: &FileDiff, index: usize, reverse: bool) -> Result<(), String> {
+    let hunk = diff.hunks.get(index).ok_or_else(|| "no such hunk".to_string())?;
+    let mut patch = diff.header.clone();
+    patch.extend_from_slice(&hunk.raw);
+    let mut args = vec!["apply", "--cached", "--whitespace=nowarn"];
+    if reverse {
+        args.push("--reverse");
+    }
+    args.push("-");
+    git_with_input(root, &args, Some(&patch)).map(|_| ())
+}
+
+/// The contents of `path` at revision `rev`, e.g. "HEAD"; an empty `rev` reads the index.
+pub fn show_file(root: &Path, rev: &str, path: &Path) -> Result<Vec<u8>, String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    git_bytes(root, &["show", &format!("{}:{}", rev, relative)])
+}
+
+/// Like [`show_file`], but `None` when `path` isn't in `rev`: it was added since, or `rev`
+/// is a branch with no commits yet.
+pub fn show_committed_file(root: &Path, rev: &str, path: &Path) -> Result<Option<Vec<u8>>, String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    // With --quiet a missing commit fails without a message; anything else still has one
+    let commit = match git(root, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)]) {
+        Ok(commit) => commit.trim().to_string(),
+        Err(e) if e.is_empty() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if git_bytes(root, &["ls-tree", "-z", "--name-only", &commit, "--", &relative])?.is_empty() {
+        return Ok(None);
+    }
+    git_bytes(root, &["show", &format!("{}:{}", commit, relative)]).map(Some)
+}
+
+pub fn stage_path(root: &Path, path: &Path) -> Result<(), String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    git(root, &["add", "--all", "--", &relative]).map(|_| ())
+}
+
+pub fn unstage_path(root: &Path, path: &Path) -> Result<(), String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    // Before the first commit there is no HEAD to reset to
+    git(root, &["reset", "--quiet", "--", &relative])
+        .or_else(|_| git(root, &["rm", "--cached", "--quiet", "-r", "--", &relative]))
+        .map(|_| ())
+}
+
+pub fn commit(root: &Path, message: &str, amend: bool) -> Result<(), String> {
+    let mut args = vec!["commit", "--quiet", "--file=-"];
+    if amend {
+        args.push("--amend");
+    }
+    git_with_input(root, &args, Some(message.as_bytes())).map(|_| ())
+}
+
+pub fn last_commit_message(root: &Path) -> Result<String, String> {
+    git(root, &["log", "-1", "--format=%B"]).map(|m| m.trim_end().to_string())
+}
+
+pub fn branches(root: &Path) -> Result<Vec<String>, String> {
+    let output = git(root, &["branch", "--format=%(refname:short)"])?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+pub fn switch_branch(root: &Path, branch: &str) -> Result<(), String> {
+    git(root, &["switch", "--quiet", branch]).map(|_| ())
+}
+
+pub fn create_branch(root: &Path, branch: &str) -> Result<(), String> {
+    git(root, &["switch", "--quiet", "--create", branch]).map(|_| ())
+}
+
+/// A stash entry: where it is in the stash list and the commit that holds it.
+#[derive(Clone)]
+pub struct Stash {
+    pub index: usize,
+    pub id: String,
+    pub message: String,
+}
+
+impl Stash {
+    pub fn name(&self) -> String {
+        format!("stash@{{{}}}", self.index)
+    }
+}
+
+/// Stash entries, newest first.
+pub fn stashes(root: &Path) -> Result<Vec<Stash>, String> {
+    let output = git(root, &["stash", "list", "--format=%H%x00%gs"])?;
+    let stashes = output
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let (id, message) = line.split_once('\0').unwrap_or((line, ""));
+            Stash { index, id: id.to_string(), message: message.to_string() }
+        })
+        .collect();
+    Ok(stashes)
+}
+
+pub fn stash_push(root: &Path, message: &str) -> Result<(), String> {
+    let mut args = vec!["stash", "push", "--quiet", "--include-untracked"];
+    if !message.trim().is_empty() {
+        args.extend(["--message", message.trim()]);
+    }
+    git(root, &args).map(|_| ())
+}
+
+/// Runs `git stash <command>` (apply, pop or drop) on `stash`, as long as it is still at
+/// the same place in the stash list.
+pub fn stash_command(root: &Path, command: &str, stash: &Stash) -> Result<(), String> {
+    let name = stash.name();
+    let id = git(root, &["rev-parse", "--verify", "--quiet", &name]).unwrap_or_default();
+    if id.trim() != stash.id {
+        return Err("the stash list has changed since it was read".to_string());
+    }
+    git(root, &["stash", command, "--quiet", &name]).map(|_| ())
+}
+
+/// The commit a line was last changed in, as reported by `git blame`.
+pub struct BlameCommit {
+    pub id: String,
+    pub author: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub summary: String,
+}
+
+impl BlameCommit {
+    /// Lines changed in the working copy or index but not committed yet.
+    pub fn is_uncommitted(&self) -> bool {
+        self.id.bytes().all(|b| b == b'0')
+    }
+}
+
+/// Blames every line of `contents`, the current text of `path`, so unsaved edits line up.
+pub fn blame(root: &Path, path: &Path, contents: &[u8]) -> Result<Vec<Arc<BlameCommit>>, String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    let output = git_with_input(root, &["blame", "--porcelain", "--contents", "-", "--", &relative], Some(contents))?;
+    Ok(parse_blame(&String::from_utf8_lossy(&output)))
+}
+
+/// Parses `git blame --porcelain`: a header line per source line, commit details the
+/// first time each commit appears, then the line itself after a tab.
+fn parse_blame(output: &str) -> Vec<Arc<BlameCommit>> {
+    let mut commits: HashMap<String, BlameCommit> = HashMap::new();
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<(String, usize)> = None;
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            lines.extend(current.take().map(|(id, number)| (number, id)));
+            continue;
+        }
+        let mut words = line.splitn(2, ' ');
+        let (key, value) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        // SHA-1 or SHA-256 ids
+        if matches!(key.len(), 40 | 64) && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let number = value.split(' ').nth(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+            commits.entry(key.to_string()).or_insert_with(|| BlameCommit {
+                id: key.to_string(),
+                author: String::new(),
+                time: 0,
+                summary: String::new(),
+            });
+            current = Some((key.to_string(), number));
+        } else if let Some(commit) = current.as_ref().and_then(|(id, _)| commits.get_mut(id)) {
+            match key {
+                "author" => commit.author = value.to_string(),
+                "author-time" => commit.time = value.parse().unwrap_or(0),
+                "summary" => commit.summary = value.to_string(),
+                _ => {}
+            }
+        }
+    }
+    lines.sort_by_key(|(number, _)| *number);
+    let commits: HashMap<String, Arc<BlameCommit>> = commits.into_iter().map(|(id, c)| (id, Arc::new(c))).collect();
+    lines.into_iter().filter_map(|(_, id)| commits.get(&id).cloned()).collect()
+}
+
+/// A commit in a file's history, with the path the file had at that commit.
+pub struct LogEntry {
+    pub id: String,
+    pub short_id: String,
+    pub author: String,
+    pub date: String,
+    pub summary: String,
+    pub path: PathBuf,
+}
+
+/// Commits that touched `path`, newest first, following it through renames.
+pub fn file_log(root: &Path, path: &Path) -> Result<Vec<LogEntry>, String> {
+    let relative = repo_relative(root, path).ok_or_else(|| "path is outside the repository".to_string())?;
+    let output = git(
+        root,
+        &[
+            "log",
+            "-z",
+            "--follow",
+            "--name-only",
+            "--date=short",
+            "--format=%x1e%H%x00%h%x00%an%x00%ad%x00%s",
+            "--",
+            &relative,
+        ],
+    )?;
+    // Each record is the NUL-separated fields, a NUL, then a newline and the path unquoted
+    let entries = output
+        .split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.split('\0');
+            let mut field = || fields.next().unwrap_or_default().to_string();
+            let (id, short_id, author, date, summary) = (field(), field(), field(), field(), field());
+            if id.is_empty() {
+                return None;
+            }
+            let name = fields.next().map(|n| n.trim_start_matches('\n')).filter(|n| !n.is_empty());
+            let path = root.join(name.unwrap_or(relative.as_str()));
+            Some(LogEntry { id, short_id, author, date, summary, path })
+        })
+        .collect();
+    Ok(entries)
+}
+
+#[derive(Default)]
+struct FolderState {
+    /// `None` until the first read finishes, then `None` inside when the folder isn't in a repository.
+    status: Option<Option<RepoStatus>>,
+    pending: Option<u64>,
+    stale: bool,
+}
+
+/// Keeps the git status of every open folder up to date, running git on background
+/// threads. At most one read per folder is in flight; changes that arrive meanwhile
+/// queue a single re-read.
+pub struct GitTracker {
+    folders: HashMap<PathBuf, FolderState>,
+    generation: u64,
+    /// Bumped whenever a read finishes, so anything derived from the repository knows to update.
+    revision: u64,
+    tx: Sender<(PathBuf, u64, Option<RepoStatus>)>,
+    rx: Receiver<(PathBuf, u64, Option<RepoStatus>)>,
+    ctx: egui::Context,
+}
+
+impl GitTracker {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { folders: HashMap::new(), generation: 0, revision: 0, tx, rx, ctx: ctx.clone() }
+    }
+
+    /// Tracks exactly `folders`, starting reads for new ones.
+    pub fn set_folders(&mut self, folders: &[PathBuf]) {
+        self.folders.retain(|folder, _| folders.contains(folder));
+        for folder in folders {
+            self.folders.entry(folder.clone()).or_insert_with(|| FolderState { stale: true, ..Default::default() });
+        }
+    }
+
+    /// Re-reads every repository, e.g. after files changed on disk.
+    pub fn refresh(&mut self) {
+        for state in self.folders.values_mut() {
+            state.stale = true;
+        }
+    }
+
+    /// Picks up finished reads and starts any that are due.
+    pub fn poll(&mut self) {
+        for (folder, generation, status) in self.rx.try_iter() {
+            if let Some(state) = self.folders.get_mut(&folder) {
+                if state.pending == Some(generation) {
+                    state.status = Some(status);
+                    state.pending = None;
+                    self.revision += 1;
+                }
+            }
+        }
+        for (folder, state) in &mut self.folders {
+            if !state.stale || state.pending.is_some() {
+                continue;
+            }
+            self.generation += 1;
+            state.pending = Some(self.generation);
+            state.stale = false;
+
+            let tx = self.tx.clone();
+            let ctx = self.ctx.clone();
+            let folder = folder.clone();
+            let generation = self.generation;
+            std::thread::spawn(move || {
+                let status = find_repo_root(&folder).and_then(|root| read_status(&root).ok());
+                if tx.send((folder, generation, status)).is_ok() {
+                    ctx.request_repaint();
+                }
+            });
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The repository containing `path`, if its status has been read.
+    pub fn repo_for(&self, path: &Path) -> Option<&RepoStatus> {
+        self.folders
+            .values()
+            .filter_map(|state| state.status.as_ref()?.as_ref())
+            .filter(|repo| path.starts_with(&repo.root))
+            .max_by_key(|repo| repo.root.components().count())
+    }
+
+    pub fn status_of(&self, path: &Path) -> Option<FileStatus> {
+        self.repo_for(path)?.status_of(path)
+    }
+
+    /// Every repository found so far, once each even if several folders share it.
+    pub fn repos(&self) -> Vec<&RepoStatus> {
+        let mut repos: Vec<&RepoStatus> = self
+            .folders
+            .values()
+            .filter_map(|state| state.status.as_ref()?.as_ref())
+            .collect();
+        repos.sort_by(|a, b| a.root.cmp(&b.root));
+        repos.dedup_by(|a, b| a.root == b.root);
+        repos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A fresh repository on branch `main` with one commit holding `kept.txt`,
+    /// `old.txt` and `src/lib.rs`.
+    fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q", "-b", "main"]).unwrap();
+        git(root, &["config", "user.name", "Test"]).unwrap();
+        git(root, &["config", "user.email", "test@example.com"]).unwrap();
+        git(root, &["config", "commit.gpgsign", "false"]).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("kept.txt"), "kept\n").unwrap();
+        fs::write(root.join("old.txt"), "to be renamed\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "pub fn f() {}\n").unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        git(root, &["add", "."]).unwrap();
+        git(root, &["commit", "-q", "-m", "initial"]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reports_each_kind_of_change() {
+        let dir = repo();
+        let root = dir.path();
+        fs::write(root.join("src/lib.rs"), "pub fn f() { changed() }\n").unwrap();
+        fs::write(root.join("new.txt"), "untracked\n").unwrap();
+        fs::write(root.join("staged.txt"), "staged\n").unwrap();
+        git(root, &["add", "staged.txt"]).unwrap();
+        git(root, &["mv", "old.txt", "renamed.txt"]).unwrap();
+        fs::write(root.join("debug.log"), "ignored\n").unwrap();
+
+        let status = read_status(root).unwrap();
+        assert_eq!(status.branch, "main");
+        assert_eq!(status.status_of(&root.join("src/lib.rs")), Some(FileStatus::Modified));
+        assert_eq!(status.status_of(&root.join("new.txt")), Some(FileStatus::Untracked));
+        assert_eq!(status.status_of(&root.join("staged.txt")), Some(FileStatus::Added));
+        assert_eq!(status.status_of(&root.join("renamed.txt")), Some(FileStatus::Renamed));
+        assert_eq!(status.status_of(&root.join("debug.log")), Some(FileStatus::Ignored));
+        assert_eq!(status.status_of(&root.join("kept.txt")), None);
+        // The original name of a rename is consumed, not reported on its own
+        assert_eq!(status.status_of(&root.join("old.txt")), None);
+
+        let change = |name: &str| status.changes.iter().find(|c| c.path == root.join(name)).unwrap();
+        assert_eq!(change("staged.txt").staged, Some(FileStatus::Added));
+        assert_eq!(change("staged.txt").unstaged, None);
+        assert_eq!(change("src/lib.rs").staged, None);
+        assert_eq!(change("src/lib.rs").unstaged, Some(FileStatus::Modified));
+        assert_eq!(change("new.txt").unstaged, Some(FileStatus::Untracked));
+        assert!(status.changes.iter().all(|c| c.path != root.join("debug.log")));
+    }
+
+    #[test]
+    fn folders_take_the_strongest_status_inside() {
+        let dir = repo();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/deep/er")).unwrap();
+        fs::write(root.join("src/deep/er/new.rs"), "").unwrap();
+        git(root, &["add", "src/deep"]).unwrap();
+        fs::write(root.join("src/lib.rs"), "changed\n").unwrap();
+        fs::create_dir(root.join("untracked_dir")).unwrap();
+        fs::write(root.join("untracked_dir/a.txt"), "").unwrap();
+        fs::create_dir(root.join("logs")).unwrap();
+        fs::write(root.join("logs/x.log"), "").unwrap();
+
+        let status = read_status(root).unwrap();
+        // Modified outranks the added file further down
+        assert_eq!(status.status_of(&root.join("src")), Some(FileStatus::Modified));
+        assert_eq!(status.status_of(&root.join("src/deep")), Some(FileStatus::Added));
+        assert_eq!(status.status_of(&root.join("src/deep/er")), Some(FileStatus::Added));
+        assert_eq!(status.status_of(root), Some(FileStatus::Modified));
+        // Untracked folders are reported whole and cover everything inside
+        assert_eq!(status.status_of(&root.join("untracked_dir/a.txt")), Some(FileStatus::Untracked));
+        assert_eq!(status.status_of(&root.join("untracked_dir/later/b.txt")), Some(FileStatus::Untracked));
+        // Ignored files don't colour their folders
+        assert_eq!(status.status_of(&root.join("logs/x.log")), Some(FileStatus::Ignored));
+        assert_eq!(status.status_of(&root.join("logs")), None);
+    }
+
+    #[test]
+    fn conflicts_outrank_everything() {
+        let status = parse_status(Path::new("/r"), "UU a/b.txt\0?? a/c.txt\0 M a/d.txt\0");
+        assert_eq!(status.status_of(Path::new("/r/a/b.txt")), Some(FileStatus::Conflicted));
+        assert_eq!(status.status_of(Path::new("/r/a")), Some(FileStatus::Conflicted));
+        assert_eq!(status.status_of(Path::new("/r")), Some(FileStatus::Conflicted));
+    }
+
+    #[test]
+    fn parses_branch_headers() {
+        let status = parse_status(Path::new("/r"), "## main...origin/main [ahead 2, behind 13]\0 M a.txt\0");
+        assert_eq!((status.branch.as_str(), status.ahead, status.behind), ("main", 2, 13));
+        assert_eq!(status.status_of(Path::new("/r/a.txt")), Some(FileStatus::Modified));
+
+        let status = parse_status(Path::new("/r"), "## feature/x...origin/feature/x [behind 1]\0");
+        assert_eq!((status.branch.as_str(), status.ahead, status.behind), ("feature/x", 0, 1));
+
+        let status = parse_status(Path::new("/r"), "## topic\0");
+        assert_eq!((status.branch.as_str(), status.ahead, status.behind), ("topic", 0, 0));
+
+        let status = parse_status(Path::new("/r"), "## No commits yet on trunk\0");
+        assert_eq!(status.branch, "trunk");
+
+        let status = parse_status(Path::new("/r"), "## HEAD (no branch)\0");
+        assert_eq!(status.branch, "");
+    }
+
+    #[test]
+    fn paths_with_spaces_and_arrows_survive_z_output() {
+        let status = parse_status(Path::new("/r"), "R  new name -> x.txt\0old name.txt\0?? weird \"quote\".txt\0");
+        assert_eq!(status.status_of(Path::new("/r/new name -> x.txt")), Some(FileStatus::Renamed));
+        assert_eq!(status.status_of(Path::new("/r/weird \"quote\".txt")), Some(FileStatus::Untracked));
+        assert_eq!(status.changes.len(), 2);
+    }
+
+    #[test]
+    fn counts_commits_ahead_of_and_behind_upstream() {
+        let upstream = repo();
+        let work = tempfile::tempdir().unwrap();
+        let clone = work.path().join("clone");
+        git(work.path(), &["clone", "-q", &upstream.path().to_string_lossy(), "clone"]).unwrap();
+        git(&clone, &["config", "user.name", "Test"]).unwrap();
+        git(&clone, &["config", "user.email", "test@example.com"]).unwrap();
+        git(&clone, &["commit", "-q", "--allow-empty", "-m", "local 1"]).unwrap();
+        git(&clone, &["commit", "-q", "--allow-empty", "-m", "local 2"]).unwrap();
+        git(upstream.path(), &["commit", "-q", "--allow-empty", "-m", "remote"]).unwrap();
+        git(&clone, &["fetch", "-q"]).unwrap();
+
+        let status = read_status(&clone).unwrap();
+        assert_eq!((status.branch.as_str(), status.ahead, status.behind), ("main", 2, 1));
+    }
+
+    #[test]
+    fn detached_head_shows_the_commit() {
+        let dir = repo();
+        let id = git(dir.path(), &["rev-parse", "--short", "HEAD"]).unwrap();
+        git(dir.path(), &["checkout", "-q", "--detach"]).unwrap();
+        assert_eq!(read_status(dir.path()).unwrap().branch, id.trim());
+    }
+
+    #[test]
+    fn finds_the_root_from_a_subfolder() {
+        let dir = repo();
+        assert_eq!(find_repo_root(&dir.path().join("src")), Some(dir.path().to_path_buf()));
+        let outside = tempfile::tempdir().unwrap();
+        assert_eq!(find_repo_root(outside.path()), None);
+    }
+
+    #[test]
+    fn tracker_reads_status_in_the_background() {
+        let dir = repo();
+        fs::write(dir.path().join("kept.txt"), "edited\n").unwrap();
+        let mut tracker = GitTracker::new(&egui::Context::default());
+        tracker.set_folders(&[dir.path().join("src")]);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while tracker.revision() == 0 && std::time::Instant::now() < deadline {
+            tracker.poll();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(tracker.status_of(&dir.path().join("kept.txt")), Some(FileStatus::Modified));
+        assert_eq!(tracker.repos().len(), 1);
+    }
+
+    #[test]
+    fn files_missing_from_a_commit_are_none() {
+        let dir = repo();
+        let root = dir.path();
+        fs::write(root.join("new.txt"), "new\n").unwrap();
+        assert_eq!(show_committed_file(root, "HEAD", &root.join("kept.txt")).unwrap(), Some(b"kept\n".to_vec()));
+        assert_eq!(show_committed_file(root, "HEAD", &root.join("new.txt")).unwrap(), None);
+        assert!(show_committed_file(root, "HEAD", Path::new("/elsewhere/file.txt")).is_err());
+
+        let unborn = tempfile::tempdir().unwrap();
+        git(unborn.path(), &["init", "-q", "-b", "main"]).unwrap();
+        assert_eq!(show_committed_file(unborn.path(), "HEAD", &unborn.path().join("a.txt")).unwrap(), None);
+        // Outside any repository is an error, not an empty file
+        let plain = tempfile::tempdir().unwrap();
+        assert!(show_committed_file(plain.path(), "HEAD", &plain.path().join("a.txt")).is_err());
+    }
+
+    #[test]
+    fn file_log_follows_renames() {
+        let dir = repo();
+        let root = dir.path();
+        git(root, &["mv", "old.txt", "new.txt"]).unwrap();
+        git(root, &["commit", "-q", "-m", "rename old.txt"]).unwrap();
+        fs::write(root.join("new.txt"), "edited\n").unwrap();
+        git(root, &["commit", "-q", "-am", "edit new.txt"]).unwrap();
+
+        let log = file_log(root, &root.join("new.txt")).unwrap();
+        let summaries: Vec<&str> = log.iter().map(|e| e.summary.as_str()).collect();
+        assert_eq!(summaries, ["edit new.txt", "rename old.txt", "initial"]);
+        assert_eq!(log[0].path, root.join("new.txt"));
+        assert_eq!(log[2].path, root.join("old.txt"));
+        assert_eq!(log[0].author, "Test");
+        assert!(log[0].id.starts_with(&log[0].short_id));
+        assert!(file_log(root, Path::new("/elsewhere/file.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_log_keeps_unusual_names_whole() {
+        let dir = repo();
+        let root = dir.path();
+        let name = "café \"quoted\"\nline.txt";
+        fs::write(root.join(name), "a\n").unwrap();
+        git(root, &["add", "--", name]).unwrap();
+        git(root, &["commit", "-q", "-m", "add it"]).unwrap();
+
+        let log = file_log(root, &root.join(name)).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].path, root.join(name));
+        assert_eq!(log[0].summary, "add it");
+    }
+
+    #[test]
+    fn blames_committed_and_unsaved_lines() {
+        let dir = repo();
+        let root = dir.path();
+        let path = root.join("blamed.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        git(root, &["add", "blamed.txt"]).unwrap();
+        git(root, &["commit", "-q", "-m", "first"]).unwrap();
+        fs::write(&path, "one\n2\nthree\n").unwrap();
+        git(root, &["commit", "-q", "-am", "second"]).unwrap();
+
+        // Lines from one commit repeat its id without its details
+        let lines = blame(root, &path, b"one\n2\nthree\nunsaved\n").unwrap();
+        let summaries: Vec<&str> = lines.iter().map(|c| c.summary.as_str()).collect();
+        assert_eq!(summaries[..3], ["first", "second", "first"]);
+        assert!(Arc::ptr_eq(&lines[0], &lines[2]));
+        assert_eq!(lines[0].author, "Test");
+        assert!(lines[0].time > 0);
+        assert!(lines[3].is_uncommitted());
+        assert!(!lines[1].is_uncommitted());
+    }
+
+    #[test]
+    fn blames_sha256_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q", "-b", "main", "--object-format=sha256"]).unwrap();
+        git(root, &["config", "user.name", "Test"]).unwrap();
+        git(root, &["config", "user.email", "test@example.com"]).unwrap();
+        git(root, &["config", "commit.gpgsign", "false"]).unwrap();
+        fs::write(root.join("a.txt"), "a\n").unwrap();
+        git(root, &["add", "a.txt"]).unwrap();
+        git(root, &["commit", "-q", "-m", "only"]).unwrap();
+
+        let lines = blame(root, &root.join("a.txt"), b"a\nb\n").unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].id.len(), 64);
+        assert_eq!(lines[0].summary, "only");
+        assert!(lines[1].is_uncommitted());
+    }
+
+    #[test]
+    fn stages_one_hunk_of_a_crlf_file() {
+        let dir = repo();
+        let root = dir.path();
+        let path = root.join("crlf.txt");
+        let lines: Vec<Vec<u8>> = (0..20).map(|i| format!("line {}\r\n", i).into_bytes()).collect();
+        fs::write(&path, lines.concat()).unwrap();
+        git(root, &["add", "crlf.txt"]).unwrap();
+        git(root, &["commit", "-q", "-m", "add crlf.txt"]).unwrap();
+
+        // Two hunks far apart, one of them not valid UTF-8
+        let mut edited = lines.clone();
+        edited[1] = b"caf\xe9\r\n".to_vec();
+        edited[18] = b"changed\r\n".to_vec();
+        fs::write(&path, edited.concat()).unwrap();
+
+        let diff = file_diff(root, &path, false).unwrap();
+        assert_eq!(diff.hunks.len(), 2);
+        assert!(diff.hunks[0].lines.contains(&"+caf\u{fffd}".to_string()));
+        apply_hunk(root, &diff, 0, false).unwrap();
+        let mut staged = lines.clone();
+        staged[1] = edited[1].clone();
+        assert_eq!(show_file(root, "", &path).unwrap(), staged.concat());
+
+        let diff = file_diff(root, &path, true).unwrap();
+        assert_eq!(diff.hunks.len(), 1);
+        apply_hunk(root, &diff, 0, true).unwrap();
+        assert_eq!(show_file(root, "", &path).unwrap(), lines.concat());
+    }
+
+    #[test]
+    fn stash_commands_refuse_a_stale_entry() {
+        let dir = repo();
+        let root = dir.path();
+        fs::write(root.join("kept.txt"), "first\n").unwrap();
+        stash_push(root, "first").unwrap();
+        let stale = stashes(root).unwrap();
+        fs::write(root.join("kept.txt"), "second\n").unwrap();
+        stash_push(root, "second").unwrap();
+
+        // stash@{0} is now "second", which the old list doesn't know about
+        assert!(stash_command(root, "drop", &stale[0]).is_err());
+        let current = stashes(root).unwrap();
+        assert_eq!(current.len(), 2);
+        assert!(current[0].message.ends_with("second"));
+        stash_command(root, "drop", &current[1]).unwrap();
+        let left = stashes(root).unwrap();
+        assert_eq!(left.len(), 1);
+        assert!(left[0].message.ends_with("second"));
+    }
+}
@@ -0,0 +1,348 @@
+use eframe::egui;
+use encoding_rs::Encoding;
+use similar::{DiffOp, TextDiff};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::encoding::{self, LineEnding, TextEncoding};
+use crate::git;
+
+/// Give up on an exact diff after this long and settle for a coarser one, so typing in
+/// a huge file never stalls.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(50);
+
+const OUT_OF_DATE: &str = "the change no longer matches the file; try again";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl ChangeKind {
+    pub fn color(self, visuals: &egui::Visuals) -> egui::Color32 {
+        match self {
+            ChangeKind::Added => egui::Color32::from_rgb(0x73, 0xc9, 0x91),
+            ChangeKind::Modified => egui::Color32::from_rgb(0x4e, 0x94, 0xce),
+            ChangeKind::Deleted => visuals.error_fg_color,
+        }
+    }
+}
+
+/// A run of buffer lines that differs from the index.
+pub struct Hunk {
+    pub kind: ChangeKind,
+    /// Buffer lines `start..end`; empty for a deletion, which sits just before `start`.
+    pub start: usize,
+    pub end: usize,
+    /// The lines of the indexed file they replace.
+    base_start: usize,
+    base_end: usize,
+    pub original: String,
+}
+
+impl Hunk {
+    /// Whether the cursor on `line` is on this hunk. A deletion counts for the line after it.
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.start..self.end.max(self.start + 1)).contains(&line)
+    }
+
+    /// `content` with this change put back the way it is in the index. Hunks are worked
+    /// out ahead of time, so one that no longer fits `content` is refused.
+    pub fn revert(&self, content: &str) -> Result<String, String> {
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let (Some(before), Some(after)) = (lines.get(..self.start), lines.get(self.end..)) else {
+            return Err(OUT_OF_DATE.to_string());
+        };
+        let mut text = String::with_capacity(content.len());
+        text.extend(before.iter().copied());
+        text.push_str(&self.original);
+        text.extend(after.iter().copied());
+        Ok(text)
+    }
+}
+
+struct Source {
+    path: PathBuf,
+    repo_root: PathBuf,
+    revision: u64,
+    encoding: &'static Encoding,
+}
+
+/// Lines of the open file that differ from the version in the git index. The indexed
+/// text is read in the background whenever the repository changes; the diff against
+/// the buffer is redone only when the buffer has been edited.
+pub struct GitGutter {
+    source: Option<Source>,
+    /// The indexed text, normalized to `\n` like the buffer.
+    base: Option<String>,
+    hunks: Vec<Hunk>,
+    /// The buffer's edit count when `hunks` were worked out.
+    diffed_edits: Option<u64>,
+    generation: u64,
+    tx: Sender<(u64, Option<String>)>,
+    rx: Receiver<(u64, Option<String>)>,
+    ctx: egui::Context,
+}
+
+impl GitGutter {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            source: None,
+            base: None,
+            hunks: Vec::new(),
+            diffed_edits: None,
+            generation: 0,
+            tx,
+            rx,
+            ctx: ctx.clone(),
+        }
+    }
+
+    /// Points the gutter at `path` in the repository at `repo_root`, re-reading the index
+    /// when either changes or `revision` says the repository did.
+    pub fn set_source(&mut self, path: Option<&Path>, repo_root: Option<&Path>, revision: u64, encoding: &'static Encoding) {
+        let (Some(path), Some(repo_root)) = (path, repo_root) else {
+            self.source = None;
+            self.base = None;
+            self.hunks.clear();
+            return;
+        };
+        if let Some(source) = &self.source {
+            if source.path == path && source.repo_root == repo_root && source.encoding == encoding {
+                if source.revision == revision {
+                    return;
+                }
+            } else {
+                // A different file; don't show the old one's changes while loading
+                self.base = None;
+                self.hunks.clear();
+            }
+        }
+        self.source = Some(Source { path: path.to_path_buf(), repo_root: repo_root.to_path_buf(), revision, encoding });
+
+        self.generation += 1;
+        let generation = self.generation;
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let repo_root = repo_root.to_path_buf();
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            // Fails for untracked files, which get no gutter
+            let base = git::show_file(&repo_root, "", &path)
+                .ok()
+                .map(|bytes| encoding::decode_with(&bytes, encoding).text);
+            if tx.send((generation, base)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Picks up a newly read index version and re-diffs `content` if it or the index
+    /// changed; `edits` counts the changes to `content`.
+    pub fn update(&mut self, content: &str, edits: u64) {
+        for (generation, base) in self.rx.try_iter() {
+            if generation == self.generation {
+                self.base = base;
+                self.diffed_edits = None;
+            }
+        }
+        let Some(base) = &self.base else {
+            self.hunks.clear();
+            return;
+        };
+        if self.diffed_edits != Some(edits) {
+            self.hunks = diff_hunks(base, content);
+            self.diffed_edits = Some(edits);
+        }
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    pub fn hunk_at_line(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|h| h.contains_line(line))
+    }
+
+    /// Writes just `hunk` from `content` into the index, leaving the rest of the file's
+    /// changes unstaged.
+    pub fn stage(&self, content: &str, hunk: &Hunk, encoding: TextEncoding, line_ending: LineEnding) -> Result<(), String> {
+        let (Some(source), Some(base)) = (&self.source, &self.base) else {
+            return Err("the file is not tracked by git".to_string());
+        };
+        let relative = git::repo_relative(&source.repo_root, &source.path)
+            .ok_or_else(|| "the file is outside the repository".to_string())?;
+
+        let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let (Some(before), Some(replaced), Some(changed), Some(after)) = (
+            base_lines.get(..hunk.base_start),
+            base_lines.get(hunk.base_start..hunk.base_end),
+            lines.get(hunk.start..hunk.end),
+            base_lines.get(hunk.base_end..),
+        ) else {
+            return Err(OUT_OF_DATE.to_string());
+        };
+        // The index may have moved on since the hunk was worked out
+        if replaced.concat() != hunk.original {
+            return Err(OUT_OF_DATE.to_string());
+        }
+        let mut staged = String::with_capacity(base.len());
+        staged.extend(before.iter().copied());
+        staged.extend(changed.iter().copied());
+        staged.extend(after.iter().copied());
+        let bytes = encoding::encode(&staged, encoding, line_ending)?;
+
+        let root = &source.repo_root;
+        // Keep the file's mode, e.g. the executable bit
+        let listing = git::git(root, &["ls-files", "--stage", "--", &relative])?;
+        let mode = listing.split_whitespace().next().ok_or_else(|| "the file is not in the index".to_string())?;
+        let blob = git::git_with_input(root, &["hash-object", "-w", "--stdin", "--path", &relative], Some(&bytes))?;
+        let blob = String::from_utf8_lossy(&blob).trim().to_string();
+        git::git(root, &["update-index", "--cacheinfo", &format!("{},{},{}", mode, blob, relative)])?;
+        Ok(())
+    }
+}
+
+/// Groups the line diff between `base` and `content` into hunks of adjacent changes.
+fn diff_hunks(base: &str, content: &str) -> Vec<Hunk> {
+    let diff = TextDiff::configure().timeout(DIFF_TIMEOUT).diff_lines(base, content);
+    let base_lines = diff.old_slices();
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut in_change = false;
+    for op in diff.ops() {
+        if let DiffOp::Equal { .. } = op {
+            in_change = false;
+            continue;
+        }
+        let (old, new) = (op.old_range(), op.new_range());
+        match hunks.last_mut() {
+            // Directly follows another change, e.g. an insertion right after a deletion
+            Some(hunk) if in_change => {
+                hunk.end = new.end;
+                hunk.base_end = old.end;
+            }
+            _ => hunks.push(Hunk {
+                kind: ChangeKind::Modified,
+                start: new.start,
+                end: new.end,
+                base_start: old.start,
+                base_end: old.end,
+                original: String::new(),
+            }),
+        }
+        in_change = true;
+    }
+    for hunk in &mut hunks {
+        hunk.kind = match (hunk.base_start == hunk.base_end, hunk.start == hunk.end) {
+            (true, _) => ChangeKind::Added,
+            (_, true) => ChangeKind::Deleted,
+            _ => ChangeKind::Modified,
+        };
+        hunk.original = base_lines[hunk.base_start..hunk.base_end].concat();
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const BASE: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    #[test]
+    fn hunks_are_classified() {
+        let hunks = diff_hunks(BASE, "zero\none\nTWO\nthree\nfive\n");
+        let kinds: Vec<_> = hunks.iter().map(|h| (h.kind, h.start, h.end)).collect();
+        assert!(kinds == [(ChangeKind::Added, 0, 1), (ChangeKind::Modified, 2, 3), (ChangeKind::Deleted, 4, 4)]);
+        assert_eq!(hunks[1].original, "two\n");
+        assert_eq!(hunks[2].original, "four\n");
+        // A deletion is picked up from the line after it
+        assert!(hunks[2].contains_line(4));
+    }
+
+    #[test]
+    fn revert_puts_back_each_hunk() {
+        let content = "zero\none\nTWO\nthree\nfive\n";
+        for hunk in diff_hunks(BASE, content) {
+            let reverted = hunk.revert(content).unwrap();
+            assert!(diff_hunks(BASE, &reverted).len() == 2, "{}", reverted);
+        }
+        let hunks = diff_hunks(BASE, content);
+        assert_eq!(hunks[1].revert(content).unwrap(), "zero\none\ntwo\nthree\nfive\n");
+        assert_eq!(hunks[2].revert(content).unwrap(), "zero\none\nTWO\nthree\nfour\nfive\n");
+    }
+
+    #[test]
+    fn stale_hunks_are_refused() {
+        let hunks = diff_hunks(BASE, "one\ntwo\nthree\nfour\nfive\nsix\nseven\n");
+        // The buffer was cut short after the hunks were worked out
+        assert!(hunks[0].revert("one\n").is_err());
+    }
+
+    fn staged_text(root: &Path) -> String {
+        git::git(root, &["show", ":file.txt"]).unwrap()
+    }
+
+    fn loaded_gutter(root: &Path, content: &str) -> GitGutter {
+        let mut gutter = GitGutter::new(&egui::Context::default());
+        gutter.set_source(Some(&root.join("file.txt")), Some(root), 1, encoding_rs::UTF_8);
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while gutter.base.is_none() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            gutter.update(content, 0);
+        }
+        gutter.update(content, 0);
+        gutter
+    }
+
+    #[test]
+    fn rediffs_only_after_an_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git::git(root, &["init", "-q"]).unwrap();
+        fs::write(root.join("file.txt"), BASE).unwrap();
+        git::git(root, &["add", "file.txt"]).unwrap();
+
+        let mut gutter = loaded_gutter(root, BASE);
+        assert!(gutter.hunks().is_empty());
+        gutter.update("changed\n", 0);
+        assert!(gutter.hunks().is_empty());
+        gutter.update("changed\n", 1);
+        assert!(!gutter.hunks().is_empty());
+    }
+
+    #[test]
+    fn stages_one_hunk_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git::git(root, &["init", "-q"]).unwrap();
+        fs::write(root.join("file.txt"), BASE).unwrap();
+        git::git(root, &["add", "file.txt"]).unwrap();
+
+        let content = "zero\none\nTWO\nthree\nfour\nfive\n";
+        let gutter = loaded_gutter(root, content);
+        assert_eq!(gutter.hunks().len(), 2);
+        let hunk = gutter.hunk_at_line(2).unwrap();
+        gutter.stage(content, hunk, TextEncoding::default(), LineEnding::Lf).unwrap();
+        assert_eq!(staged_text(root), "one\nTWO\nthree\nfour\nfive\n");
+
+        // Hunks that don't fit the buffer or the index any more are refused
+        assert!(gutter.stage("short\n", hunk, TextEncoding::default(), LineEnding::Lf).is_err());
+        let stale = Hunk {
+            kind: ChangeKind::Modified,
+            start: 0,
+            end: 1,
+            base_start: 0,
+            base_end: 1,
+            original: "nope\n".to_string(),
+        };
+        assert!(gutter.stage(content, &stale, TextEncoding::default(), LineEnding::Lf).is_err());
+        assert_eq!(staged_text(root), "one\nTWO\nthree\nfour\nfive\n");
+    }
+}
@@ -0,0 +1,127 @@
+use eframe::egui;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+
+use crate::git::{self, LogEntry};
+
+pub enum HistoryAction {
+    /// Open the file as it was at this commit.
+    Open(usize),
+    /// Diff the file at this commit against the working copy.
+    Compare(usize),
+}
+
+/// The commits that touched one file, newest first. The log is read in the background,
+/// since following renames through a long history can take a while.
+pub struct FileHistory {
+    pub path: PathBuf,
+    pub repo_root: PathBuf,
+    /// `None` until the log has been read.
+    pub entries: Option<Result<Vec<LogEntry>, String>>,
+    pub open: bool,
+    rx: Receiver<Result<Vec<LogEntry>, String>>,
+}
+
+impl FileHistory {
+    pub fn new(ctx: &egui::Context, path: PathBuf, repo_root: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        let (log_root, log_path) = (repo_root.clone(), path.clone());
+        std::thread::spawn(move || {
+            if tx.send(git::file_log(&log_root, &log_path)).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+        Self { path, repo_root, entries: None, open: true, rx }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<HistoryAction> {
+        if let Ok(entries) = self.rx.try_recv() {
+            self.entries = Some(entries);
+        }
+        let mut action = None;
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut open = self.open;
+        egui::Window::new(format!("History of {}", name))
+            .id(egui::Id::new("file_history"))
+            .open(&mut open)
+            .default_size([520.0, 360.0])
+            .show(ctx, |ui| {
+                let entries = match &self.entries {
+                    None => {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.weak("Reading history…");
+                        });
+                        return;
+                    }
+                    Some(Ok(entries)) => entries,
+                    Some(Err(e)) => {
+                        ui.colored_label(ui.visuals().error_fg_color, e.as_str());
+                        return;
+                    }
+                };
+                if entries.is_empty() {
+                    ui.weak("No commits touch this file yet");
+                    return;
+                }
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    for (index, entry) in entries.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.monospace(&entry.short_id).on_hover_text(&entry.id);
+                            ui.weak(&entry.date);
+                            ui.label(&entry.summary).on_hover_text(format!("{}\n{}", entry.author, entry.path.display()));
+                            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                if ui.small_button("Compare").on_hover_text("Compare with Working Copy").clicked() {
+                                    action = Some(HistoryAction::Compare(index));
+                                }
+                                if ui.small_button("Open").on_hover_text("Open this revision read-only").clicked() {
+                                    action = Some(HistoryAction::Open(index));
+                                }
+                            });
+                        });
+                    }
+                });
+            });
+        self.open = open;
+        action
+    }
+}
+
+/// A file as it was at some commit, shown read-only in place of the editor.
+pub struct RevisionView {
+    title: String,
+    text: String,
+}
+
+impl RevisionView {
+    pub fn new(title: String, text: String) -> Self {
+        Self { title, text }
+    }
+
+    /// Shows the revision; returns true when the user closed it.
+    pub fn show(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut closed = false;
+        ui.horizontal(|ui| {
+            ui.strong(&self.title);
+            ui.weak("(read-only)");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("✖").on_hover_text("Close Revision").clicked() {
+                    closed = true;
+                }
+            });
+        });
+        ui.separator();
+        egui::ScrollArea::both()
+            .id_source("revision_view")
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.text.as_str())
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(f32::INFINITY),
+                );
+            });
+        closed
+    }
+}
@@ -0,0 +1,360 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::format::FormatterCommand;
+
+/// How long a language server gets to answer one request, starting up included.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A language server kept running between formats, so only the first one pays for starting
+/// it up. Shut down when dropped.
+pub struct LanguageServer {
+    program: String,
+    child: Child,
+    session: Session,
+}
+
+impl LanguageServer {
+    /// Starts `server` for the files under `root` and checks that it can format documents.
+    pub fn start(server: &FormatterCommand, root: &Path) -> Result<Self, String> {
+        let mut child = Command::new(&server.program)
+            .args(&server.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", server.program, e))?;
+        let session = match Session::new(&mut child) {
+            Ok(session) => session,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{}: {}", server.program, e));
+            }
+        };
+        let mut server = Self { program: server.program.clone(), child, session };
+        server.session.initialize(root).map_err(|e| format!("{}: {}", server.program, e))?;
+        Ok(server)
+    }
+
+    /// Formats `input` as the contents of `path`.
+    pub fn format(&mut self, path: &Path, input: &str) -> Result<String, String> {
+        self.session.format(path, input).map_err(|e| format!("{}: {}", self.program, e))
+    }
+
+    /// False once the server has exited or stopped answering, after which it should be dropped.
+    pub fn is_healthy(&mut self) -> bool {
+        !self.session.stalled && matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl Drop for LanguageServer {
+    fn drop(&mut self) {
+        self.session.shutdown();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A JSON-RPC connection to a language server over its stdin and stdout.
+struct Session {
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    next_id: u64,
+    timeout: Duration,
+    /// Set once a request went unanswered; a late reply may still be on its way.
+    stalled: bool,
+    version: i64,
+}
+
+impl Session {
+    fn new(child: &mut Child) -> Result<Self, String> {
+        let stdin = child.stdin.take().ok_or("No stdin")?;
+        let stdout = child.stdout.take().ok_or("No stdout")?;
+        let (tx, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self { stdin, messages, next_id: 0, timeout: REQUEST_TIMEOUT, stalled: false, version: 0 })
+    }
+
+    fn initialize(&mut self, root: &Path) -> Result<(), String> {
+        let capabilities = self.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": file_uri(root),
+                "capabilities": {},
+            }),
+        )?;
+        let provider = &capabilities["capabilities"]["documentFormattingProvider"];
+        if provider.is_null() || *provider == false {
+            return Err("the server can't format documents".to_string());
+        }
+        self.notify("initialized", json!({}))
+    }
+
+    /// Opens the document, asks for `textDocument/formatting` and closes it again.
+    fn format(&mut self, path: &Path, input: &str) -> Result<String, String> {
+        let uri = file_uri(path);
+        // Versions only ever go up, even across opening and closing
+        self.version += 1;
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": language_id(path),
+                    "version": self.version,
+                    "text": input,
+                }
+            }),
+        )?;
+        let edits = self.request(
+            "textDocument/formatting",
+            json!({
+                "textDocument": { "uri": uri },
+                "options": { "tabSize": 4, "insertSpaces": true },
+            }),
+        );
+        self.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri } }))?;
+        match edits? {
+            Value::Null => Ok(input.to_string()),
+            Value::Array(edits) => apply_text_edits(input, &edits),
+            _ => Err("unexpected formatting response".to_string()),
+        }
+    }
+
+    /// Asks the server to exit, without waiting long for it to agree.
+    fn shutdown(&mut self) {
+        self.timeout = Duration::from_millis(500);
+        if self.request("shutdown", Value::Null).is_ok() {
+            let _ = self.notify("exit", Value::Null);
+        }
+    }
+
+    fn send(&mut self, message: Value) -> Result<(), String> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("failed to write to the server: {}", e))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<(), String> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    /// Sends a request and waits for its result, answering anything the server asks in between.
+    fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let mut message = match self.messages.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.stalled = true;
+                    return Err(format!("no reply to {} in time", method));
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err("the server exited".to_string()),
+            };
+            if let Some(server_method) = message["method"].as_str() {
+                // A request from the server, which may hold up its reply until answered
+                if !message["id"].is_null() {
+                    let result = match server_method {
+                        "workspace/configuration" => {
+                            let items = message["params"]["items"].as_array().map_or(0, Vec::len);
+                            Value::Array(vec![Value::Null; items])
+                        }
+                        _ => Value::Null,
+                    };
+                    self.send(json!({ "jsonrpc": "2.0", "id": message["id"].take(), "result": result }))?;
+                }
+                continue;
+            }
+            if message["id"] != id {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                return Err(error["message"].as_str().unwrap_or("request failed").to_string());
+            }
+            return Ok(message["result"].take());
+        }
+    }
+}
+
+/// One `Content-Length` framed message, or `None` once the stream ends.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let Some(length) = length else { continue };
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        if let Ok(message) = serde_json::from_slice(&body) {
+            return Some(message);
+        }
+    }
+}
+
+/// `text` with LSP `TextEdit`s applied. Positions count UTF-16 code units, as the protocol
+/// does by default.
+fn apply_text_edits(text: &str, edits: &[Value]) -> Result<String, String> {
+    let line_starts: Vec<usize> = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    let offset = |position: &Value| -> Result<usize, String> {
+        let (line, character) = match (position["line"].as_u64(), position["character"].as_u64()) {
+            (Some(line), Some(character)) => (line as usize, character as usize),
+            _ => return Err("malformed edit position".to_string()),
+        };
+        let Some(&start) = line_starts.get(line) else {
+            return Ok(text.len());
+        };
+        let end = line_starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+        let mut units = 0;
+        for (i, c) in text[start..end].char_indices() {
+            if units >= character {
+                return Ok(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Ok(end)
+    };
+    let mut ranges = Vec::with_capacity(edits.len());
+    for edit in edits {
+        let start = offset(&edit["range"]["start"])?;
+        let end = offset(&edit["range"]["end"])?;
+        let new_text = edit["newText"].as_str().ok_or("malformed edit")?;
+        if end < start {
+            return Err("malformed edit range".to_string());
+        }
+        ranges.push((start, end, new_text));
+    }
+    // Back to front so earlier offsets stay valid; edits at the same spot keep their order
+    ranges.sort_by_key(|&(start, _, _)| start);
+    let mut result = text.to_string();
+    let mut limit = text.len();
+    for &(start, end, new_text) in ranges.iter().rev() {
+        if end > limit {
+            return Err("overlapping edits".to_string());
+        }
+        result.replace_range(start..end, new_text);
+        limit = start;
+    }
+    Ok(result)
+}
+
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn language_id(path: &Path) -> String {
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let id = match ext.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" => "javascript",
+        "jsx" => "javascriptreact",
+        "ts" => "typescript",
+        "tsx" => "typescriptreact",
+        "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "md" => "markdown",
+        "yml" => "yaml",
+        other => other,
+    };
+    id.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(start: (u64, u64), end: (u64, u64), text: &str) -> Value {
+        json!({
+            "range": {
+                "start": { "line": start.0, "character": start.1 },
+                "end": { "line": end.0, "character": end.1 },
+            },
+            "newText": text,
+        })
+    }
+
+    #[test]
+    fn applies_edits_in_any_order() {
+        let edits = [edit((1, 0), (1, 0), "    "), edit((0, 9), (0, 9), " ")];
+        assert_eq!(apply_text_edits("fn main(){\nx();\n}\n", &edits).unwrap(), "fn main() {\n    x();\n}\n");
+    }
+
+    #[test]
+    fn inserts_at_the_same_spot_keep_their_order() {
+        let edits = [edit((0, 1), (0, 1), "b"), edit((0, 1), (0, 1), "c")];
+        assert_eq!(apply_text_edits("ad", &edits).unwrap(), "abcd");
+    }
+
+    #[test]
+    fn positions_count_utf16_units() {
+        // The emoji is two UTF-16 units, so character 3 is right after it
+        let edits = [edit((0, 3), (0, 4), "X")];
+        assert_eq!(apply_text_edits("a😀bc", &edits).unwrap(), "a😀Xc");
+    }
+
+    #[test]
+    fn positions_past_the_end_clamp() {
+        let edits = [edit((0, 99), (5, 0), "!")];
+        assert_eq!(apply_text_edits("ab\ncd", &edits).unwrap(), "ab!");
+    }
+
+    #[test]
+    fn overlapping_edits_are_rejected() {
+        let edits = [edit((0, 0), (0, 3), "x"), edit((0, 2), (0, 4), "y")];
+        assert!(apply_text_edits("abcdef", &edits).is_err());
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let stream = b"Content-Length: 8\r\nContent-Type: x\r\n\r\n{\"id\":1}content-length: 2\r\n\r\n{}";
+        let mut reader = &stream[..];
+        assert_eq!(read_message(&mut reader), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut reader), Some(json!({})));
+        assert_eq!(read_message(&mut reader), None);
+    }
+
+    #[test]
+    fn file_uris_are_percent_encoded() {
+        assert_eq!(file_uri(Path::new("/tmp/my file.rs")), "file:///tmp/my%20file.rs");
+    }
+}
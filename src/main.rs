@@ -1,13 +1,58 @@
 use eframe::egui;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{ThemeSet, Style};
 use syntect::parsing::SyntaxSet;
 use syntect::util::LinesWithEndings;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+
+mod ai;
+mod ai_actions;
+mod ai_provider;
+mod blame;
+mod diff_view;
+mod encoding;
+mod file_ops;
+mod file_tree;
+mod file_view;
+mod format;
+mod git;
+mod gutter;
+mod history;
+mod lsp;
+mod merge;
+mod merge_editor;
+mod notifications;
+mod recovery;
+mod save;
+mod source_control;
+mod trash;
+mod watcher;
+mod workspace;
+
+use ai::{AiTasks, AiUpdate, CompletionError};
+use ai_actions::{CodeAction, CodeContext, Proposal, ProposalOutcome};
+use ai_provider::{AIConfig, ProviderKind};
+use blame::Blame;
+use diff_view::DiffView;
+use encoding::{LineEnding, TextEncoding};
+use file_ops::FileOp;
+use file_tree::{EntryKind, FileTree, TreeEntry, TreeFilter, TreeRow};
+use file_view::FileView;
+use merge::Resolution;
+use merge_editor::{MergeEditor, MergeOutcome};
+use format::{FormatJob, FormatTasks, FormatterConfig};
+use git::GitTracker;
+use gutter::{ChangeKind, GitGutter};
+use history::{FileHistory, HistoryAction, RevisionView};
+use notifications::Notifications;
+use recovery::AutosaveMode;
+use source_control::{PanelEvent, SourceControl};
+use watcher::FsWatcher;
+use workspace::{Workspace, WorkspaceSettings, WORKSPACE_EXTENSION};
 
 enum VimMode {
     Normal,
@@ -15,6 +60,77 @@ enum VimMode {
     // Command,
 }
 
+/// Something that would throw away the current buffer, held back until the user
+/// decides what to do with unsaved changes.
+enum PendingAction {
+    NewDocument,
+    /// A new untitled buffer holding this text.
+    NewDocumentWithText(String),
+    OpenFile(PathBuf),
+    OpenFileWithFolder(PathBuf),
+    Reopen(&'static encoding_rs::Encoding),
+    Recover(PathBuf),
+    /// Check out a branch in the repository at the path.
+    SwitchBranch(PathBuf, String),
+    Exit,
+}
+
+/// What to do with a finished AI reply.
+enum AiAction {
+    Insert,
+    ReplaceSelection,
+    NewBuffer,
+}
+
+/// An inline completion shown after the cursor until it is accepted or typed over.
+struct GhostText {
+    /// The character index the suggestion is inserted at.
+    cursor: usize,
+    text: String,
+}
+
+impl GhostText {
+    /// The length in bytes of the suggestion's next word, with the whitespace before it.
+    fn next_word_len(&self) -> usize {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let start = self.text.find(|c: char| !c.is_whitespace()).unwrap_or(self.text.len());
+        let rest = &self.text[start..];
+        let word = if rest.starts_with(is_word) {
+            rest.find(|c: char| !is_word(c)).unwrap_or(rest.len())
+        } else {
+            rest.chars().next().map_or(0, char::len_utf8)
+        };
+        start + word
+    }
+}
+
+fn is_writable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| !meta.permissions().readonly())
+}
+
+/// A format running in the background, and where to save the buffer once it is done if
+/// it was started by a save.
+struct PendingFormat {
+    id: u64,
+    /// The buffer's edit count when the format was asked for; the result is dropped if it has been edited since.
+    edits: u64,
+    save: Option<(PathBuf, TextEncoding)>,
+}
+
+/// Drag-and-drop payload for entries dragged around the file tree.
+struct DraggedPath(PathBuf);
+
+/// How often a dirty buffer is copied to its swap file.
+const SWAP_INTERVAL: Duration = Duration::from_secs(5);
+/// How long autosave waits before trying again after a failed write.
+const AUTOSAVE_RETRY: Duration = Duration::from_secs(30);
+const GUTTER_WIDTH: f32 = 8.0;
+const BLAME_WIDTH: f32 = 220.0;
+/// Ask for an inline completion once typing has paused for this long.
+const COMPLETION_DELAY: Duration = Duration::from_millis(500);
+/// Re-blame edited text only once typing has paused for this long.
+const BLAME_DELAY: Duration = Duration::from_secs(1);
+
 struct SplashScreen {
     show_splash: bool,
 }
@@ -36,18 +152,100 @@ struct TextEditor {
     splash_screen: SplashScreen,
     vim_mode: bool,
     vim_state: VimMode,
-    current_dir: Option<PathBuf>,
+    workspace: Workspace,
+    /// The editor's own settings, kept aside while a workspace's settings are applied.
+    user_settings: Option<WorkspaceSettings>,
     selected_file: Option<PathBuf>,
     expanded_folders: HashMap<PathBuf, bool>,
-    context_menu: Option<(PathBuf, egui::Pos2)>,
     new_item_name: String,
     creating_new_item: Option<bool>,
+    /// Folder the item being created goes into.
+    new_item_parent: Option<PathBuf>,
+    /// Tree entry to scroll into view on the next frame.
+    reveal_path: Option<PathBuf>,
     refresh_tree: bool,
     show_about: bool,
     version: String,
     rust_icon: Option<egui::TextureHandle>,
     ai_config: AIConfig,
     ai_response: Option<String>,
+    ai_error: Option<String>,
+    ai: AiTasks,
+    show_ai_prompt: bool,
+    /// The code action the running AI request is for.
+    code_action: Option<(CodeAction, CodeContext)>,
+    /// A suggested edit shown as a diff in place of the editor.
+    ai_proposal: Option<Proposal>,
+    /// The latest compiler or formatter error, for Fix Diagnostic.
+    diagnostic: String,
+    inline_completion: bool,
+    ghost_text: Option<GhostText>,
+    /// Set by typing; a completion is requested once the typing pauses.
+    completion_wanted: bool,
+    /// The edit count and cursor the running completion was asked for.
+    completion_key: Option<(u64, usize)>,
+    formatter_config: FormatterConfig,
+    format_on_save: bool,
+    format_tasks: FormatTasks,
+    /// The format running in the background, if any.
+    formatting: Option<PendingFormat>,
+    /// What to do once a save waiting on the formatter has gone through.
+    action_after_save: Option<PendingAction>,
+    notifications: Notifications,
+    encoding: TextEncoding,
+    line_ending: LineEnding,
+    view: FileView,
+    dirty: bool,
+    /// Bumped whenever `content` changes, so what is worked out from it can tell it is out of
+    /// date without comparing the text.
+    edits: u64,
+    pending_action: Option<PendingAction>,
+    allow_close: bool,
+    window_title: String,
+    watcher: Option<FsWatcher>,
+    /// The text as it was last read from or written to disk.
+    disk_content: String,
+    /// Newer text found on disk while the buffer had unsaved changes.
+    external_change: Option<String>,
+    autosave: AutosaveMode,
+    autosave_delay_secs: u64,
+    last_edit: Instant,
+    last_swap: Instant,
+    /// When autosave last failed to write the file, so it doesn't retry every frame.
+    autosave_failed_at: Option<Instant>,
+    swap_pending: bool,
+    swap_path: Option<PathBuf>,
+    was_focused: bool,
+    recoverable: Vec<(PathBuf, recovery::SwapFile)>,
+    /// The tree entry being renamed inline, the name typed so far, and whether the box still needs focus.
+    renaming: Option<(PathBuf, String, bool)>,
+    confirm_delete: Option<PathBuf>,
+    /// Set when the platform has no trash for `confirm_delete`, so the dialog asks to delete it for good.
+    delete_permanently: bool,
+    /// A save that couldn't replace the file safely, waiting for leave to overwrite it in place.
+    confirm_overwrite: Option<(PathBuf, TextEncoding)>,
+    /// File tree operations that can be undone, most recent last.
+    file_history: Vec<FileOp>,
+    /// Where the open file was before it was moved to the trash; undoing the delete
+    /// reattaches the buffer to it.
+    trashed_file: Option<PathBuf>,
+    file_tree: FileTree,
+    git: GitTracker,
+    gutter: GitGutter,
+    blame: Blame,
+    /// The Git > File History window.
+    history: Option<FileHistory>,
+    /// A past revision shown read-only in place of the editor.
+    revision_view: Option<RevisionView>,
+    source_control: SourceControl,
+    /// A comparison shown in place of the editor.
+    diff_view: Option<DiffView>,
+    /// File picked with "Select for Compare" in the explorer.
+    compare_base: Option<PathBuf>,
+    merge_editor: Option<MergeEditor>,
+    show_source_control: bool,
+    tree_query: String,
+    exclude_globs_text: String,
 }
 
 impl Default for TextEditor {
@@ -61,33 +259,103 @@ impl Default for TextEditor {
             splash_screen: SplashScreen::default(),
             vim_mode: false,
             vim_state: VimMode::Normal,
-            current_dir: None,
+            workspace: Workspace::default(),
+            user_settings: None,
             selected_file: None,
             expanded_folders: HashMap::new(),
-            context_menu: None,
             new_item_name: String::new(),
             creating_new_item: None,
+            new_item_parent: None,
+            reveal_path: None,
             refresh_tree: false,
             show_about: false,
             version: env!("CARGO_PKG_VERSION").to_string(),
             rust_icon: None,
             ai_config: AIConfig::default(),
             ai_response: None,
+            ai_error: None,
+            ai: AiTasks::new(&egui::Context::default()),
+            show_ai_prompt: false,
+            code_action: None,
+            ai_proposal: None,
+            diagnostic: String::new(),
+            inline_completion: false,
+            ghost_text: None,
+            completion_wanted: false,
+            completion_key: None,
+            formatter_config: FormatterConfig::default(),
+            format_on_save: false,
+            format_tasks: FormatTasks::new(&egui::Context::default()),
+            formatting: None,
+            action_after_save: None,
+            notifications: Notifications::default(),
+            encoding: TextEncoding::default(),
+            line_ending: LineEnding::Lf,
+            view: FileView::Text,
+            dirty: false,
+            edits: 0,
+            pending_action: None,
+            allow_close: false,
+            window_title: String::new(),
+            watcher: None,
+            disk_content: String::new(),
+            external_change: None,
+            autosave: AutosaveMode::Off,
+            autosave_delay_secs: 2,
+            last_edit: Instant::now(),
+            last_swap: Instant::now(),
+            autosave_failed_at: None,
+            swap_pending: false,
+            swap_path: None,
+            was_focused: true,
+            recoverable: Vec::new(),
+            renaming: None,
+            confirm_delete: None,
+            delete_permanently: false,
+            confirm_overwrite: None,
+            file_history: Vec::new(),
+            trashed_file: None,
+            file_tree: FileTree::new(&egui::Context::default()),
+            git: GitTracker::new(&egui::Context::default()),
+            gutter: GitGutter::new(&egui::Context::default()),
+            blame: Blame::new(&egui::Context::default()),
+            history: None,
+            revision_view: None,
+            source_control: SourceControl::new(&egui::Context::default()),
+            diff_view: None,
+            compare_base: None,
+            merge_editor: None,
+            show_source_control: false,
+            tree_query: String::new(),
+            exclude_globs_text: TreeFilter::default().exclude_globs.join("\n"),
         }
     }
 }
 
 impl TextEditor {
     fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let mut editor = Self::default();
+        let mut editor = Self {
+            file_tree: FileTree::new(&cc.egui_ctx),
+            git: GitTracker::new(&cc.egui_ctx),
+            gutter: GitGutter::new(&cc.egui_ctx),
+            blame: Blame::new(&cc.egui_ctx),
+            source_control: SourceControl::new(&cc.egui_ctx),
+            ai: AiTasks::new(&cc.egui_ctx),
+            format_tasks: FormatTasks::new(&cc.egui_ctx),
+            ..Self::default()
+        };
         editor.load_rust_icon(cc);
+        match FsWatcher::new(&cc.egui_ctx) {
+            Ok(watcher) => editor.watcher = Some(watcher),
+            Err(e) => editor.notifications.error(format!("File watching is unavailable: {}", e)),
+        }
+        editor.recoverable = recovery::list_swaps();
         editor
     }
 
     fn load_rust_icon(&mut self, cc: &eframe::CreationContext<'_>) {
         let rust_icon_path = PathBuf::from("Rust.png");
-        if rust_icon_path.exists() {
-            let image = image::open(rust_icon_path).expect("Failed to open Rust.png");
+        if let Ok(image) = image::open(rust_icon_path) {
             let image_buffer = image.to_rgba8();
             let size = [image.width() as _, image.height() as _];
             let image_data = egui::ColorImage::from_rgba_unmultiplied(size, image_buffer.as_flat_samples().as_slice());
@@ -95,8 +363,15 @@ impl TextEditor {
         }
     }
 
+    /// Picks the syntax from the file's name, or from its first line (e.g. a shebang).
     fn detect_language(&mut self) {
-        // Existing code for detecting language
+        let by_path = self
+            .file_path
+            .as_ref()
+            .and_then(|path| self.syntax_set.find_syntax_for_file(path).ok().flatten());
+        let first_line = self.content.lines().next().unwrap_or_default();
+        let syntax = by_path.or_else(|| self.syntax_set.find_syntax_by_first_line(first_line));
+        self.current_syntax = syntax.map(|syntax| syntax.name.clone());
     }
 
     fn highlight_content(&self) -> Vec<(Style, String)> {
@@ -124,158 +399,1378 @@ impl TextEditor {
             if ui.button("🔄").clicked() {
                 self.refresh_tree = true;
             }
+            ui.menu_button("⚙", |ui| {
+                let mut filter = self.file_tree.filter().clone();
+                ui.checkbox(&mut filter.show_hidden, "Show Hidden Files");
+                ui.checkbox(&mut filter.respect_ignore_files, "Respect .gitignore");
+                ui.label("Exclude (one glob per line):");
+                if ui.text_edit_multiline(&mut self.exclude_globs_text).changed() {
+                    filter.exclude_globs = self
+                        .exclude_globs_text
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                self.file_tree.set_filter(filter);
+            });
         });
+        ui.add(egui::TextEdit::singleline(&mut self.tree_query).hint_text("Filter files").desired_width(f32::INFINITY));
 
         if self.refresh_tree {
-            self.expanded_folders.clear();
+            self.file_tree.invalidate_all();
+            self.git.refresh();
             self.refresh_tree = false;
         }
 
-        if let Some(dir) = &self.current_dir.clone() {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                self.show_folder_contents(ui, dir, 0);
-            });
+        self.file_tree.poll();
+        if !self.workspace.is_empty() {
+            let roots: Vec<(PathBuf, String)> =
+                self.workspace.folders.iter().map(|f| (f.path.clone(), f.display_name())).collect();
+            self.file_tree.set_roots(&roots);
+            self.file_tree.set_query(&self.tree_query);
+            let expanded = &self.expanded_folders;
+            let rows = self.file_tree.visible_rows(&|path| *expanded.get(path).unwrap_or(&false));
+            let row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+            let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false, false]);
+            if let Some(reveal) = &self.reveal_path {
+                let index = rows
+                    .iter()
+                    .position(|row| matches!(row, TreeRow::Entry { entry, .. } if entry.path == *reveal));
+                // The row may not be listed yet while its folder loads; try again next frame
+                if let Some(index) = index {
+                    let offset = (index as f32 * row_height - ui.available_height() / 2.0).max(0.0);
+                    scroll_area = scroll_area.vertical_scroll_offset(offset);
+                    self.reveal_path = None;
+                }
+            }
+            scroll_area
+                .show_rows(ui, row_height, rows.len(), |ui, range| {
+                    for row in &rows[range] {
+                        self.show_tree_row(ui, row);
+                    }
+                });
         }
+    }
 
-        // Handle context menu
-        if let Some((path, pos)) = self.context_menu.take() {
-            egui::Area::new("context_menu")
-                .fixed_pos(pos)
-                .show(ui.ctx(), |ui| {
-                    egui::Frame::popup(ui.style()).show(ui, |ui| {
-                        ui.set_min_width(150.0);
-                        if ui.button("New File").clicked() {
-                            self.creating_new_item = Some(true);
-                            self.new_item_name.clear();
-                            ui.close_menu();
+    fn show_tree_row(&mut self, ui: &mut egui::Ui, row: &TreeRow) {
+        let (depth, entry) = match row {
+            TreeRow::Entry { depth, entry } => (*depth, entry),
+            TreeRow::Root(entry) => {
+                self.show_tree_root(ui, entry);
+                return;
+            }
+            TreeRow::Loading { depth } => {
+                ui.horizontal(|ui| {
+                    ui.add_space((depth * 20) as f32);
+                    ui.spinner();
+                    ui.weak("Loading…");
+                });
+                return;
+            }
+            TreeRow::Error { depth, message } => {
+                Self::show_tree_error(ui, *depth, message, None);
+                return;
+            }
+        };
+        if let EntryKind::Broken(error) = &entry.kind {
+            Self::show_tree_error(ui, depth, error, Some(&entry.name));
+            return;
+        }
+        let path = &entry.path;
+        let is_dir = entry.is_dir();
+
+        ui.horizontal(|ui| {
+            ui.add_space((depth * 20) as f32);
+            let is_rust_file = path.extension().is_some_and(|ext| ext == "rs");
+
+            // Add icon
+            if is_dir {
+                ui.label(if *self.expanded_folders.get(path).unwrap_or(&false) { "▼" } else { "▶" });
+            } else if is_rust_file && self.rust_icon.is_some() {
+                let rust_icon = self.rust_icon.as_ref().unwrap();
+                let size = ui.text_style_height(&egui::TextStyle::Body);
+                ui.add(egui::Image::new(rust_icon).fit_to_exact_size(egui::vec2(size, size)));
+            } else {
+                ui.label("  "); // Spacer for other file types
+            }
+
+            if self.renaming.as_ref().is_some_and(|(p, _, _)| p == path) {
+                self.show_rename_box(ui);
+                return;
+            }
+
+            // Add button with file/folder name
+            let is_selected = self.selected_file.as_ref() == Some(path);
+            let is_dirty = self.dirty && self.file_path.as_ref() == Some(path);
+            let label = if is_dirty { format!("{} *", entry.name) } else { entry.name.clone() };
+            let git_status = self.git.status_of(path);
+            let mut text = egui::RichText::new(label);
+            if let Some(status) = git_status {
+                text = text.color(status.color(ui.visuals()));
+            }
+            let mut response = ui
+                .add(egui::SelectableLabel::new(is_selected, text))
+                .interact(egui::Sense::drag());
+            if let Some(status) = git_status {
+                response = response.on_hover_text(status.label());
+            }
+            if response.clicked() {
+                self.selected_file = Some(path.clone());
+                if is_dir {
+                    let is_expanded = self.expanded_folders.entry(path.clone()).or_insert(false);
+                    *is_expanded = !*is_expanded;
+                } else if self.file_path.as_ref() != Some(path) {
+                    self.request_action(ui.ctx(), PendingAction::OpenFile(path.clone()));
+                }
+            }
+
+            // Drag entries onto a folder, or onto a file to drop next to it
+            response.dnd_set_drag_payload(DraggedPath(path.clone()));
+            let target_dir = if is_dir { path.clone() } else { path.parent().unwrap_or(path).to_path_buf() };
+            if response.dnd_hover_payload::<DraggedPath>().is_some() {
+                ui.painter().rect_stroke(response.rect, 2.0, ui.visuals().selection.stroke);
+            }
+            if let Some(dragged) = response.dnd_release_payload::<DraggedPath>() {
+                if dragged.0.parent() != Some(target_dir.as_path()) {
+                    match file_ops::move_into(&dragged.0, &target_dir) {
+                        Ok(to) => {
+                            self.path_moved(&dragged.0, &to);
+                            self.file_history.push(FileOp::Moved { from: dragged.0.to_path_buf(), to });
                         }
-                        if ui.button("New Folder").clicked() {
-                            self.creating_new_item = Some(false);
-                            self.new_item_name.clear();
-                            ui.close_menu();
+                        Err(e) => self.notifications.error(format!("Failed to move {}: {}", dragged.0.display(), e)),
+                    }
+                }
+            }
+
+            response.context_menu(|ui| self.show_tree_context_menu(ui, path));
+        });
+    }
+
+    fn show_tree_root(&mut self, ui: &mut egui::Ui, entry: &TreeEntry) {
+        let path = &entry.path;
+        ui.horizontal(|ui| {
+            let expanded = *self.expanded_folders.get(path).unwrap_or(&false);
+            ui.label(if expanded { "▼" } else { "▶" });
+            let is_selected = self.selected_file.as_ref() == Some(path);
+            let response = ui
+                .add(egui::SelectableLabel::new(is_selected, egui::RichText::new(&entry.name).strong()))
+                .on_hover_text(path.display().to_string());
+            if response.clicked() {
+                self.selected_file = Some(path.clone());
+                self.expanded_folders.insert(path.clone(), !expanded);
+            }
+
+            if response.dnd_hover_payload::<DraggedPath>().is_some() {
+                ui.painter().rect_stroke(response.rect, 2.0, ui.visuals().selection.stroke);
+            }
+            if let Some(dragged) = response.dnd_release_payload::<DraggedPath>() {
+                if dragged.0.parent() != Some(path.as_path()) {
+                    match file_ops::move_into(&dragged.0, path) {
+                        Ok(to) => {
+                            self.path_moved(&dragged.0, &to);
+                            self.file_history.push(FileOp::Moved { from: dragged.0.to_path_buf(), to });
                         }
-                        if path.is_file() {
-                            if ui.button("Delete").clicked() {
-                                if let Err(e) = fs::remove_file(&path) {
-                                    eprintln!("Failed to delete file: {}", e);
-                                }
-                                ui.close_menu();
+                        Err(e) => self.notifications.error(format!("Failed to move {}: {}", dragged.0.display(), e)),
+                    }
+                }
+            }
+
+            response.context_menu(|ui| {
+                ui.set_min_width(150.0);
+                if ui.button("New File").clicked() {
+                    self.start_new_item(true, Some(path.clone()));
+                    ui.close_menu();
+                }
+                if ui.button("New Folder").clicked() {
+                    self.start_new_item(false, Some(path.clone()));
+                    ui.close_menu();
+                }
+                ui.separator();
+                if ui.button("Copy Path").clicked() {
+                    ui.ctx().output_mut(|o| o.copied_text = path.display().to_string());
+                    ui.close_menu();
+                }
+                if ui.button("Reveal in File Manager").clicked() {
+                    if let Err(e) = file_ops::reveal(path) {
+                        self.notifications.error(format!("Failed to open file manager: {}", e));
+                    }
+                    ui.close_menu();
+                }
+                ui.separator();
+                if ui.button("Remove Folder from Workspace").clicked() {
+                    self.workspace.remove_folder(path);
+                    self.persist_workspace_folders();
+                    ui.close_menu();
+                }
+            });
+        });
+    }
+
+    fn show_tree_context_menu(&mut self, ui: &mut egui::Ui, path: &PathBuf) {
+        ui.set_min_width(150.0);
+        let folder = if path.is_dir() { path.clone() } else { path.parent().unwrap_or(path).to_path_buf() };
+        if ui.button("New File").clicked() {
+            self.start_new_item(true, Some(folder.clone()));
+            ui.close_menu();
+        }
+        if ui.button("New Folder").clicked() {
+            self.start_new_item(false, Some(folder));
+            ui.close_menu();
+        }
+        ui.separator();
+        if ui.button("Rename").clicked() {
+            self.start_rename(path);
+            ui.close_menu();
+        }
+        if ui.button("Duplicate").clicked() {
+            match file_ops::duplicate(path) {
+                Ok(copy) => {
+                    self.file_tree.invalidate(&copy);
+                    self.file_history.push(FileOp::Created(copy));
+                }
+                Err(e) => self.notifications.error(format!("Failed to duplicate {}: {}", path.display(), e)),
+            }
+            ui.close_menu();
+        }
+        if ui.button("Delete").clicked() {
+            self.confirm_delete = Some(path.clone());
+            self.delete_permanently = false;
+            ui.close_menu();
+        }
+        if !path.is_dir() {
+            ui.separator();
+            if ui.button("Select for Compare").clicked() {
+                self.compare_base = Some(path.clone());
+                ui.close_menu();
+            }
+            if let Some(base) = self.compare_base.clone().filter(|base| base != path) {
+                let name = base.file_name().unwrap_or_default().to_string_lossy();
+                if ui.button(format!("Compare with '{}'", name)).clicked() {
+                    self.compare_files(&base, path);
+                    ui.close_menu();
+                }
+            }
+        }
+        ui.separator();
+        if ui.button("Copy Path").clicked() {
+            ui.ctx().output_mut(|o| o.copied_text = path.display().to_string());
+            ui.close_menu();
+        }
+        if ui.button("Copy Relative Path").clicked() {
+            let relative = self
+                .workspace
+                .root_of(path)
+                .and_then(|root| path.strip_prefix(root).ok())
+                .unwrap_or(path);
+            ui.ctx().output_mut(|o| o.copied_text = relative.display().to_string());
+            ui.close_menu();
+        }
+        if ui.button("Reveal in File Manager").clicked() {
+            if let Err(e) = file_ops::reveal(path) {
+                self.notifications.error(format!("Failed to open file manager: {}", e));
+            }
+            ui.close_menu();
+        }
+    }
+
+    fn start_rename(&mut self, path: &Path) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        self.renaming = Some((path.to_path_buf(), name, true));
+    }
+
+    /// The inline name editor shown in place of the entry being renamed.
+    fn show_rename_box(&mut self, ui: &mut egui::Ui) {
+        let Some((path, mut name, first_frame)) = self.renaming.take() else {
+            return;
+        };
+        let response = ui.text_edit_singleline(&mut name);
+        if first_frame {
+            response.request_focus();
+        }
+        let original = path.file_name().unwrap_or_default().to_string_lossy();
+        let validated = file_ops::validate_rename(&path, &name);
+        // Only complain once the name has been changed
+        if let (Err(e), true) = (&validated, name.trim() != original) {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+        if !response.lost_focus() {
+            self.renaming = Some((path, name, false));
+            return;
+        }
+        // Enter commits; Escape or clicking elsewhere cancels
+        if !ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            return;
+        }
+        let to = match validated {
+            Ok(to) => to,
+            Err(_) => {
+                // Keep editing so the name can be fixed
+                self.renaming = Some((path, name, true));
+                return;
+            }
+        };
+        if to == path {
+            return;
+        }
+        match file_ops::rename(&path, &to) {
+            Ok(()) => {
+                self.path_moved(&path, &to);
+                self.file_history.push(FileOp::Moved { from: path, to });
+            }
+            Err(e) => self.notifications.error(format!("Failed to rename {}: {}", path.display(), e)),
+        }
+    }
+
+    fn show_delete_dialog(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.confirm_delete.clone() else {
+            return;
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        egui::Window::new("Delete")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(match (self.delete_permanently, path.is_dir()) {
+                    (true, true) => format!("The folder '{}' can't be moved to the trash. Delete it and everything in it permanently? This can't be undone.", name),
+                    (true, false) => format!("'{}' can't be moved to the trash. Delete it permanently? This can't be undone.", name),
+                    (false, true) => format!("Move the folder '{}' and everything in it to the trash?", name),
+                    (false, false) => format!("Move '{}' to the trash?", name),
+                });
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if self.delete_permanently && ui.button("Delete Permanently").clicked() {
+                        match file_ops::remove_recursive(&path) {
+                            Ok(()) => {
+                                self.notifications.info(format!("Deleted '{}'", name));
+                                self.path_trashed(&path);
+                            }
+                            Err(e) => self.notifications.error(format!("Failed to delete {}: {}", path.display(), e)),
+                        }
+                        self.confirm_delete = None;
+                    }
+                    if !self.delete_permanently && ui.button("Move to Trash").clicked() {
+                        match trash::move_to_trash(&path) {
+                            Ok(item) => {
+                                self.notifications.info(format!("Moved '{}' to the trash", name));
+                                self.path_trashed(&path);
+                                self.file_history.push(FileOp::Trashed(item));
+                                self.confirm_delete = None;
                             }
-                        } else if path.is_dir() {
-                            if ui.button("Delete").clicked() {
-                                if let Err(e) = fs::remove_dir_all(&path) {
-                                    eprintln!("Failed to delete directory: {}", e);
-                                }
-                                ui.close_menu();
+                            // Only after asking again, since there is no getting it back
+                            Err(e) if e.kind() == io::ErrorKind::Unsupported => self.delete_permanently = true,
+                            Err(e) => {
+                                self.notifications.error(format!("Failed to delete {}: {}", path.display(), e));
+                                self.confirm_delete = None;
                             }
                         }
-                    });
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_delete = None;
+                    }
                 });
+            });
+    }
+
+    fn undo_file_op(&mut self) {
+        let Some(op) = self.file_history.pop() else {
+            return;
+        };
+        let result = match &op {
+            FileOp::Trashed(item) => item.restore().map(|_| self.path_restored(&item.original)),
+            FileOp::Moved { from, to } => file_ops::rename(to, from).map(|_| self.path_moved(to, from)),
+            FileOp::Created(path) => trash::move_to_trash(path).map(|_| self.path_trashed(path)),
+        };
+        if let Err(e) = result {
+            self.notifications.error(format!("Failed to undo {}: {}", op.description().to_lowercase(), e));
         }
+    }
 
-        // Handle new item creation
-        if let Some(is_file) = self.creating_new_item {
-            self.show_new_item_dialog(ui.ctx(), is_file);
+    /// Points everything that referred to `from`, or to something inside it, at `to`.
+    fn path_moved(&mut self, from: &Path, to: &Path) {
+        let remap = |path: &PathBuf| path.strip_prefix(from).ok().map(|rest| to.join(rest));
+        if let Some(path) = self.file_path.as_ref().and_then(remap) {
+            self.file_path = Some(path);
         }
+        if let Some(path) = self.selected_file.as_ref().and_then(remap) {
+            self.selected_file = Some(path);
+        }
+        self.expanded_folders = self
+            .expanded_folders
+            .drain()
+            .map(|(path, expanded)| (remap(&path).unwrap_or(path), expanded))
+            .collect();
+        self.file_tree.invalidate(from);
+        self.file_tree.invalidate(to);
     }
 
-    fn show_folder_contents(&mut self, ui: &mut egui::Ui, path: &PathBuf, depth: usize) {
-        let entries = fs::read_dir(path).unwrap_or_else(|_| panic!("Failed to read directory"));
-        for entry in entries.filter_map(|e| e.ok()) {
-            let path = entry.path();
-            let name = path.file_name().unwrap_or_default().to_string_lossy();
-            let is_dir = path.is_dir();
+    /// Detaches the buffer from its file if `path` was it, or a folder holding it, so saving
+    /// can't quietly recreate it. The text stays open as unsaved changes.
+    fn path_trashed(&mut self, path: &Path) {
+        self.file_tree.invalidate(path);
+        if self.file_path.as_ref().is_some_and(|p| p.starts_with(path)) {
+            self.trashed_file = self.file_path.take();
+            self.external_change = None;
+            self.dirty = true;
+        }
+    }
 
-            ui.horizontal(|ui| {
-                ui.add_space((depth * 20) as f32);
-                let is_rust_file = path.extension().map_or(false, |ext| ext == "rs");
+    /// Reattaches the buffer to its file when the delete that detached it is undone.
+    fn path_restored(&mut self, path: &Path) {
+        self.file_tree.invalidate(path);
+        if self.file_path.is_none() && self.trashed_file.as_ref().is_some_and(|p| p.starts_with(path)) {
+            self.file_path = self.trashed_file.take();
+            self.dirty = self.content != self.disk_content;
+        }
+    }
 
-                // Add icon
-                if is_dir {
-                    ui.label(if *self.expanded_folders.get(&path).unwrap_or(&false) { "▼" } else { "▶" });
-                } else if is_rust_file && self.rust_icon.is_some() {
-                    let rust_icon = self.rust_icon.as_ref().unwrap();
-                    ui.image(rust_icon);
-                } else {
-                    ui.label("  "); // Spacer for other file types
+    /// An entry the tree couldn't read, shown in place instead of the entry itself.
+    fn show_tree_error(ui: &mut egui::Ui, depth: usize, error: &str, name: Option<&str>) {
+        ui.horizontal(|ui| {
+            ui.add_space((depth * 20) as f32);
+            let color = ui.visuals().error_fg_color;
+            ui.colored_label(color, "⚠");
+            let text = match name {
+                Some(name) => egui::RichText::new(name).color(color),
+                None => egui::RichText::new(error).color(color).italics(),
+            };
+            ui.label(text).on_hover_text(error);
+        });
+    }
+
+    fn load_file(&mut self, ctx: &egui::Context, path: &PathBuf) -> bool {
+        let view = match self.open_view(ctx, path) {
+            Ok(view) => view,
+            Err(e) => {
+                self.notifications.error(format!("Failed to open {}: {}", path.display(), e));
+                return false;
+            }
+        };
+        self.selected_file = Some(path.clone());
+        self.file_path = Some(path.clone());
+        self.trashed_file = None;
+        self.current_syntax = None;
+        // A format or save started for the previous file no longer applies
+        self.formatting = None;
+        if view.is_text() {
+            let bytes = match fs::read(path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    self.notifications.error(format!("Failed to open {}: {}", path.display(), e));
+                    return false;
                 }
+            };
+            self.set_decoded(encoding::decode(&bytes));
+            self.detect_language();
+            self.highlight_content(); // Ensure this is called
+        } else {
+            self.content.clear();
+            self.disk_content.clear();
+            self.edits += 1;
+        }
+        self.view = view;
+        self.external_change = None;
+        self.dirty = false;
+        true
+    }
 
-                // Add button with file/folder name
-                let is_selected = self.selected_file.as_ref() == Some(&path);
-                if ui.add(egui::SelectableLabel::new(is_selected, name.to_string())).clicked() {
-                    if is_dir {
-                        let is_expanded = self.expanded_folders.entry(path.clone()).or_insert(false);
-                        *is_expanded = !*is_expanded;
-                    } else {
-                        self.load_file(&path);
+    /// Decides how to show `path`: images and binaries get viewers, huge files are opened lazily.
+    fn open_view(&mut self, ctx: &egui::Context, path: &PathBuf) -> Result<FileView, String> {
+        if let Some(image) = file_view::open_image(ctx, path) {
+            match image {
+                Ok(image) => return Ok(FileView::Image(image)),
+                // Not actually an image despite the extension; fall through to the other checks
+                Err(e) => self.notifications.error(format!("Could not decode image: {}", e)),
+            }
+        }
+        let len = fs::metadata(path).map_err(|e| e.to_string())?.len();
+        if file_view::looks_binary(path).map_err(|e| e.to_string())? {
+            return file_view::HexView::open(path).map(FileView::Hex).map_err(|e| e.to_string());
+        }
+        if len > file_view::LARGE_FILE_THRESHOLD {
+            return file_view::LargeFileView::open(ctx, path).map(FileView::Large).map_err(|e| e.to_string());
+        }
+        Ok(FileView::Text)
+    }
+
+    fn set_decoded(&mut self, decoded: encoding::DecodedText) {
+        if decoded.had_errors {
+            self.notifications.error(format!(
+                "Some bytes are not valid {}; saving will replace them",
+                decoded.encoding.encoding.name()
+            ));
+        }
+        self.content = decoded.text;
+        self.edits += 1;
+        self.disk_content = self.content.clone();
+        self.encoding = decoded.encoding;
+        self.line_ending = decoded.line_ending;
+    }
+
+    fn reopen_with_encoding(&mut self, encoding: &'static encoding_rs::Encoding) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        if !self.view.is_text() {
+            return;
+        }
+        match fs::read(&path) {
+            Ok(bytes) => {
+                self.set_decoded(encoding::decode_with(&bytes, encoding));
+                self.dirty = false;
+            }
+            Err(e) => self.notifications.error(format!("Failed to reopen {}: {}", path.display(), e)),
+        }
+    }
+
+    fn new_document(&mut self) {
+        self.content = String::new();
+        self.edits += 1;
+        self.disk_content = String::new();
+        self.external_change = None;
+        self.file_path = None;
+        self.trashed_file = None;
+        self.current_syntax = None;
+        self.formatting = None;
+        self.encoding = TextEncoding::default();
+        self.line_ending = LineEnding::Lf;
+        self.view = FileView::Text;
+        self.dirty = false;
+    }
+
+    /// Runs `action` right away, or asks what to do with unsaved changes first.
+    fn request_action(&mut self, ctx: &egui::Context, action: PendingAction) {
+        if self.dirty {
+            self.pending_action = Some(action);
+        } else {
+            self.perform_action(ctx, action);
+        }
+    }
+
+    fn perform_action(&mut self, ctx: &egui::Context, action: PendingAction) {
+        match action {
+            PendingAction::NewDocument => self.new_document(),
+            PendingAction::NewDocumentWithText(text) => {
+                self.new_document();
+                self.content = text;
+                self.edits += 1;
+                self.dirty = true;
+                self.swap_pending = true;
+            }
+            PendingAction::OpenFile(path) => {
+                self.load_file(ctx, &path);
+            }
+            PendingAction::OpenFileWithFolder(path) => {
+                // Keep the workspace if the file is part of it, otherwise show the file's folder
+                if self.load_file(ctx, &path) && self.workspace.root_of(&path).is_none() {
+                    if let Some(dir) = path.parent() {
+                        self.open_workspace(Workspace::single(dir.to_path_buf()));
                     }
                 }
+            }
+            PendingAction::Reopen(encoding) => self.reopen_with_encoding(encoding),
+            PendingAction::Recover(swap_path) => self.recover(ctx, &swap_path),
+            PendingAction::SwitchBranch(repo, branch) => self.switch_branch(ctx, &repo, &branch),
+            PendingAction::Exit => {
+                self.remove_swap();
+                self.allow_close = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    fn switch_branch(&mut self, ctx: &egui::Context, repo: &Path, branch: &str) {
+        if let Err(e) = git::switch_branch(repo, branch) {
+            self.notifications.error(format!("Failed to switch branch: {}", e));
+            return;
+        }
+        self.git.refresh();
+        // Show the file as it is on the new branch, dropping any discarded edits
+        if let Some(path) = self.file_path.clone().filter(|p| p.starts_with(repo) && p.exists()) {
+            self.load_file(ctx, &path);
+        }
+    }
+
+    fn recover(&mut self, ctx: &egui::Context, swap_path: &PathBuf) {
+        let swap = match recovery::read_swap(swap_path) {
+            Ok(swap) => swap,
+            Err(e) => {
+                self.notifications.error(format!("Failed to read recovery file: {}", e));
+                return;
+            }
+        };
+        match &swap.original_path {
+            Some(path) if path.exists() => {
+                if !self.load_file(ctx, path) {
+                    return;
+                }
+                self.replace_content(ctx, &swap.content);
+            }
+            original => {
+                self.new_document();
+                self.file_path = original.clone();
+                self.content = swap.content;
+                self.edits += 1;
+            }
+        }
+        self.dirty = true;
+        self.swap_pending = true;
+        self.splash_screen.show_splash = false;
+        let _ = fs::remove_file(swap_path);
+        self.recoverable.retain(|(p, _)| p != swap_path);
+    }
+
+    fn show_recovery_dialog(&mut self, ctx: &egui::Context) {
+        if self.recoverable.is_empty() {
+            return;
+        }
+        let mut restore = None;
+        let mut discard = None;
+        let mut discard_all = false;
+        egui::Window::new("Recover Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Hydroxite didn't exit cleanly last time. These buffers had unsaved changes:");
+                ui.add_space(5.0);
+                egui::Grid::new("recoverable_buffers").striped(true).show(ui, |ui| {
+                    for (swap_path, swap) in &self.recoverable {
+                        ui.label(swap.title());
+                        ui.label(swap.age());
+                        if ui.button("Restore").clicked() {
+                            restore = Some(swap_path.clone());
+                        }
+                        if ui.button("Discard").clicked() {
+                            discard = Some(swap_path.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.add_space(5.0);
+                if ui.button("Discard All").clicked() {
+                    discard_all = true;
+                }
             });
 
-            if is_dir && *self.expanded_folders.get(&path).unwrap_or(&false) {
-                self.show_folder_contents(ui, &path, depth + 1);
+        if let Some(swap_path) = restore {
+            self.request_action(ctx, PendingAction::Recover(swap_path));
+        }
+        if let Some(swap_path) = discard {
+            recovery::discard_swap(&swap_path);
+            self.recoverable.retain(|(p, _)| *p != swap_path);
+        }
+        if discard_all {
+            for (swap_path, _) in self.recoverable.drain(..) {
+                recovery::discard_swap(&swap_path);
             }
         }
     }
 
-    fn load_file(&mut self, path: &PathBuf) {
-        self.selected_file = Some(path.clone());
-        self.file_path = Some(path.clone());
-        self.content = fs::read_to_string(path).unwrap_or_else(|_| String::new());
-        self.detect_language();
-        self.highlight_content(); // Ensure this is called
+    /// Keeps the swap file in step with the buffer and runs the configured autosave.
+    fn tick_autosave(&mut self, ctx: &egui::Context) {
+        if !self.dirty {
+            self.remove_swap();
+            self.was_focused = ctx.input(|i| i.focused);
+            return;
+        }
+
+        let focused = ctx.input(|i| i.focused);
+        let can_autosave = self.file_path.is_some() && self.view.is_text() && self.external_change.is_none();
+        let autosave_due = match self.autosave {
+            AutosaveMode::Off => false,
+            AutosaveMode::OnFocusLost => self.was_focused && !focused,
+            AutosaveMode::AfterDelay => self.last_edit.elapsed() >= Duration::from_secs(self.autosave_delay_secs),
+        };
+        self.was_focused = focused;
+        let backing_off = self.autosave_failed_at.is_some_and(|at| at.elapsed() < AUTOSAVE_RETRY);
+        if can_autosave && autosave_due && !backing_off {
+            if let Some(path) = self.file_path.clone() {
+                if self.write_file(&path, self.encoding) {
+                    return;
+                }
+                // Keep the recovery file going below; a buffer that can't be saved needs it most
+                self.autosave_failed_at = Some(Instant::now());
+            }
+        }
+
+        if self.swap_pending && self.last_swap.elapsed() >= SWAP_INTERVAL {
+            self.write_swap();
+        }
+        // Wake up again so the timers above fire without further input
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+
+    fn write_swap(&mut self) {
+        let swap_path = recovery::swap_path_for(self.file_path.as_deref());
+        if swap_path != self.swap_path {
+            self.remove_swap();
+        }
+        let Some(swap_path) = swap_path else {
+            return;
+        };
+        let swap = recovery::SwapFile::new(self.file_path.clone(), self.content.clone());
+        if let Err(e) = recovery::write_swap(&swap_path, &swap) {
+            self.notifications.error(format!("Failed to write recovery file: {}", e));
+        }
+        self.swap_path = Some(swap_path);
+        self.swap_pending = false;
+        self.last_swap = Instant::now();
+    }
+
+    fn remove_swap(&mut self) {
+        if let Some(swap_path) = self.swap_path.take() {
+            let _ = fs::remove_file(swap_path);
+        }
+        self.swap_pending = false;
+    }
+
+    fn show_unsaved_changes_dialog(&mut self, ctx: &egui::Context) {
+        if self.pending_action.is_none() {
+            return;
+        }
+        let name = self
+            .file_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map_or_else(|| "Untitled".to_string(), |n| n.to_string_lossy().to_string());
+
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("Do you want to save the changes you made to {}?", name));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.save(ctx);
+                        if self.saving() {
+                            self.action_after_save = self.pending_action.take();
+                        } else if let Some(action) = self.pending_action.take().filter(|_| !self.dirty) {
+                            // A failed or cancelled save keeps the buffer and drops the action
+                            self.perform_action(ctx, action);
+                        }
+                    }
+                    if ui.button("Discard").clicked() {
+                        self.dirty = false;
+                        if let Some(action) = self.pending_action.take() {
+                            self.perform_action(ctx, action);
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.pending_action = None;
+                    }
+                });
+            });
+    }
+
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        let name = self
+            .file_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map_or_else(|| "Untitled".to_string(), |n| n.to_string_lossy().to_string());
+        let workspace = self.workspace.title().map(|t| format!(" - {}", t)).unwrap_or_default();
+        let title = format!("{}{}{} - Hydroxite", name, if self.dirty { " *" } else { "" }, workspace);
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    fn workspace_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("Workspace", |ui| {
+            if ui.button("Open Workspace...").clicked() {
+                let picked = rfd::FileDialog::new()
+                    .add_filter("Hydroxite Workspace", &[WORKSPACE_EXTENSION])
+                    .pick_file();
+                if let Some(path) = picked {
+                    match Workspace::load(&path) {
+                        Ok(workspace) => self.open_workspace(workspace),
+                        Err(e) => self.notifications.error(format!("Failed to open {}: {}", path.display(), e)),
+                    }
+                }
+                ui.close_menu();
+            }
+            if ui.button("Add Folder to Workspace...").clicked() {
+                if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                    if self.workspace.add_folder(path.clone()) {
+                        self.expanded_folders.insert(path, true);
+                        self.persist_workspace_folders();
+                    }
+                }
+                ui.close_menu();
+            }
+            ui.separator();
+            let has_file = self.workspace.file.is_some();
+            if ui.add_enabled(!self.workspace.is_empty(), egui::Button::new("Save Workspace")).clicked() {
+                self.save_workspace(!has_file);
+                ui.close_menu();
+            }
+            if ui.add_enabled(!self.workspace.is_empty(), egui::Button::new("Save Workspace As...")).clicked() {
+                self.save_workspace(true);
+                ui.close_menu();
+            }
+            if ui.add_enabled(!self.workspace.is_empty(), egui::Button::new("Close Workspace")).clicked() {
+                self.open_workspace(Workspace::default());
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// Switches the explorer to `workspace` and applies its settings over the editor's own.
+    fn open_workspace(&mut self, workspace: Workspace) {
+        if let Some(settings) = self.user_settings.take() {
+            self.apply_settings(&settings);
+        }
+        if workspace.file.is_some() {
+            self.user_settings = Some(self.current_settings());
+            self.apply_settings(&workspace.settings);
+        }
+        self.expanded_folders.clear();
+        for root in workspace.roots() {
+            self.expanded_folders.insert(root.to_path_buf(), true);
+        }
+        self.workspace = workspace;
+    }
+
+    fn current_settings(&self) -> WorkspaceSettings {
+        WorkspaceSettings {
+            tree_filter: Some(self.file_tree.filter().clone()),
+            format_on_save: Some(self.format_on_save),
+            formatters: Some(self.formatter_config.clone()),
+            autosave: Some(self.autosave),
+            autosave_delay_secs: Some(self.autosave_delay_secs),
+        }
+    }
+
+    fn apply_settings(&mut self, settings: &WorkspaceSettings) {
+        if let Some(filter) = &settings.tree_filter {
+            self.exclude_globs_text = filter.exclude_globs.join("\n");
+            self.file_tree.set_filter(filter.clone());
+        }
+        if let Some(format_on_save) = settings.format_on_save {
+            self.format_on_save = format_on_save;
+        }
+        if let Some(formatters) = &settings.formatters {
+            self.formatter_config = formatters.clone();
+        }
+        if let Some(autosave) = settings.autosave {
+            self.autosave = autosave;
+        }
+        if let Some(delay) = settings.autosave_delay_secs {
+            self.autosave_delay_secs = delay;
+        }
+    }
+
+    /// Saves the folders and the current settings to the workspace file, asking where
+    /// first if `choose_path` is set.
+    fn save_workspace(&mut self, choose_path: bool) {
+        let path = match &self.workspace.file {
+            Some(path) if !choose_path => path.clone(),
+            _ => {
+                let mut dialog = rfd::FileDialog::new().add_filter("Hydroxite Workspace", &[WORKSPACE_EXTENSION]);
+                if let Some(root) = self.workspace.first_root() {
+                    let name = self.workspace.title().unwrap_or_else(|| "workspace".to_string());
+                    dialog = dialog
+                        .set_directory(root.parent().unwrap_or(&root))
+                        .set_file_name(format!("{}.{}", name, WORKSPACE_EXTENSION));
+                }
+                match dialog.save_file() {
+                    Some(path) => path,
+                    None => return,
+                }
+            }
+        };
+        let settings = self.current_settings();
+        // The editor's own settings still apply once this workspace is closed
+        self.user_settings.get_or_insert_with(|| settings.clone());
+        self.workspace.settings = settings;
+        match self.workspace.save(&path) {
+            Ok(()) => self.notifications.info(format!("Saved workspace to {}", path.display())),
+            Err(e) => self.notifications.error(format!("Failed to save workspace: {}", e)),
+        }
+    }
+
+    /// Writes a folder added or removed back to the workspace file, if there is one.
+    fn persist_workspace_folders(&mut self) {
+        if let Some(path) = self.workspace.file.clone() {
+            if let Err(e) = self.workspace.save(&path) {
+                self.notifications.error(format!("Failed to save workspace: {}", e));
+            }
+        }
+    }
+
+    fn encoding_menu(&mut self, ui: &mut egui::Ui) {
+        ui.add_enabled_ui(self.file_path.is_some() && self.view.is_text(), |ui| {
+            ui.menu_button("Reopen with Encoding", |ui| {
+                for &encoding in encoding::COMMON_ENCODINGS {
+                    if ui.button(encoding.name()).clicked() {
+                        self.request_action(ui.ctx(), PendingAction::Reopen(encoding));
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+        ui.menu_button("Save with Encoding", |ui| {
+            for &encoding in encoding::COMMON_ENCODINGS {
+                if ui.button(encoding.name()).clicked() {
+                    self.save_with_encoding(ui.ctx(), TextEncoding::new(encoding));
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            if ui.button("UTF-8 with BOM").clicked() {
+                self.save_with_encoding(ui.ctx(), TextEncoding { encoding: encoding_rs::UTF_8, bom: true });
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// Checks that the buffer can be written in `encoding` before asking where to save it.
+    fn save_with_encoding(&mut self, ctx: &egui::Context, encoding: TextEncoding) {
+        match encoding::encode(&self.content, encoding, self.line_ending) {
+            Ok(_) => self.save_in(ctx, encoding),
+            Err(e) => self.notifications.error(format!("Failed to save: {}", e)),
+        }
+    }
+
+    fn show_status_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let repo_path = self.file_path.clone().or_else(|| self.workspace.first_root());
+            if let Some(repo) = repo_path.and_then(|p| self.git.repo_for(&p)) {
+                let mut branch = format!("⎇ {}", repo.branch);
+                if repo.ahead > 0 {
+                    branch.push_str(&format!(" ↑{}", repo.ahead));
+                }
+                if repo.behind > 0 {
+                    branch.push_str(&format!(" ↓{}", repo.behind));
+                }
+                ui.label(branch).on_hover_text(repo.root.display().to_string());
+            }
+            if self.formatting.is_some() {
+                ui.spinner();
+                ui.weak("Formatting…");
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if let Some(label) = self.view.label() {
+                    ui.label(label);
+                    return;
+                }
+                ui.menu_button(self.line_ending.label(), |ui| {
+                    for line_ending in [LineEnding::Lf, LineEnding::CrLf] {
+                        if ui.radio(self.line_ending == line_ending, line_ending.label()).clicked() {
+                            self.line_ending = line_ending;
+                            ui.close_menu();
+                        }
+                    }
+                });
+                ui.menu_button(self.encoding.label(), |ui| {
+                    self.encoding_menu(ui);
+                });
+                if let Some(language) = &self.current_syntax {
+                    ui.label(language);
+                }
+            });
+        });
+    }
+
+    fn editor_id() -> egui::Id {
+        egui::Id::new("editor")
+    }
+
+    fn format_document(&mut self) {
+        if let Some(path) = self.file_path.clone() {
+            self.start_format(&path, None);
+        }
+    }
+
+    /// Starts formatting the buffer in the background with the tools configured for files
+    /// like `path`, to be saved to `save` afterwards if given. Returns whether it started.
+    fn start_format(&mut self, path: &Path, save: Option<(PathBuf, TextEncoding)>) -> bool {
+        if !self.view.is_text() {
+            return false;
+        }
+        let server = self.formatter_config.language_server_for(path).cloned();
+        let formatter = self.formatter_config.formatter_for(path).cloned();
+        if server.is_none() && formatter.is_none() {
+            self.notifications.error(format!("No formatter configured for {}", path.display()));
+            return false;
+        }
+        let root = self.workspace.root_of(path).or(path.parent()).unwrap_or(Path::new(".")).to_path_buf();
+        let job = FormatJob { path: path.to_path_buf(), root, input: self.content.clone(), server, formatter };
+        let id = self.format_tasks.start(job);
+        // A save waiting on an earlier format still happens
+        let save = save.or_else(|| self.formatting.take().and_then(|f| f.save));
+        self.formatting = Some(PendingFormat { id, edits: self.edits, save });
+        true
+    }
+
+    fn poll_format(&mut self, ctx: &egui::Context) {
+        for (id, result) in self.format_tasks.poll() {
+            let Some(pending) = self.formatting.take_if(|f| f.id == id) else {
+                continue;
+            };
+            match result {
+                // Typing meanwhile wins over the formatted text
+                Ok(_) if self.edits != pending.edits => {
+                    self.notifications.info("The document changed while it was being formatted, so it was left as it is");
+                }
+                Ok(formatted) => {
+                    if formatted != self.content {
+                        self.replace_content(ctx, &formatted);
+                        self.dirty = true;
+                        self.swap_pending = true;
+                    }
+                }
+                Err(e) => {
+                    self.notifications.error(format!("Failed to format document: {}", e));
+                    self.diagnostic = e;
+                }
+            }
+            if let Some((path, encoding)) = pending.save {
+                self.finish_save(ctx, path, encoding);
+            }
+        }
+    }
+
+    /// Whether a save is waiting for the buffer to be formatted.
+    fn saving(&self) -> bool {
+        self.formatting.as_ref().is_some_and(|f| f.save.is_some())
+    }
+
+    /// Swaps in `text` by editing only the lines that differ, keeping the cursor and undo history.
+    fn replace_content(&mut self, ctx: &egui::Context, text: &str) {
+        let mut state = egui::TextEdit::load_state(ctx, Self::editor_id()).unwrap_or_default();
+        let cursor = state.cursor.char_range().map_or(0, |r| r.primary.index);
+        let new_cursor = format::apply_minimal_edit(&mut self.content, text, cursor);
+        self.edits += 1;
+        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(new_cursor))));
+        state.store(ctx, Self::editor_id());
+    }
+
+    fn poll_file_changes(&mut self, ctx: &egui::Context) {
+        let mut git_folders: Vec<PathBuf> = self.workspace.roots().map(Path::to_path_buf).collect();
+        if let Some(dir) = self.file_path.as_ref().and_then(|p| p.parent()) {
+            if self.workspace.root_of(dir).is_none() {
+                git_folders.push(dir.to_path_buf());
+            }
+        }
+        self.git.set_folders(&git_folders);
+        self.git.poll();
+
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        let mut watched: Vec<_> = self
+            .workspace
+            .roots()
+            .map(|root| (root.to_path_buf(), notify::RecursiveMode::Recursive))
+            .collect();
+        if let Some(dir) = self.file_path.as_ref().and_then(|p| p.parent()) {
+            if self.workspace.root_of(dir).is_none() {
+                watched.push((dir.to_path_buf(), notify::RecursiveMode::NonRecursive));
+            }
+        }
+        watcher.set_watched(watched);
+
+        let changed = watcher.changed_paths();
+        if !changed.is_empty() {
+            self.git.refresh();
+        }
+        for path in &changed {
+            self.file_tree.invalidate(path);
+        }
+        if let Some(path) = self.file_path.clone() {
+            if self.view.is_text() && changed.contains(&path) {
+                self.handle_external_change(ctx, &path);
+            }
+        }
+    }
+
+    fn handle_external_change(&mut self, ctx: &egui::Context, path: &PathBuf) {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) if !path.exists() => {
+                if !self.dirty {
+                    self.notifications.error(format!("{} was deleted on disk", path.display()));
+                }
+                // Keep the text around and make sure closing asks before dropping it
+                self.dirty = true;
+                return;
+            }
+            Err(_) => return,
+        };
+        let disk = encoding::decode_with(&bytes, self.encoding.encoding).text;
+        // Our own saves come back through the watcher too
+        if disk == self.disk_content {
+            return;
+        }
+        if self.dirty {
+            self.external_change = Some(disk);
+        } else {
+            self.replace_content(ctx, &disk);
+            self.disk_content = disk;
+            self.notifications.info(format!("Reloaded {}", path.display()));
+        }
+    }
+
+    fn show_external_change_dialog(&mut self, ctx: &egui::Context) {
+        let Some(disk) = self.external_change.clone() else {
+            return;
+        };
+        let name = self
+            .file_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+
+        egui::Window::new("File Changed on Disk")
+            .collapsible(false)
+            .default_width(600.0)
+            .show(ctx, |ui| {
+                ui.label(format!("{} was changed by another program while you had unsaved changes.", name));
+                ui.label("Changes from your version to the version on disk:");
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    let diff = similar::TextDiff::from_lines(&self.content, &disk);
+                    for line in diff.unified_diff().context_radius(3).to_string().lines() {
+                        let color = if line.starts_with('+') {
+                            egui::Color32::from_rgb(100, 200, 100)
+                        } else if line.starts_with('-') {
+                            egui::Color32::from_rgb(220, 100, 100)
+                        } else {
+                            ui.visuals().text_color()
+                        };
+                        ui.label(egui::RichText::new(line).monospace().color(color));
+                    }
+                });
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Reload from Disk").clicked() {
+                        self.replace_content(ctx, &disk);
+                        self.disk_content = disk.clone();
+                        self.dirty = false;
+                        self.external_change = None;
+                    }
+                    if ui.button("Merge").clicked() {
+                        let merged = merge::merge3(&self.disk_content, &self.content, &disk, "Mine", "Disk");
+                        if merged.conflicts > 0 {
+                            self.notifications.error(format!("Merged with {} conflict(s) to resolve", merged.conflicts));
+                        }
+                        self.replace_content(ctx, &merged.text);
+                        self.disk_content = disk.clone();
+                        self.swap_pending = true;
+                        self.external_change = None;
+                    }
+                    if ui.button("Keep Mine").clicked() {
+                        // Saving will now knowingly overwrite the version on disk
+                        self.disk_content = disk.clone();
+                        self.external_change = None;
+                    }
+                });
+            });
+    }
+
+    fn save(&mut self, ctx: &egui::Context) {
+        self.save_in(ctx, self.encoding);
+    }
+
+    /// Saves the buffer in `encoding`, which becomes the buffer's encoding once it has been written.
+    fn save_in(&mut self, ctx: &egui::Context, encoding: TextEncoding) {
+        if !self.view.is_text() {
+            self.notifications.error("This file is open read-only");
+            return;
+        }
+        if let Some(path) = self.file_path.clone().or_else(|| self.pick_save_path()) {
+            self.save_to(ctx, path, encoding);
+        }
+    }
+
+    fn save_as(&mut self, ctx: &egui::Context) {
+        if !self.view.is_text() {
+            self.notifications.error("This file is open read-only");
+            return;
+        }
+        if let Some(path) = self.pick_save_path() {
+            self.save_to(ctx, path, self.encoding);
+        }
+    }
+
+    fn pick_save_path(&self) -> Option<PathBuf> {
+        let mut dialog = rfd::FileDialog::new();
+        let dir = self.file_path.as_ref().and_then(|p| p.parent()).map(Path::to_path_buf);
+        if let Some(dir) = dir.or_else(|| self.workspace.first_root()) {
+            dialog = dialog.set_directory(dir);
+        }
+        if let Some(name) = self.file_path.as_ref().and_then(|p| p.file_name()) {
+            dialog = dialog.set_file_name(name.to_string_lossy());
+        }
+        dialog.save_file()
+    }
+
+    /// Saves the buffer to `path`, which becomes the buffer's file once it has been written.
+    /// With format on save, the write waits for the formatter.
+    fn save_to(&mut self, ctx: &egui::Context, path: PathBuf, encoding: TextEncoding) {
+        if self.format_on_save && self.start_format(&path, Some((path.clone(), encoding))) {
+            return;
+        }
+        self.finish_save(ctx, path, encoding);
+    }
+
+    fn finish_save(&mut self, ctx: &egui::Context, path: PathBuf, encoding: TextEncoding) {
+        if self.write_file(&path, encoding) {
+            self.file_path = Some(path);
+            self.trashed_file = None;
+        }
+        // What the unsaved changes dialog was waiting to do
+        if let Some(action) = self.action_after_save.take().filter(|_| !self.dirty) {
+            self.perform_action(ctx, action);
+        }
+    }
+
+    /// Writes the buffer to `path` in `encoding`; returns whether it was written.
+    fn write_file(&mut self, path: &Path, encoding: TextEncoding) -> bool {
+        let bytes = match encoding::encode(&self.content, encoding, self.line_ending) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.notifications.error(format!("Failed to save {}: {}", path.display(), e));
+                return false;
+            }
+        };
+        match save::atomic_write(path, &bytes) {
+            Ok(()) => {
+                self.file_written(path, encoding);
+                true
+            }
+            // A file we may write but not replace, e.g. one owned by someone else
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && is_writable(path) => {
+                self.confirm_overwrite = Some((path.to_path_buf(), encoding));
+                false
+            }
+            Err(e) => {
+                self.notifications.error(format!("Failed to save {}: {}", path.display(), e));
+                false
+            }
+        }
+    }
+
+    fn file_written(&mut self, path: &Path, encoding: TextEncoding) {
+        self.encoding = encoding;
+        self.dirty = false;
+        self.autosave_failed_at = None;
+        self.disk_content = self.content.clone();
+        self.external_change = None;
+        self.mark_resolved_if_clean(path);
+    }
+
+    fn show_overwrite_dialog(&mut self, ctx: &egui::Context) {
+        let Some((path, encoding)) = self.confirm_overwrite.clone() else {
+            return;
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        egui::Window::new("Overwrite in Place")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "'{}' can't be replaced safely, because you don't own it or its folder is read-only. \
+                     Overwrite it in place? A copy of the original is kept until the write has finished.",
+                    name
+                ));
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Overwrite").clicked() {
+                        self.confirm_overwrite = None;
+                        let result = encoding::encode(&self.content, encoding, self.line_ending)
+                            .and_then(|bytes| save::overwrite_in_place(&path, &bytes).map_err(|e| e.to_string()));
+                        match result {
+                            Ok(()) => {
+                                self.file_written(&path, encoding);
+                                self.file_path = Some(path.clone());
+                                self.trashed_file = None;
+                            }
+                            Err(e) => self.notifications.error(format!("Failed to save {}: {}", path.display(), e)),
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_overwrite = None;
+                    }
+                });
+            });
     }
 
     fn show_taskbar(&mut self, ui: &mut egui::Ui) {
         egui::menu::bar(ui, |ui| {
             ui.menu_button("File", |ui| {
                 if ui.button("New File").clicked() {
-                    self.creating_new_item = Some(true);
-                    self.new_item_name.clear();
+                    self.start_new_item(true, self.workspace.first_root());
                     ui.close_menu();
                 }
                 if ui.button("New Folder").clicked() {
-                    self.creating_new_item = Some(false);
-                    self.new_item_name.clear();
+                    self.start_new_item(false, self.workspace.first_root());
                     ui.close_menu();
                 }
                 if ui.button("New").clicked() {
-                    self.content = String::new();
-                    self.file_path = None;
-                    self.current_syntax = None;
+                    self.request_action(ui.ctx(), PendingAction::NewDocument);
                     ui.close_menu();
                 }
                 if ui.button("Open").clicked() {
                     if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        self.file_path = Some(path.clone());
-                        self.current_dir = path.parent().map(|p| p.to_path_buf());
-                        self.content = fs::read_to_string(&path).unwrap_or_else(|_| String::new());
-                        self.detect_language();
+                        self.request_action(ui.ctx(), PendingAction::OpenFileWithFolder(path));
                     }
                     ui.close_menu();
                 }
                 if ui.button("Open Folder").clicked() {
                     if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                        self.current_dir = Some(path);
-                        self.expanded_folders.clear(); // Reset expanded state when opening a new folder
+                        self.open_workspace(Workspace::single(path));
                     }
                     ui.close_menu();
                 }
+                self.workspace_menu(ui);
                 if ui.button("Save").clicked() {
-                    if let Some(path) = &self.file_path {
-                        fs::write(path, &self.content).expect("Unable to write file");
-                    } else if let Some(path) = rfd::FileDialog::new().save_file() {
-                        self.file_path = Some(path.clone());
-                        fs::write(&path, &self.content).expect("Unable to write file");
-                    }
+                    self.save(ui.ctx());
+                    ui.close_menu();
+                }
+                if ui.button("Save As...").clicked() {
+                    self.save_as(ui.ctx());
+                    ui.close_menu();
+                }
+                let can_compare = self.file_path.is_some() && self.view.is_text();
+                if ui.add_enabled(can_compare, egui::Button::new("Compare with Saved")).clicked() {
+                    self.compare_with_saved();
                     ui.close_menu();
                 }
+                ui.separator();
+                self.encoding_menu(ui);
+                ui.menu_button("Auto Save", |ui| {
+                    ui.radio_value(&mut self.autosave, AutosaveMode::Off, "Off");
+                    ui.radio_value(&mut self.autosave, AutosaveMode::OnFocusLost, "On Focus Lost");
+                    ui.radio_value(&mut self.autosave, AutosaveMode::AfterDelay, "After Delay");
+                    ui.add_enabled(
+                        self.autosave == AutosaveMode::AfterDelay,
+                        egui::DragValue::new(&mut self.autosave_delay_secs).clamp_range(1..=600).suffix(" s"),
+                    );
+                });
+                ui.separator();
                 if ui.button("Exit").clicked() {
-                    std::process::exit(0);
+                    self.request_action(ui.ctx(), PendingAction::Exit);
+                    ui.close_menu();
                 }
             });
 
             ui.menu_button("Edit", |ui| {
+                let undo_label = match self.file_history.last() {
+                    Some(op) => format!("Undo {}", op.description()),
+                    None => "Undo File Operation".to_string(),
+                };
+                if ui.add_enabled(!self.file_history.is_empty(), egui::Button::new(undo_label)).clicked() {
+                    self.undo_file_op();
+                    ui.close_menu();
+                }
+                ui.separator();
                 if ui.button("Cut").clicked() {
                     // Implement cut functionality
                     ui.close_menu();
@@ -288,10 +1783,70 @@ impl TextEditor {
                     // Implement paste functionality
                     ui.close_menu();
                 }
+                ui.separator();
+                if ui.add(egui::Button::new("Format Document").shortcut_text("Shift+Alt+F")).clicked() {
+                    self.format_document();
+                    ui.close_menu();
+                }
+                ui.checkbox(&mut self.format_on_save, "Format on Save");
+            });
+
+            ui.menu_button("Git", |ui| {
+                let line = self.cursor_line(ui.ctx());
+                let on_change = self.gutter.hunk_at_line(line).is_some();
+                if ui.add_enabled(on_change, egui::Button::new("Revert Change at Cursor")).clicked() {
+                    self.revert_hunk(ui.ctx(), line);
+                    ui.close_menu();
+                }
+                if ui.add_enabled(on_change, egui::Button::new("Stage Change at Cursor")).clicked() {
+                    self.stage_hunk(line);
+                    ui.close_menu();
+                }
+                let has_conflicts = !merge::find_conflicts(&self.content).is_empty();
+                if ui.add_enabled(has_conflicts, egui::Button::new("Open Merge Editor")).clicked() {
+                    self.open_merge_editor();
+                    ui.close_menu();
+                }
+                ui.separator();
+                let in_repo = self.file_path.as_ref().is_some_and(|p| self.git.repo_for(p).is_some());
+                if ui.add_enabled(in_repo && self.view.is_text(), egui::Button::new("Compare with HEAD")).clicked() {
+                    self.compare_with_head();
+                    ui.close_menu();
+                }
+                ui.separator();
+                ui.add_enabled(in_repo && self.view.is_text(), egui::Checkbox::new(&mut self.blame.enabled, "Blame"));
+                if ui.add_enabled(in_repo, egui::Button::new("File History")).clicked() {
+                    self.open_file_history(ui.ctx());
+                    ui.close_menu();
+                }
+            });
+
+            ui.menu_button("AI", |ui| {
+                if ui.add(egui::Button::new("Prompt…").shortcut_text("Ctrl+I")).clicked() {
+                    self.show_ai_prompt = true;
+                    ui.close_menu();
+                }
+                ui.checkbox(&mut self.inline_completion, "Inline Completions").on_hover_text(
+                    "Suggests code after a pause in typing. Tab accepts it, Ctrl+→ accepts the next word.",
+                );
+                ui.separator();
+                for action in CodeAction::ALL {
+                    let enabled = self.view.is_text()
+                        && (action != CodeAction::FixDiagnostic || !self.diagnostic.trim().is_empty());
+                    let button = ui
+                        .add_enabled(enabled, egui::Button::new(action.label()))
+                        .on_hover_text("Works on the selection, or the whole file when nothing is selected")
+                        .on_disabled_hover_text("Needs a diagnostic: run Format Document or paste one into the AI Prompt");
+                    if button.clicked() {
+                        self.start_code_action(ui.ctx(), action);
+                        ui.close_menu();
+                    }
+                }
             });
 
             ui.menu_button("View", |ui| {
                 ui.checkbox(&mut self.vim_mode, "Vim Mode");
+                ui.checkbox(&mut self.show_source_control, "Source Control");
                 // Add more view options here
             });
 
@@ -325,19 +1880,50 @@ impl TextEditor {
         !should_close
     }
 
+    fn start_new_item(&mut self, is_file: bool, parent: Option<PathBuf>) {
+        self.creating_new_item = Some(is_file);
+        self.new_item_parent = parent;
+        self.new_item_name.clear();
+    }
+
     fn show_new_item_dialog(&mut self, ctx: &egui::Context, is_file: bool) {
         let title = if is_file { "New File" } else { "New Folder" };
-        
+        let Some(parent) = self.new_item_parent.clone() else {
+            self.notifications.error("Open a folder first");
+            self.creating_new_item = None;
+            return;
+        };
+        let validated = file_ops::validate_new_name(&parent, &self.new_item_name);
+
         egui::Window::new(title)
             .resizable(false)
             .show(ctx, |ui| {
+                let relative = self
+                    .workspace
+                    .root_of(&parent)
+                    .and_then(|root| parent.strip_prefix(root).ok())
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(&parent);
+                ui.weak(format!("In {}", relative.display()));
+                let mut submitted = false;
                 ui.horizontal(|ui| {
                     ui.label("Name:");
-                    ui.text_edit_singleline(&mut self.new_item_name);
+                    let response = ui.text_edit_singleline(&mut self.new_item_name);
+                    if !response.has_focus() && !response.lost_focus() {
+                        response.request_focus();
+                    }
+                    submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
                 });
+                // Only complain once something has been typed
+                if let Err(e) = &validated {
+                    if !self.new_item_name.trim().is_empty() {
+                        ui.colored_label(ui.visuals().error_fg_color, e);
+                    }
+                }
                 ui.horizontal(|ui| {
-                    if ui.button("Create").clicked() {
-                        self.create_new_item(is_file);
+                    let create = ui.add_enabled(validated.is_ok(), egui::Button::new("Create"));
+                    if let (true, Ok(path)) = (create.clicked() || submitted, &validated) {
+                        self.create_new_item(ctx, path, is_file);
                         self.creating_new_item = None;
                     }
                     if ui.button("Cancel").clicked() {
@@ -347,36 +1933,89 @@ impl TextEditor {
             });
     }
 
-    fn create_new_item(&mut self, is_file: bool) {
-        if let Some(current_dir) = &self.current_dir {
-            let new_path = current_dir.join(&self.new_item_name);
-            if is_file {
-                if let Err(e) = std::fs::File::create(&new_path) {
-                    eprintln!("Failed to create file: {}", e);
-                } else {
-                    // Optionally, open the new file in the editor
-                    self.file_path = Some(new_path.clone());
-                    self.content = String::new();
-                    self.detect_language();
-                }
-            } else {
-                if let Err(e) = std::fs::create_dir(&new_path) {
-                    eprintln!("Failed to create folder: {}", e);
-                }
+    fn create_new_item(&mut self, ctx: &egui::Context, new_path: &PathBuf, is_file: bool) {
+        // Remember the outermost folder we have to create so undo removes all of it
+        let created_root = new_path
+            .ancestors()
+            .take_while(|p| !p.exists())
+            .last()
+            .unwrap_or(new_path)
+            .to_path_buf();
+
+        let result = if is_file {
+            new_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::OpenOptions::new().write(true).create_new(true).open(new_path).map(|_| ()))
+        } else {
+            fs::create_dir_all(new_path)
+        };
+        if let Err(e) = result {
+            let kind = if is_file { "file" } else { "folder" };
+            self.notifications.error(format!("Failed to create {}: {}", kind, e));
+            return;
+        }
+
+        self.file_tree.invalidate(&created_root);
+        self.file_history.push(FileOp::Created(created_root));
+        self.reveal(new_path);
+        if is_file {
+            self.request_action(ctx, PendingAction::OpenFile(new_path.clone()));
+        }
+    }
+
+    /// Expands the folders leading to `path`, selects it and scrolls it into view.
+    fn reveal(&mut self, path: &Path) {
+        // Includes the root itself, which is a collapsible node in a multi-root workspace
+        if let Some(root) = self.workspace.root_of(path) {
+            for ancestor in path.ancestors().skip(1).take_while(|a| a.starts_with(root)) {
+                self.expanded_folders.insert(ancestor.to_path_buf(), true);
             }
-            // Refresh the file tree
-            self.refresh_tree = true;
         }
+        self.selected_file = Some(path.to_path_buf());
+        self.reveal_path = Some(path.to_path_buf());
     }
 
     fn show_editor(&mut self, ui: &mut egui::Ui) {
+        let show_blame = self.blame.enabled && self.in_git_repo();
+        self.handle_ghost_text_keys(ui.ctx());
         let editor = egui::TextEdit::multiline(&mut self.content)
+            .id(Self::editor_id())
             .desired_width(f32::INFINITY)
-            .font(egui::TextStyle::Monospace);
+            .font(egui::TextStyle::Monospace)
+            // Tab accepts the suggestion instead of moving focus
+            .lock_focus(self.ghost_text.is_some());
 
-        let response = ui.add(editor);
+        let (blame_x, gutter_x, output) = ui
+            .horizontal_top(|ui| {
+                let blame_x = ui.cursor().left();
+                if show_blame {
+                    ui.allocate_space(egui::vec2(BLAME_WIDTH, 0.0));
+                }
+                let (_, gutter) = ui.allocate_space(egui::vec2(GUTTER_WIDTH, 0.0));
+                (blame_x, gutter.left(), editor.show(ui))
+            })
+            .inner;
+        let lines = line_spans(&output.galley, output.galley_pos);
+        if show_blame {
+            self.show_blame(ui, blame_x, &lines);
+        }
+        self.show_gutter(ui, gutter_x, &lines, output.galley_pos);
+        self.show_conflict_actions(ui, &lines, output.response.rect);
+        self.show_ghost_text(ui, &output.galley, output.galley_pos);
+        let response = output.response;
 
         if response.changed() {
+            self.edits += 1;
+            self.dirty = true;
+            self.swap_pending = true;
+            self.last_edit = Instant::now();
+            self.ghost_text = None;
+            self.ai.cancel_completion();
+            self.completion_wanted = self.inline_completion;
+            if self.inline_completion {
+                ui.ctx().request_repaint_after(COMPLETION_DELAY);
+            }
             // Get the cursor position from the UI state
             if let Some(cursor_pos) = ui.input(|i| i.events.iter().find_map(|e| {
                 if let egui::Event::Text(_text) = e {
@@ -406,42 +2045,687 @@ impl TextEditor {
                 }
             }
         }
+    }
+
+    /// Marks lines that differ from the git index next to the editor. Hovering a mark shows
+    /// what was there before; right-clicking it offers to revert or stage the change.
+    fn show_gutter(&mut self, ui: &mut egui::Ui, x: f32, lines: &[(f32, f32)], origin: egui::Pos2) {
+        let mut revert_line = None;
+        let mut stage_line = None;
+        for hunk in self.gutter.hunks() {
+            let color = hunk.kind.color(ui.visuals());
+            let rect = if hunk.kind == ChangeKind::Deleted {
+                let y = lines.get(hunk.start).map_or_else(|| lines.last().map_or(origin.y, |l| l.1), |l| l.0);
+                let rect = egui::Rect::from_min_max(egui::pos2(x, y - 4.0), egui::pos2(x + GUTTER_WIDTH, y + 4.0));
+                ui.painter().add(egui::Shape::convex_polygon(
+                    vec![rect.left_top(), rect.right_center(), rect.left_bottom()],
+                    color,
+                    egui::Stroke::NONE,
+                ));
+                rect
+            } else {
+                let top = lines.get(hunk.start).map_or(origin.y, |l| l.0);
+                let bottom = lines.get(hunk.end - 1).map_or(top, |l| l.1);
+                let rect = egui::Rect::from_min_max(egui::pos2(x + 2.0, top), egui::pos2(x + GUTTER_WIDTH - 2.0, bottom));
+                ui.painter().rect_filled(rect, 1.0, color);
+                rect
+            };
+
+            let response = ui
+                .interact(rect, ui.id().with(("git_hunk", hunk.start)), egui::Sense::click())
+                .on_hover_ui(|ui| {
+                    if hunk.original.is_empty() {
+                        ui.label("Added lines");
+                    } else {
+                        ui.label("Original:");
+                        let preview: String = hunk.original.split_inclusive('\n').take(30).collect();
+                        ui.monospace(preview.trim_end_matches('\n'));
+                    }
+                    ui.weak("Right-click to revert or stage");
+                });
+            response.context_menu(|ui| {
+                if ui.button("Revert Change").clicked() {
+                    revert_line = Some(hunk.start);
+                    ui.close_menu();
+                }
+                if ui.button("Stage Change").clicked() {
+                    stage_line = Some(hunk.start);
+                    ui.close_menu();
+                }
+            });
+        }
+        if let Some(line) = revert_line {
+            self.revert_hunk(ui.ctx(), line);
+        }
+        if let Some(line) = stage_line {
+            self.stage_hunk(line);
+        }
+    }
+
+    /// Tints the two sides of every conflict in the buffer and puts buttons to resolve it
+    /// on its first marker line.
+    fn show_conflict_actions(&mut self, ui: &mut egui::Ui, lines: &[(f32, f32)], editor_rect: egui::Rect) {
+        let conflicts = merge::find_conflicts(&self.content);
+        let span = |range: &std::ops::Range<usize>| -> Option<egui::Rect> {
+            let top = lines.get(range.start)?.0;
+            let bottom = lines.get(range.end.checked_sub(1)?)?.1;
+            Some(egui::Rect::from_x_y_ranges(editor_rect.x_range(), top..=bottom))
+        };
+        let mut resolve = None;
+        let mut open_merge_editor = false;
+        for (index, conflict) in conflicts.iter().enumerate() {
+            for (range, color) in [
+                (&conflict.ours, egui::Color32::from_rgba_unmultiplied(0x50, 0xc0, 0x70, 24)),
+                (&conflict.theirs, egui::Color32::from_rgba_unmultiplied(0x4e, 0x94, 0xce, 24)),
+            ] {
+                if let Some(rect) = span(range) {
+                    ui.painter().rect_filled(rect, 0.0, color);
+                }
+            }
+            let Some(&(top, bottom)) = lines.get(conflict.start) else {
+                continue;
+            };
+            let bar = egui::Rect::from_x_y_ranges(editor_rect.x_range(), top..=bottom);
+            // A child ui doesn't take space in the editor's layout, so the buttons float over the marker line
+            let mut ui = ui.child_ui(bar, egui::Layout::right_to_left(egui::Align::Center));
+            ui.spacing_mut().button_padding.y = 0.0;
+            if ui.small_button("Merge Editor…").clicked() {
+                open_merge_editor = true;
+            }
+            for (label, resolution) in [
+                ("Accept Both", Resolution::Both),
+                ("Accept Theirs", Resolution::Theirs),
+                ("Accept Ours", Resolution::Ours),
+            ] {
+                if ui.small_button(label).clicked() {
+                    resolve = Some((index, resolution));
+                }
+            }
+        }
+        if let Some((index, resolution)) = resolve {
+            let text = merge::resolve(&self.content, &conflicts[index], resolution);
+            self.replace_content(ui.ctx(), &text);
+            self.dirty = true;
+            self.swap_pending = true;
+            self.last_edit = Instant::now();
+        }
+        if open_merge_editor {
+            self.open_merge_editor();
+        }
+    }
+
+    fn open_merge_editor(&mut self) {
+        if let Some(path) = self.file_path.clone() {
+            self.merge_editor = Some(MergeEditor::new(path, &self.content));
+        }
+    }
+
+    /// Stages a file git reports as conflicted once it has been saved without markers,
+    /// which is how git learns the conflict is resolved.
+    fn mark_resolved_if_clean(&mut self, path: &Path) {
+        if self.git.status_of(path) != Some(git::FileStatus::Conflicted) || !merge::find_conflicts(&self.content).is_empty() {
+            return;
+        }
+        let Some(root) = self.git.repo_for(path).map(|repo| repo.root.clone()) else {
+            return;
+        };
+        match git::stage_path(&root, path) {
+            Ok(()) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                self.notifications.info(format!("Marked {} as resolved", name));
+                self.git.refresh();
+            }
+            Err(e) => self.notifications.error(format!("Failed to mark {} as resolved: {}", path.display(), e)),
+        }
+    }
+
+    /// The line the editor's cursor is on.
+    fn cursor_line(&self, ctx: &egui::Context) -> usize {
+        let state = egui::TextEdit::load_state(ctx, Self::editor_id()).unwrap_or_default();
+        let cursor = state.cursor.char_range().map_or(0, |r| r.primary.index);
+        self.content.chars().take(cursor).filter(|&c| c == '\n').count()
+    }
+
+    fn revert_hunk(&mut self, ctx: &egui::Context, line: usize) {
+        let text = match self.gutter.hunk_at_line(line).map(|hunk| hunk.revert(&self.content)) {
+            Some(Ok(text)) => text,
+            Some(Err(e)) => {
+                self.notifications.error(format!("Failed to revert change: {}", e));
+                return;
+            }
+            None => return,
+        };
+        self.replace_content(ctx, &text);
+        self.dirty = true;
+        self.swap_pending = true;
+        self.last_edit = Instant::now();
+    }
+
+    fn stage_hunk(&mut self, line: usize) {
+        let Some(hunk) = self.gutter.hunk_at_line(line) else {
+            return;
+        };
+        match self.gutter.stage(&self.content, hunk, self.encoding, self.line_ending) {
+            Ok(()) => self.git.refresh(),
+            Err(e) => self.notifications.error(format!("Failed to stage change: {}", e)),
+        }
+    }
+
+    fn compare_with_saved(&mut self) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let saved = encoding::decode_with(&bytes, self.encoding.encoding).text;
+                let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                self.diff_view = Some(DiffView::new(
+                    format!("{} (Saved ↔ Working)", name),
+                    format!("{} (saved)", path.display()),
+                    &saved,
+                    format!("{} (unsaved changes)", path.display()),
+                    &self.content,
+                ));
+            }
+            Err(e) => self.notifications.error(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn compare_with_head(&mut self) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        let Some(root) = self.git.repo_for(&path).map(|repo| repo.root.clone()) else {
+            return;
+        };
+        // A file added since the last commit compares against nothing
+        let head = match git::show_committed_file(&root, "HEAD", &path) {
+            Ok(bytes) => bytes.map(|bytes| encoding::decode_with(&bytes, self.encoding.encoding).text).unwrap_or_default(),
+            Err(e) => {
+                self.notifications.error(format!("Failed to read {} from HEAD: {}", path.display(), e));
+                return;
+            }
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        self.diff_view = Some(DiffView::new(
+            format!("{} (HEAD ↔ Working)", name),
+            format!("{} (HEAD)", path.display()),
+            &head,
+            format!("{} (working copy)", path.display()),
+            &self.content,
+        ));
+    }
+
+    fn compare_files(&mut self, left: &PathBuf, right: &PathBuf) {
+        let read = |path: &PathBuf| -> Result<String, String> {
+            let text = match file_view::looks_binary(path) {
+                Ok(true) => Err("it is a binary file".to_string()),
+                Ok(false) => fs::read(path).map(|bytes| encoding::decode(&bytes).text).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            text.map_err(|e| format!("Failed to compare {}: {}", path.display(), e))
+        };
+        match read(left).and_then(|l| Ok((l, read(right)?))) {
+            Ok((left_text, right_text)) => {
+                let name = |p: &PathBuf| p.file_name().unwrap_or_default().to_string_lossy().to_string();
+                self.diff_view = Some(DiffView::new(
+                    format!("{} ↔ {}", name(left), name(right)),
+                    left.display().to_string(),
+                    &left_text,
+                    right.display().to_string(),
+                    &right_text,
+                ));
+            }
+            Err(e) => self.notifications.error(e),
+        }
+    }
+
+    /// Annotates the first line of each run of lines from the same commit with its
+    /// author and age; hovering any line shows the full commit.
+    fn show_blame(&self, ui: &mut egui::Ui, x: f32, lines: &[(f32, f32)]) {
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let small = egui::FontId::new(font.size * 0.85, font.family.clone());
+        let mut previous: Option<&str> = None;
+        for (index, &(top, bottom)) in lines.iter().enumerate() {
+            let Some(commit) = self.blame.line(index) else {
+                break;
+            };
+            let rect = egui::Rect::from_min_max(egui::pos2(x, top), egui::pos2(x + BLAME_WIDTH - 6.0, bottom));
+            if previous != Some(commit.id.as_str()) {
+                let text = if commit.is_uncommitted() {
+                    "Uncommitted".to_string()
+                } else {
+                    format!("{} · {}", commit.author, blame::age(commit.time))
+                };
+                ui.painter().with_clip_rect(rect).text(
+                    rect.left_center(),
+                    egui::Align2::LEFT_CENTER,
+                    text,
+                    small.clone(),
+                    ui.visuals().weak_text_color(),
+                );
+            }
+            previous = Some(&commit.id);
+            if commit.is_uncommitted() {
+                continue;
+            }
+            ui.interact(rect, ui.id().with(("blame_line", index)), egui::Sense::hover())
+                .on_hover_ui(|ui| {
+                    ui.monospace(&commit.id[..commit.id.len().min(10)]);
+                    ui.label(format!("{} · {}", commit.author, blame::age(commit.time)));
+                    ui.strong(&commit.summary);
+                });
+        }
+    }
+
+    /// Whether the open file is a text file inside a git repository.
+    fn in_git_repo(&self) -> bool {
+        self.view.is_text() && self.file_path.as_ref().is_some_and(|p| self.git.repo_for(p).is_some())
+    }
+
+    fn update_blame(&mut self) {
+        let path = self.file_path.clone().filter(|_| self.view.is_text());
+        // Files git doesn't know about have nothing to blame
+        let tracked = path
+            .as_deref()
+            .is_some_and(|p| !matches!(self.git.status_of(p), Some(git::FileStatus::Untracked | git::FileStatus::Ignored)));
+        let repo_root = path.as_deref().filter(|_| tracked).and_then(|p| self.git.repo_for(p)).map(|repo| repo.root.clone());
+        let idle = self.last_edit.elapsed() >= BLAME_DELAY;
+        let (encoding, line_ending) = (self.encoding, self.line_ending);
+        let content = &self.content;
+        let error = self.blame.update(path.as_deref(), repo_root.as_deref(), self.git.revision(), self.edits, idle, || {
+            encoding::encode(content, encoding, line_ending)
+        });
+        if let Some(e) = error {
+            self.blame.enabled = false;
+            self.notifications.error(format!("Failed to blame: {}", e));
+        }
+    }
+
+    fn open_file_history(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        let Some(root) = self.git.repo_for(&path).map(|repo| repo.root.clone()) else {
+            return;
+        };
+        self.history = Some(FileHistory::new(ctx, path, root));
+    }
+
+    fn show_file_history(&mut self, ctx: &egui::Context) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let action = history.show(ctx);
+        if !history.open {
+            self.history = None;
+            return;
+        }
+        let (Some(action), Some(history)) = (action, &self.history) else {
+            return;
+        };
+        let Some(Ok(entries)) = &history.entries else {
+            return;
+        };
+        let index = match action {
+            HistoryAction::Open(index) | HistoryAction::Compare(index) => index,
+        };
+        let entry = &entries[index];
+        let text = match git::show_file(&history.repo_root, &entry.id, &entry.path) {
+            Ok(bytes) => encoding::decode(&bytes).text,
+            Err(e) => {
+                self.notifications.error(format!("Failed to read {} at {}: {}", entry.path.display(), entry.short_id, e));
+                return;
+            }
+        };
+        let name = entry.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let label = format!("{} ({})", entry.path.display(), entry.short_id);
+        match action {
+            HistoryAction::Open(_) => {
+                self.diff_view = None;
+                self.revision_view = Some(RevisionView::new(format!("{} @ {} — {}", name, entry.short_id, entry.summary), text));
+            }
+            HistoryAction::Compare(_) => {
+                let working_copy = if self.file_path.as_ref() == Some(&history.path) {
+                    self.content.clone()
+                } else {
+                    match fs::read(&history.path) {
+                        Ok(bytes) => encoding::decode(&bytes).text,
+                        Err(e) => {
+                            self.notifications.error(format!("Failed to read {}: {}", history.path.display(), e));
+                            return;
+                        }
+                    }
+                };
+                self.revision_view = None;
+                self.diff_view = Some(DiffView::new(
+                    format!("{} ({} ↔ Working)", name, entry.short_id),
+                    label,
+                    &text,
+                    format!("{} (working copy)", history.path.display()),
+                    &working_copy,
+                ));
+            }
+        }
+    }
+
+    /// Tab takes the whole suggestion, Ctrl+→ its next word and Escape drops it. Moving
+    /// the cursor away drops it too.
+    fn handle_ghost_text_keys(&mut self, ctx: &egui::Context) {
+        let Some(ghost) = &self.ghost_text else {
+            return;
+        };
+        let selection = self.editor_selection(ctx);
+        let focused = ctx.memory(|m| m.has_focus(Self::editor_id()));
+        if !focused || !selection.is_empty() || selection.start != ghost.cursor || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.ghost_text = None;
+            return;
+        }
+        let accepted = if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab)) {
+            ghost.text.len()
+        } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::ArrowRight)) {
+            ghost.next_word_len()
+        } else {
+            return;
+        };
+        let Some(mut ghost) = self.ghost_text.take() else {
+            return;
+        };
+        let rest = ghost.text.split_off(accepted);
+        self.insert_text(ctx, ghost.cursor..ghost.cursor, &ghost.text);
+        if !rest.is_empty() {
+            let cursor = ghost.cursor + ghost.text.chars().count();
+            self.ghost_text = Some(GhostText { cursor, text: rest });
+        }
+    }
+
+    /// Paints the suggestion in faint text from the cursor on, over whatever follows it.
+    fn show_ghost_text(&self, ui: &egui::Ui, galley: &egui::Galley, origin: egui::Pos2) {
+        let Some(ghost) = &self.ghost_text else {
+            return;
+        };
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let cursor = galley.pos_from_ccursor(egui::text::CCursor::new(ghost.cursor)).translate(origin.to_vec2());
+        let mut pos = cursor.left_top();
+        for line in ghost.text.split('\n') {
+            let text = ui.fonts(|f| f.layout_no_wrap(line.to_string(), font.clone(), ui.visuals().weak_text_color()));
+            let rect = egui::Rect::from_min_size(pos, text.size());
+            ui.painter().rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+            ui.painter().galley(pos, text, ui.visuals().weak_text_color());
+            pos = egui::pos2(origin.x, pos.y + cursor.height());
+        }
+    }
+
+    /// Asks for a completion once typing has paused, and shows the one that comes back if
+    /// the buffer and cursor are still where it was asked for.
+    fn update_inline_completion(&mut self, ctx: &egui::Context) {
+        match self.ai.poll_completion() {
+            Some(Ok(text)) => {
+                let cursor = self.editor_selection(ctx);
+                if !text.is_empty() && cursor.is_empty() && self.completion_key == Some((self.edits, cursor.start)) {
+                    self.ghost_text = Some(GhostText { cursor: cursor.start, text });
+                }
+            }
+            // A slow or dropped request only costs this suggestion
+            Some(Err(CompletionError::Transient(_))) => {}
+            Some(Err(CompletionError::Config(e))) => {
+                self.inline_completion = false;
+                self.notifications.error(format!("Inline completions turned off: {}", e));
+            }
+            None => {}
+        }
+
+        let focused = ctx.memory(|m| m.has_focus(Self::editor_id()));
+        if !self.completion_wanted || !self.inline_completion || !focused || !self.view.is_text() {
+            return;
+        }
+        let waited = self.last_edit.elapsed();
+        if waited < COMPLETION_DELAY {
+            ctx.request_repaint_after(COMPLETION_DELAY - waited);
+            return;
+        }
+        self.completion_wanted = false;
+        let cursor = self.editor_selection(ctx);
+        if !cursor.is_empty() {
+            return;
+        }
+        match self.ai.start_completion(&self.ai_config, &self.content, cursor.start) {
+            Ok(()) => self.completion_key = Some((self.edits, cursor.start)),
+            Err(e) => {
+                self.inline_completion = false;
+                self.notifications.error(format!("Inline completions turned off: {}", e));
+            }
+        }
+    }
 
-        if let Some(ai_response) = &self.ai_response {
-            ui.label(ai_response);
+    fn apply_proposal(&mut self, ctx: &egui::Context, proposal: Proposal) {
+        if self.content != proposal.base {
+            self.notifications.error("The file changed since the suggestion was made; run the action again");
+            return;
         }
+        self.replace_content(ctx, &proposal.text);
+        self.dirty = true;
+        self.swap_pending = true;
+        self.last_edit = Instant::now();
+    }
+
+    fn update_gutter(&mut self) {
+        let path = self.file_path.as_deref().filter(|_| self.view.is_text());
+        let repo_root = path.and_then(|p| self.git.repo_for(p)).map(|repo| repo.root.clone());
+        self.gutter.set_source(path, repo_root.as_deref(), self.git.revision(), self.encoding.encoding);
+        self.gutter.update(&self.content, self.edits);
     }
 
     fn show_ai_prompt_dialog(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_ai_prompt;
+        let mut action = None;
         egui::Window::new("AI Prompt")
+            .open(&mut open)
             .resizable(false)
             .show(ctx, |ui| {
+                let config = &mut self.ai_config;
+                ui.horizontal(|ui| {
+                    ui.label("Provider:");
+                    egui::ComboBox::from_id_source("ai_provider")
+                        .selected_text(config.provider.label())
+                        .show_ui(ui, |ui| {
+                            for kind in ProviderKind::ALL {
+                                ui.selectable_value(&mut config.provider, kind, kind.label());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Endpoint:");
+                    ui.add(egui::TextEdit::singleline(&mut config.endpoint).hint_text(config.provider.default_endpoint()));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Model:");
+                    ui.add(egui::TextEdit::singleline(&mut config.model).hint_text(config.provider.default_model()));
+                    ui.label("Max Tokens:");
+                    ui.add(egui::DragValue::new(&mut config.max_tokens).clamp_range(1..=32768));
+                });
                 ui.horizontal(|ui| {
                     ui.label("API Key:");
-                    ui.text_edit_singleline(&mut self.ai_config.api_key);
+                    let hint = if config.provider.needs_api_key() { "" } else { "Optional" };
+                    ui.add(egui::TextEdit::singleline(&mut config.api_key).password(true).hint_text(hint));
                 });
                 ui.horizontal(|ui| {
                     ui.label("Prompt:");
                     ui.text_edit_singleline(&mut self.ai_config.prompt);
                 });
+                ui.collapsing("Diagnostic", |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.diagnostic)
+                            .hint_text("A compiler error for Fix Diagnostic")
+                            .font(egui::TextStyle::Monospace)
+                            .desired_rows(3),
+                    );
+                });
+                let running_for = self.ai.running_for();
+                if let Some((action, _)) = &self.code_action {
+                    ui.strong(action.label());
+                }
                 ui.horizontal(|ui| {
-                    if ui.button("Generate").clicked() {
-                        let api_key = self.ai_config.api_key.clone();
-                        let prompt = self.ai_config.prompt.clone();
-                        let client = Client::new();
-                        let response = fetch_ai_response(&client, &api_key, &prompt);
-                        self.ai_response = Some(response);
+                    let can_generate = running_for.is_none() && !self.ai_config.prompt.trim().is_empty();
+                    if ui.add_enabled(can_generate, egui::Button::new("Generate")).clicked() {
+                        self.ai_error = self.ai.start(&self.ai_config, &self.ai_config.prompt).err();
+                        self.ai_response = self.ai_error.is_none().then(String::new);
+                        self.code_action = None;
                     }
-                    if ui.button("Cancel").clicked() {
-                        self.ai_config.prompt.clear();
+                    if let Some(elapsed) = running_for {
+                        if ui.button("Cancel").clicked() {
+                            self.ai.cancel();
+                            self.code_action = None;
+                        }
+                        ui.spinner();
+                        ui.weak(format!("Generating… {}s", elapsed.as_secs()));
+                    }
+                });
+                if let Some(error) = &self.ai_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error.as_str());
+                }
+                let Some(response) = &self.ai_response else {
+                    return;
+                };
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(300.0).stick_to_bottom(true).show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut response.as_str())
+                            .font(egui::TextStyle::Monospace)
+                            .desired_width(f32::INFINITY),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    let ready = running_for.is_none() && !response.is_empty();
+                    if ui.add_enabled(ready, egui::Button::new("Insert at Cursor")).clicked() {
+                        action = Some(AiAction::Insert);
+                    }
+                    let has_selection = !self.editor_selection(ctx).is_empty();
+                    if ui.add_enabled(ready && has_selection, egui::Button::new("Replace Selection")).clicked() {
+                        action = Some(AiAction::ReplaceSelection);
+                    }
+                    if ui.add_enabled(ready, egui::Button::new("Open in New Buffer")).clicked() {
+                        action = Some(AiAction::NewBuffer);
                     }
                 });
             });
+        self.show_ai_prompt = open;
+
+        let (Some(action), Some(response)) = (action, self.ai_response.clone()) else {
+            return;
+        };
+        let text = response.replace("\r\n", "\n");
+        match action {
+            AiAction::Insert => {
+                let cursor = self.editor_selection(ctx).end;
+                self.insert_text(ctx, cursor..cursor, &text);
+            }
+            AiAction::ReplaceSelection => self.insert_text(ctx, self.editor_selection(ctx), &text),
+            AiAction::NewBuffer => self.request_action(ctx, PendingAction::NewDocumentWithText(text)),
+        }
+    }
+
+    /// The characters selected in the editor; an empty range at the cursor when nothing is.
+    fn editor_selection(&self, ctx: &egui::Context) -> std::ops::Range<usize> {
+        let state = egui::TextEdit::load_state(ctx, Self::editor_id()).unwrap_or_default();
+        state.cursor.char_range().map_or(0..0, |r| {
+            let (a, b) = (r.primary.index, r.secondary.index);
+            a.min(b)..a.max(b)
+        })
+    }
+
+    /// Replaces the characters in `range` with `text` and puts the cursor after it.
+    fn insert_text(&mut self, ctx: &egui::Context, range: std::ops::Range<usize>, text: &str) {
+        let byte = |index: usize| self.content.char_indices().nth(index).map_or(self.content.len(), |(i, _)| i);
+        let (start, end) = (byte(range.start), byte(range.end));
+        self.content.replace_range(start..end, text);
+        self.edits += 1;
+        let mut state = egui::TextEdit::load_state(ctx, Self::editor_id()).unwrap_or_default();
+        let cursor = egui::text::CCursor::new(range.start + text.chars().count());
+        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(cursor)));
+        state.store(ctx, Self::editor_id());
+        self.dirty = true;
+        self.swap_pending = true;
+        self.last_edit = Instant::now();
+    }
+
+    /// Sends the selection, or the whole file, to the AI with instructions for `action`.
+    fn start_code_action(&mut self, ctx: &egui::Context, action: CodeAction) {
+        self.detect_language();
+        let language = self.current_syntax.clone().unwrap_or_else(|| "plain text".to_string());
+        let file_name = self.file_path.as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().to_string());
+        let mut context = match CodeContext::new(&self.content, self.editor_selection(ctx), language, file_name) {
+            Ok(context) => context,
+            Err(e) => {
+                self.notifications.error(e);
+                return;
+            }
+        };
+        if action == CodeAction::FixDiagnostic {
+            context.diagnostic = Some(self.diagnostic.clone());
+        }
+        self.show_ai_prompt = true;
+        match self.ai.start(&self.ai_config, &context.prompt(action)) {
+            Ok(()) => {
+                self.ai_response = Some(String::new());
+                self.ai_error = None;
+                self.code_action = Some((action, context));
+            }
+            Err(e) => {
+                self.ai_response = None;
+                self.ai_error = Some(e);
+                self.code_action = None;
+            }
+        }
+    }
+
+    /// Adds whatever the running AI request has sent since the last frame.
+    fn poll_ai(&mut self) {
+        for update in self.ai.poll() {
+            match update {
+                AiUpdate::Text(text) => self.ai_response.get_or_insert_with(String::new).push_str(&text),
+                AiUpdate::Finished(Ok(())) => {
+                    let Some((action, context)) = self.code_action.take() else {
+                        continue;
+                    };
+                    if !action.edits_code() {
+                        continue;
+                    }
+                    let reply = self.ai_response.as_deref().unwrap_or_default();
+                    match context.apply(action, reply) {
+                        Ok(text) => {
+                            self.ai_proposal = Some(Proposal::new(action.label().to_string(), context.content, text));
+                            self.show_ai_prompt = false;
+                        }
+                        Err(e) => {
+                            if !self.show_ai_prompt {
+                                self.notifications.error(format!("{}: {}", action.label(), e));
+                            }
+                            self.ai_error = Some(e);
+                        }
+                    }
+                }
+                AiUpdate::Finished(Err(e)) => {
+                    self.code_action = None;
+                    if !self.show_ai_prompt {
+                        self.notifications.error(format!("AI request failed: {}", e));
+                    }
+                    self.ai_error = Some(e);
+                }
+            }
+        }
     }
 }
 
 impl eframe::App for TextEditor {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_file_changes(ctx);
+        self.update_gutter();
+        self.update_blame();
+        self.poll_ai();
+        self.poll_format(ctx);
+        self.update_inline_completion(ctx);
+
         if self.splash_screen.show_splash {
             egui::CentralPanel::default().show(ctx, |ui| {
                 ui.vertical_centered(|ui| {
@@ -454,17 +2738,14 @@ impl eframe::App for TextEditor {
                     
                     ui.add_space(20.0);
                     if ui.button("New File").clicked() {
-                        self.content = String::new();
-                        self.file_path = None;
-                        self.current_syntax = None;
+                        self.new_document();
                         self.splash_screen.show_splash = false;
                     }
                     if ui.button("Open File").clicked() {
                         if let Some(path) = rfd::FileDialog::new().pick_file() {
-                            self.file_path = Some(path.clone());
-                            self.content = fs::read_to_string(&path).unwrap_or_else(|_| String::new());
-                            self.detect_language();
-                            self.splash_screen.show_splash = false;
+                            if self.load_file(ctx, &path) {
+                                self.splash_screen.show_splash = false;
+                            }
                         }
                     }
                 });
@@ -474,15 +2755,83 @@ impl eframe::App for TextEditor {
                 self.show_taskbar(ui);
             });
 
+            egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+                self.show_status_bar(ui);
+            });
+
             egui::SidePanel::left("file_tree").show(ctx, |ui| {
                 self.show_file_tree(ui);
             });
 
+            if self.show_source_control {
+                egui::SidePanel::left("source_control").show(ctx, |ui| {
+                    for event in self.source_control.show(ui, &self.git, &mut self.notifications) {
+                        match event {
+                            PanelEvent::Open(path) => self.request_action(ctx, PendingAction::OpenFile(path)),
+                            PanelEvent::RepoChanged => self.git.refresh(),
+                            PanelEvent::SwitchBranch { repo, branch } => {
+                                self.request_action(ctx, PendingAction::SwitchBranch(repo, branch))
+                            }
+                        }
+                    }
+                });
+            }
+
+            self.show_file_history(ctx);
+
             egui::CentralPanel::default().show(ctx, |ui| {
                 ui.separator();
 
-                let _highlighted = self.highlight_content();
-                
+                if let Some(merge_editor) = &mut self.merge_editor {
+                    match merge_editor.show(ui) {
+                        Some(MergeOutcome::Complete(text)) => {
+                            let path = merge_editor.path.clone();
+                            self.merge_editor = None;
+                            // The buffer may have moved on to another file meanwhile
+                            if self.file_path.as_ref() == Some(&path) {
+                                self.replace_content(ctx, &text);
+                                self.dirty = true;
+                                self.save(ctx);
+                            }
+                        }
+                        Some(MergeOutcome::Cancel) => self.merge_editor = None,
+                        None => {}
+                    }
+                    return;
+                }
+
+                if let Some(proposal) = &mut self.ai_proposal {
+                    match proposal.show(ui) {
+                        Some(ProposalOutcome::Apply) => {
+                            if let Some(proposal) = self.ai_proposal.take() {
+                                self.apply_proposal(ctx, proposal);
+                            }
+                        }
+                        Some(ProposalOutcome::Discard) => self.ai_proposal = None,
+                        None => {}
+                    }
+                    return;
+                }
+
+                if let Some(revision_view) = &mut self.revision_view {
+                    if revision_view.show(ui) {
+                        self.revision_view = None;
+                    }
+                    return;
+                }
+
+                if let Some(diff_view) = &mut self.diff_view {
+                    if diff_view.show(ui) {
+                        self.diff_view = None;
+                    }
+                    return;
+                }
+
+                if !self.view.is_text() {
+                    self.view.show(ui);
+                    return;
+                }
+
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     self.show_editor(ui);
 
@@ -543,39 +2892,58 @@ impl eframe::App for TextEditor {
             self.show_about = self.show_about_dialog(ctx);
         }
 
+        if ctx.input(|i| i.viewport().close_requested()) && self.dirty && !self.allow_close {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.pending_action = Some(PendingAction::Exit);
+        }
+
+        self.tick_autosave(ctx);
+        self.show_recovery_dialog(ctx);
+        self.show_external_change_dialog(ctx);
+        self.show_delete_dialog(ctx);
+        self.show_overwrite_dialog(ctx);
+        self.show_unsaved_changes_dialog(ctx);
+        self.update_window_title(ctx);
+        self.notifications.show(ctx);
+
         if let Some(is_file) = self.creating_new_item {
             self.show_new_item_dialog(ctx, is_file);
         }
 
+        if ctx.input(|i| i.key_pressed(egui::Key::F2)) && self.renaming.is_none() && !ctx.wants_keyboard_input() {
+            if let Some(path) = self.selected_file.clone() {
+                self.start_rename(&path);
+            }
+        }
+
+        if ctx.input(|i| i.key_pressed(egui::Key::F) && i.modifiers.shift && i.modifiers.alt) {
+            self.format_document();
+        }
+
         if ctx.input(|i| i.key_pressed(egui::Key::I) && i.modifiers.ctrl) {
+            self.show_ai_prompt = true;
+        }
+        if self.show_ai_prompt {
             self.show_ai_prompt_dialog(ctx);
         }
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
-struct AIConfig {
-    api_key: String,
-    prompt: String,
-}
-
-async fn fetch_ai_response(client: &Client, api_key: &str, prompt: &str) -> String {
-    let url = "https://api.openai.com/v1/engines/davinci-codex/completions";
-    let params = serde_json::json!({
-        "prompt": prompt,
-        "max_tokens": 100,
-    });
-
-    let response = client
-        .post(url)
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&params)
-        .send()
-        .await
-        .expect("Failed to send request");
-
-    let response_json: serde_json::Value = response.json().await.expect("Failed to parse response");
-    response_json["choices"][0]["text"].as_str().unwrap_or("").to_string()
+/// The top and bottom of every logical line laid out in `galley`, which may wrap over
+/// several rows.
+fn line_spans(galley: &egui::Galley, origin: egui::Pos2) -> Vec<(f32, f32)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(f32, f32)> = None;
+    for row in &galley.rows {
+        let (top, bottom) = (origin.y + row.rect.top(), origin.y + row.rect.bottom());
+        let (line_top, _) = current.unwrap_or((top, bottom));
+        current = Some((line_top, bottom));
+        if row.ends_with_newline {
+            lines.extend(current.take());
+        }
+    }
+    lines.extend(current);
+    lines
 }
 
 fn main() -> Result<(), eframe::Error> {
@@ -590,3 +2958,17 @@ fn main() -> Result<(), eframe::Error> {
         Box::new(|cc| Box::new(TextEditor::new(cc))),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ghost_text_is_accepted_a_word_at_a_time() {
+        let ghost = |text: &str| GhostText { cursor: 0, text: text.to_string() };
+        assert_eq!(ghost("  héllo wörld").next_word_len(), "  héllo".len());
+        assert_eq!(ghost("\tπ_2+1").next_word_len(), "\tπ_2".len());
+        assert_eq!(ghost(" →x").next_word_len(), " →".len());
+        assert_eq!(ghost("   ").next_word_len(), 3);
+    }
+}
@@ -0,0 +1,337 @@
+use similar::{DiffOp, TextDiff};
+use std::ops::Range;
+
+pub const OURS_MARKER: &str = "<<<<<<<";
+/// Starts the common ancestor's lines in diff3-style conflicts.
+pub const BASE_MARKER: &str = "|||||||";
+pub const SEPARATOR_MARKER: &str = "=======";
+pub const THEIRS_MARKER: &str = ">>>>>>>";
+
+pub struct MergeResult {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// A change one side made to the base: base lines `start..end` became `lines`.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: &'a [&'a str],
+    ours: bool,
+}
+
+/// Line-based three-way merge. Changes made by only one side are taken as is; regions
+/// both sides changed differently are wrapped in conflict markers labelled with the
+/// given names.
+pub fn merge3(base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> MergeResult {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+    // Markers follow the buffer's line endings so a CRLF file doesn't end up mixed
+    let newline = line_ending(ours);
+
+    let mut hunks = side_hunks(&base_lines, &ours_lines, true);
+    hunks.extend(side_hunks(&base_lines, &theirs_lines, false));
+    hunks.sort_by_key(|h| (h.start, h.end));
+
+    let mut text = String::with_capacity(ours.len().max(theirs.len()));
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let mut i = 0;
+    while i < hunks.len() {
+        // Gather every hunk that overlaps or touches this one into one region
+        let start = hunks[i].start;
+        let mut end = hunks[i].end;
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].start <= end {
+            end = end.max(hunks[j].end);
+            j += 1;
+        }
+        let region = &hunks[i..j];
+        text.extend(base_lines[pos..start].iter().copied());
+
+        let has_ours = region.iter().any(|h| h.ours);
+        let has_theirs = region.iter().any(|h| !h.ours);
+        let our_text = side_text(&base_lines, region, start, end, true);
+        let their_text = side_text(&base_lines, region, start, end, false);
+        if !has_theirs || our_text == their_text {
+            text.push_str(&our_text);
+        } else if !has_ours {
+            text.push_str(&their_text);
+        } else {
+            conflicts += 1;
+            push_line(&mut text, &format!("{} {}", OURS_MARKER, ours_label), newline);
+            push_block(&mut text, &our_text, newline);
+            push_line(&mut text, SEPARATOR_MARKER, newline);
+            push_block(&mut text, &their_text, newline);
+            push_line(&mut text, &format!("{} {}", THEIRS_MARKER, theirs_label), newline);
+        }
+
+        pos = end;
+        i = j;
+    }
+    text.extend(base_lines[pos..].iter().copied());
+
+    MergeResult { text, conflicts }
+}
+
+fn side_hunks<'a>(base: &[&str], side: &'a [&'a str], ours: bool) -> Vec<Hunk<'a>> {
+    TextDiff::from_slices(base, side)
+        .ops()
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .map(|op| Hunk {
+            start: op.old_range().start,
+            end: op.old_range().end,
+            lines: &side[op.new_range()],
+            ours,
+        })
+        .collect()
+}
+
+/// What one side turned base lines `start..end` into.
+fn side_text(base: &[&str], region: &[Hunk], start: usize, end: usize, ours: bool) -> String {
+    let mut out = String::new();
+    let mut pos = start;
+    for hunk in region.iter().filter(|h| h.ours == ours) {
+        out.extend(base[pos..hunk.start].iter().copied());
+        out.extend(hunk.lines.iter().copied());
+        pos = hunk.end;
+    }
+    out.extend(base[pos..end].iter().copied());
+    out
+}
+
+fn line_ending(text: &str) -> &'static str {
+    if text.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+fn push_block(text: &mut String, block: &str, newline: &str) {
+    text.push_str(block);
+    if !block.is_empty() && !block.ends_with('\n') {
+        text.push_str(newline);
+    }
+}
+
+fn push_line(text: &mut String, line: &str, newline: &str) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push_str(newline);
+    }
+    text.push_str(line);
+    text.push_str(newline);
+}
+
+/// A conflict block in text, by line number. `ours` and `theirs` are the line ranges
+/// between the markers; a diff3 base section, if any, is dropped when resolving.
+pub struct Conflict {
+    pub start: usize,
+    pub end: usize,
+    pub ours: Range<usize>,
+    pub theirs: Range<usize>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+/// Finds complete conflict blocks. Markers that don't form a full block are left alone.
+pub fn find_conflicts(text: &str) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    // Cheap check first, since this runs on every frame
+    if !text.contains(OURS_MARKER) {
+        return conflicts;
+    }
+    let mut start = None;
+    let mut base = None;
+    let mut separator = None;
+    for (i, line) in text.lines().enumerate() {
+        if is_marker(line, OURS_MARKER) {
+            (start, base, separator) = (Some(i), None, None);
+        } else if is_marker(line, BASE_MARKER) && start.is_some() && separator.is_none() {
+            base = Some(i);
+        } else if is_marker(line, SEPARATOR_MARKER) && start.is_some() && separator.is_none() {
+            separator = Some(i);
+        } else if is_marker(line, THEIRS_MARKER) {
+            if let (Some(s), Some(sep)) = (start, separator) {
+                conflicts.push(Conflict { start: s, end: i + 1, ours: s + 1..base.unwrap_or(sep), theirs: sep + 1..i });
+            }
+            (start, base, separator) = (None, None, None);
+        }
+    }
+    conflicts
+}
+
+/// Whether `line` is `marker` on its own or followed by a label, so that longer runs such
+/// as a `========` heading underline aren't taken for one.
+fn is_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+}
+
+/// `text` with `conflict` replaced by the side(s) picked.
+pub fn resolve(text: &str, conflict: &Conflict, resolution: Resolution) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let newline = line_ending(text);
+    let mut out = String::with_capacity(text.len());
+    out.extend(lines[..conflict.start].iter().copied());
+    if matches!(resolution, Resolution::Ours | Resolution::Both) {
+        push_lines(&mut out, &lines[conflict.ours.clone()], newline);
+    }
+    if matches!(resolution, Resolution::Theirs | Resolution::Both) {
+        push_lines(&mut out, &lines[conflict.theirs.clone()], newline);
+    }
+    out.extend(lines[conflict.end.min(lines.len())..].iter().copied());
+    out
+}
+
+/// `text` with every conflict resolved the same way.
+pub fn resolve_all(text: &str, resolution: Resolution) -> String {
+    let mut text = text.to_string();
+    // Back to front so earlier line numbers stay valid
+    for conflict in find_conflicts(&text).iter().rev() {
+        text = resolve(&text, conflict, resolution);
+    }
+    text
+}
+
+fn push_lines(out: &mut String, lines: &[&str], newline: &str) {
+    for line in lines {
+        push_block(out, line, newline);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    #[test]
+    fn takes_changes_made_on_one_side() {
+        let ours = "one\nTWO\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+        let merged = merge3(BASE, ours, theirs, "Mine", "Disk");
+        assert_eq!(merged.text, "one\nTWO\nthree\nfour\nFIVE\nsix\n");
+        assert_eq!(merged.conflicts, 0);
+
+        // Either side alone, or both making the same change, merges cleanly too
+        assert_eq!(merge3(BASE, ours, BASE, "a", "b").text, ours);
+        assert_eq!(merge3(BASE, BASE, theirs, "a", "b").text, theirs);
+        assert_eq!(merge3(BASE, ours, ours, "a", "b").text, ours);
+        assert_eq!(merge3(BASE, BASE, BASE, "a", "b").text, BASE);
+    }
+
+    #[test]
+    fn deletions_merge_cleanly() {
+        let ours = "one\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\n";
+        let merged = merge3(BASE, ours, theirs, "a", "b");
+        assert_eq!(merged.text, "one\nthree\nfour\n");
+        assert_eq!(merged.conflicts, 0);
+    }
+
+    #[test]
+    fn overlapping_edits_conflict() {
+        let ours = "one\nmine\nthree\nfour\nfive\n";
+        let theirs = "one\ntheirs\nthree\nfour\nfive\n";
+        let merged = merge3(BASE, ours, theirs, "Mine", "Disk");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "one\n<<<<<<< Mine\nmine\n=======\ntheirs\n>>>>>>> Disk\nthree\nfour\nfive\n"
+        );
+    }
+
+    #[test]
+    fn conflict_without_trailing_newline() {
+        let merged = merge3("a\nb", "a\nours", "a\ntheirs", "Mine", "Disk");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(merged.text, "a\n<<<<<<< Mine\nours\n=======\ntheirs\n>>>>>>> Disk\n");
+    }
+
+    #[test]
+    fn crlf_conflicts_keep_crlf_markers() {
+        let base = BASE.replace('\n', "\r\n");
+        let ours = "one\r\nTWO\r\nthree\r\nfour\r\nfive\r\n";
+        let theirs = "one\r\ntwo\r\nthree\r\nfour\r\nFIVE\r\n";
+        let merged = merge3(&base, ours, theirs, "Mine", "Disk");
+        assert_eq!(merged.text, "one\r\nTWO\r\nthree\r\nfour\r\nFIVE\r\n");
+
+        let merged = merge3(&base, "one\r\nmine\r\nthree\r\nfour\r\nfive\r\n", "one\r\ndisk\r\nthree\r\nfour\r\nFIVE\r\n", "Mine", "Disk");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(merged.text, "one\r\n<<<<<<< Mine\r\nmine\r\n=======\r\ndisk\r\n>>>>>>> Disk\r\nthree\r\nfour\r\nFIVE\r\n");
+    }
+
+    #[test]
+    fn separate_conflicts_are_counted() {
+        let ours = "1\ntwo\nthree\nfour\n5\n";
+        let theirs = "uno\ntwo\nthree\nfour\ncinco\n";
+        let merged = merge3(BASE, ours, theirs, "a", "b");
+        assert_eq!(merged.conflicts, 2);
+        assert_eq!(find_conflicts(&merged.text).len(), 2);
+    }
+
+    #[test]
+    fn finds_conflicts_by_line() {
+        let text = "keep\n<<<<<<< HEAD\nours 1\nours 2\n=======\ntheirs\n>>>>>>> branch\nend\n";
+        let conflicts = find_conflicts(text);
+        assert_eq!(conflicts.len(), 1);
+        let conflict = &conflicts[0];
+        assert_eq!((conflict.start, conflict.end), (1, 7));
+        assert_eq!(conflict.ours, 2..4);
+        assert_eq!(conflict.theirs, 5..6);
+    }
+
+    #[test]
+    fn finds_diff3_conflicts_and_skips_broken_ones() {
+        let text = "<<<<<<< ours\na\n||||||| base\nb\n=======\nc\n>>>>>>> theirs\n<<<<<<< dangling\nx\n";
+        let conflicts = find_conflicts(text);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].ours, 1..2);
+        assert_eq!(conflicts[0].theirs, 5..6);
+        assert!(find_conflicts("no markers here\n").is_empty());
+        assert!(find_conflicts("<<<<<<< a\nb\n>>>>>>> c\n").is_empty());
+    }
+
+    #[test]
+    fn longer_runs_are_not_markers() {
+        let text = "<<<<<<< ours\nTitle\n========\n=======\n>>>>>>>> not a marker\nTitle\n=======x\n>>>>>>>\n";
+        let conflicts = find_conflicts(text);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].ours, 1..3);
+        assert_eq!(conflicts[0].theirs, 4..7);
+        assert_eq!(resolve(text, &conflicts[0], Resolution::Ours), "Title\n========\n");
+    }
+
+    #[test]
+    fn resolves_each_way() {
+        let text = "keep\n<<<<<<< HEAD\nours\n||||||| base\nold\n=======\ntheirs\n>>>>>>> branch\nend\n";
+        let conflict = &find_conflicts(text)[0];
+        assert_eq!(resolve(text, conflict, Resolution::Ours), "keep\nours\nend\n");
+        assert_eq!(resolve(text, conflict, Resolution::Theirs), "keep\ntheirs\nend\n");
+        assert_eq!(resolve(text, conflict, Resolution::Both), "keep\nours\ntheirs\nend\n");
+    }
+
+    #[test]
+    fn resolves_all_conflicts() {
+        let merged = merge3(BASE, "1\ntwo\nthree\nfour\n5\n", "uno\ntwo\nthree\nfour\ncinco\n", "a", "b");
+        assert_eq!(resolve_all(&merged.text, Resolution::Theirs), "uno\ntwo\nthree\nfour\ncinco\n");
+        assert_eq!(resolve_all(&merged.text, Resolution::Ours), "1\ntwo\nthree\nfour\n5\n");
+    }
+
+    #[test]
+    fn resolving_keeps_crlf() {
+        let text = "one\r\n<<<<<<< Mine\r\nmine\r\n=======\r\ndisk\r\n>>>>>>> Disk\r\nend";
+        let conflict = &find_conflicts(text)[0];
+        assert_eq!(conflict.ours, 2..3);
+        assert_eq!(resolve(text, conflict, Resolution::Theirs), "one\r\ndisk\r\nend");
+        let text = "<<<<<<< Mine\r\nmine\r\n=======\r\ndisk\r\n>>>>>>> Disk";
+        assert_eq!(resolve(text, &find_conflicts(text)[0], Resolution::Both), "mine\r\ndisk\r\n");
+    }
+}
@@ -0,0 +1,101 @@
+use eframe::egui;
+use std::path::PathBuf;
+
+use crate::merge::{self, Resolution};
+
+pub enum MergeOutcome {
+    /// The merged text, with no conflicts left.
+    Complete(String),
+    Cancel,
+}
+
+/// Three panes for resolving a file's conflicts: our version and their version on either
+/// side, and the editable result in the middle.
+pub struct MergeEditor {
+    pub path: PathBuf,
+    ours: String,
+    theirs: String,
+    result: String,
+}
+
+impl MergeEditor {
+    /// Starts from `text` as it is, conflict markers and all.
+    pub fn new(path: PathBuf, text: &str) -> Self {
+        Self {
+            path,
+            ours: merge::resolve_all(text, Resolution::Ours),
+            theirs: merge::resolve_all(text, Resolution::Theirs),
+            result: text.to_string(),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<MergeOutcome> {
+        let conflicts = merge::find_conflicts(&self.result);
+        let mut outcome = None;
+
+        ui.horizontal(|ui| {
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+            ui.strong(format!("Merging {}", name));
+            if conflicts.is_empty() {
+                ui.label("No conflicts left");
+            } else {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("{} conflicts left", conflicts.len()));
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("Cancel").clicked() {
+                    outcome = Some(MergeOutcome::Cancel);
+                }
+                let complete = ui
+                    .add_enabled(conflicts.is_empty(), egui::Button::new("Complete Merge"))
+                    .on_hover_text("Saves the result and marks the file as resolved");
+                if complete.clicked() {
+                    outcome = Some(MergeOutcome::Complete(self.result.clone()));
+                }
+                if ui.button("Accept All Theirs").clicked() {
+                    self.result = merge::resolve_all(&self.result, Resolution::Theirs);
+                }
+                if ui.button("Accept All Ours").clicked() {
+                    self.result = merge::resolve_all(&self.result, Resolution::Ours);
+                }
+            });
+        });
+
+        // At most one conflict is resolved per frame, so the line numbers are always fresh
+        let mut resolve = None;
+        for (index, conflict) in conflicts.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Conflict at line {}:", conflict.start + 1));
+                for (label, resolution) in [
+                    ("Accept Ours", Resolution::Ours),
+                    ("Accept Theirs", Resolution::Theirs),
+                    ("Accept Both", Resolution::Both),
+                ] {
+                    if ui.small_button(label).clicked() {
+                        resolve = Some((index, resolution));
+                    }
+                }
+            });
+        }
+        if let Some((index, resolution)) = resolve {
+            self.result = merge::resolve(&self.result, &conflicts[index], resolution);
+        }
+        ui.separator();
+
+        ui.columns(3, |columns| {
+            pane(&mut columns[0], "Ours (current)", &mut self.ours.as_str());
+            pane(&mut columns[1], "Result", &mut self.result);
+            pane(&mut columns[2], "Theirs (incoming)", &mut self.theirs.as_str());
+        });
+        outcome
+    }
+}
+
+fn pane(ui: &mut egui::Ui, label: &str, text: &mut dyn egui::TextBuffer) {
+    ui.weak(label);
+    egui::ScrollArea::both()
+        .id_source(("merge_editor", label))
+        .auto_shrink([false, false])
+        .show(ui, |ui| {
+            ui.add(egui::TextEdit::multiline(text).font(egui::TextStyle::Monospace).desired_width(f32::INFINITY));
+        });
+}
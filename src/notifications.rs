@@ -0,0 +1,77 @@
+use eframe::egui;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+struct Toast {
+    level: Level,
+    message: String,
+    created: Instant,
+}
+
+/// Short-lived messages stacked in the bottom-right corner of the window.
+#[derive(Default)]
+pub struct Notifications {
+    toasts: Vec<Toast>,
+}
+
+impl Notifications {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Level::Info, message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(Level::Error, message.into());
+    }
+
+    fn push(&mut self, level: Level, message: String) {
+        self.toasts.push(Toast { level, message, created: Instant::now() });
+    }
+
+    fn lifetime(level: Level) -> Duration {
+        match level {
+            Level::Info => Duration::from_secs(4),
+            Level::Error => Duration::from_secs(10),
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        self.toasts.retain(|t| t.created.elapsed() < Self::lifetime(t.level));
+        if self.toasts.is_empty() {
+            return;
+        }
+
+        let mut dismissed = None;
+        egui::Area::new("notifications")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -10.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (i, toast) in self.toasts.iter().enumerate() {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(320.0);
+                        ui.horizontal(|ui| {
+                            let (icon, color) = match toast.level {
+                                Level::Info => ("ℹ", ui.visuals().text_color()),
+                                Level::Error => ("⚠", ui.visuals().error_fg_color),
+                            };
+                            ui.colored_label(color, icon);
+                            ui.label(&toast.message);
+                            if ui.small_button("✖").clicked() {
+                                dismissed = Some(i);
+                            }
+                        });
+                    });
+                }
+            });
+        if let Some(i) = dismissed {
+            self.toasts.remove(i);
+        }
+
+        // Keep repainting so toasts disappear even when nothing else is happening
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+}
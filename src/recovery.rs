@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::save;
+
+/// A copy of a dirty buffer written to the recovery directory so a crash doesn't lose it.
+#[derive(Serialize, Deserialize)]
+pub struct SwapFile {
+    pub original_path: Option<PathBuf>,
+    pub content: String,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    /// The process that wrote it; its swaps are off limits while it runs.
+    #[serde(default)]
+    pub pid: u32,
+}
+
+impl SwapFile {
+    pub fn new(original_path: Option<PathBuf>, content: String) -> Self {
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Self { original_path, content, saved_at, pid: std::process::id() }
+    }
+
+    pub fn title(&self) -> String {
+        self.original_path
+            .as_ref()
+            .map_or_else(|| "Untitled".to_string(), |p| p.display().to_string())
+    }
+
+    pub fn age(&self) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let secs = now.saturating_sub(self.saved_at);
+        match secs {
+            0..=59 => "just now".to_string(),
+            60..=3599 => format!("{} min ago", secs / 60),
+            3600..=86399 => format!("{} h ago", secs / 3600),
+            _ => format!("{} days ago", secs / 86400),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AutosaveMode {
+    Off,
+    OnFocusLost,
+    AfterDelay,
+}
+
+pub fn recovery_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("hydroxite").join("recovery"))
+}
+
+/// Where the swap file for `original` lives. Swaps are keyed by process too, so two running
+/// editors with the same file open don't overwrite each other's.
+pub fn swap_path_for(original: Option<&Path>) -> Option<PathBuf> {
+    let name = match original {
+        Some(path) => {
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            format!("{:016x}-{}.json", hasher.finish(), std::process::id())
+        }
+        None => format!("untitled-{}.json", std::process::id()),
+    };
+    recovery_dir().map(|dir| dir.join(name))
+}
+
+/// A lock held for as long as this process runs, telling other instances that its swaps
+/// are still in use.
+static SESSION_LOCK: OnceLock<Option<File>> = OnceLock::new();
+
+fn lock_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.lock", pid))
+}
+
+fn claim_session(dir: &Path) {
+    SESSION_LOCK.get_or_init(|| {
+        let file = File::create(lock_path(dir, std::process::id())).ok()?;
+        file.try_lock().ok()?;
+        Some(file)
+    });
+}
+
+/// Whether the process that wrote a swap is still running. A lock file nobody holds is
+/// left over from a crash and is cleaned up.
+fn owner_alive(dir: &Path, pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    let path = lock_path(dir, pid);
+    let Ok(file) = File::open(&path) else {
+        return false;
+    };
+    match file.try_lock() {
+        Ok(()) => {
+            drop(file);
+            let _ = fs::remove_file(&path);
+            false
+        }
+        Err(_) => true,
+    }
+}
+
+pub fn write_swap(path: &Path, swap: &SwapFile) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+        claim_session(dir);
+    }
+    let json = serde_json::to_vec(swap).map_err(io::Error::other)?;
+    save::atomic_write(path, &json)
+}
+
+pub fn read_swap(path: &Path) -> io::Result<SwapFile> {
+    let json = fs::read(path)?;
+    serde_json::from_slice(&json).map_err(io::Error::other)
+}
+
+/// Swap files left behind by sessions that didn't exit cleanly, newest first. Swaps of
+/// other instances that are still running are left out.
+pub fn list_swaps() -> Vec<(PathBuf, SwapFile)> {
+    recovery_dir().map_or_else(Vec::new, |dir| list_swaps_in(&dir))
+}
+
+fn list_swaps_in(dir: &Path) -> Vec<(PathBuf, SwapFile)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut swaps: Vec<(PathBuf, SwapFile)> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|p| read_swap(&p).ok().map(|swap| (p, swap)))
+        .filter(|(_, swap)| !owner_alive(dir, swap.pid))
+        .collect();
+    swaps.sort_by_key(|(_, swap)| std::cmp::Reverse(swap.saved_at));
+    swaps
+}
+
+/// Deletes a swap listed by `list_swaps`, unless its owner has started running since.
+pub fn discard_swap(path: &Path) {
+    let live = path
+        .parent()
+        .zip(read_swap(path).ok())
+        .is_some_and(|(dir, swap)| owner_alive(dir, swap.pid));
+    if !live {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, pid: u32, saved_at: u64) {
+        let swap = SwapFile { original_path: None, content: name.to_string(), saved_at, pid };
+        fs::write(dir.join(name), serde_json::to_vec(&swap).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn lists_swaps_of_exited_sessions_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "old.json", 1_000_001, 10);
+        write(dir.path(), "new.json", 1_000_002, 20);
+        // A lock left behind by a crash doesn't keep its swaps hidden, and is cleaned up
+        File::create(lock_path(dir.path(), 1_000_001)).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a swap").unwrap();
+
+        let swaps = list_swaps_in(dir.path());
+        let names: Vec<&str> = swaps.iter().map(|(_, swap)| swap.content.as_str()).collect();
+        assert_eq!(names, ["new.json", "old.json"]);
+        assert!(!lock_path(dir.path(), 1_000_001).exists());
+    }
+
+    #[test]
+    fn swaps_of_running_sessions_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "mine.json", std::process::id(), 10);
+        write(dir.path(), "running.json", 1_000_003, 20);
+        let lock = File::create(lock_path(dir.path(), 1_000_003)).unwrap();
+        lock.try_lock().unwrap();
+        assert!(list_swaps_in(dir.path()).is_empty());
+
+        discard_swap(&dir.path().join("running.json"));
+        assert!(dir.path().join("running.json").exists());
+        drop(lock);
+        discard_swap(&dir.path().join("running.json"));
+        assert!(!dir.path().join("running.json").exists());
+    }
+
+    #[test]
+    fn swaps_from_before_owners_were_recorded_are_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("legacy.json"), r#"{"original_path":null,"content":"x","saved_at":5}"#).unwrap();
+        assert_eq!(list_swaps_in(dir.path()).len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to `path` without ever leaving a truncated file behind.
+///
+/// The data goes to a temporary file next to `path` which is then renamed over it, so a
+/// full disk or a crash mid-write leaves the original untouched. The original's permissions
+/// and (on Unix) owner are carried over to the new file.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // Replace the file a symlink points at rather than the link itself
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+    if original.as_ref().is_some_and(|meta| meta.permissions().readonly()) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "file is read-only"));
+    }
+
+    let tmp_path = temp_path_for(&path)?;
+    let result = write_temp(&tmp_path, contents, original.as_ref()).and_then(|_| fs::rename(&tmp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Overwrites `path` in place, for files `atomic_write` can't replace: ones we may write
+/// but not own, or that sit in a folder we can't create files in.
+///
+/// A copy of the original is made in the temporary folder first and only removed once the
+/// new contents are safely on disk. If the write fails, the error says where the copy is.
+pub fn overwrite_in_place(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let backup = std::env::temp_dir().join(format!("{}.hydroxite-{}.bak", name, std::process::id()));
+    fs::copy(&path, &backup)?;
+    let result = File::options()
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()));
+    match result {
+        Ok(()) => {
+            let _ = fs::remove_file(&backup);
+            Ok(())
+        }
+        Err(e) => Err(io::Error::new(e.kind(), format!("{} (the original is kept at {})", e, backup.display()))),
+    }
+}
+
+fn temp_path_for(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Ok(dir.join(format!(".{}.hydroxite-{}.tmp", name.to_string_lossy(), std::process::id())))
+}
+
+fn write_temp(tmp_path: &Path, contents: &[u8], original: Option<&fs::Metadata>) -> io::Result<()> {
+    let mut file = File::create(tmp_path)?;
+    file.write_all(contents)?;
+    if let Some(meta) = original {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            std::os::unix::fs::chown(tmp_path, Some(meta.uid()), Some(meta.gid()))?;
+        }
+        fs::set_permissions(tmp_path, meta.permissions())?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_contents_and_leaves_no_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "old contents that are longer").unwrap();
+        atomic_write(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn creates_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.txt");
+        atomic_write(&path, b"hello").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn refuses_read_only_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ro.txt");
+        fs::write(&path, "keep").unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        let error = atomic_write(&path, b"lost").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep");
+    }
+
+    #[test]
+    fn overwrites_in_place_and_drops_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in-place.txt");
+        fs::write(&path, "old contents that are longer").unwrap();
+        overwrite_in_place(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let backup = std::env::temp_dir().join(format!("in-place.txt.hydroxite-{}.bak", std::process::id()));
+        assert!(!backup.exists());
+        assert!(overwrite_in_place(&dir.path().join("missing.txt"), b"x").is_err());
+    }
+
+    /// A folder we can't create files in, holding a file we can still write to.
+    #[cfg(unix)]
+    #[test]
+    fn locked_folders_are_not_written_behind_our_back() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        let path = locked.join("a.txt");
+        fs::write(&path, "keep").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        // Root can create files anywhere, so there is nothing to refuse
+        if File::create(locked.join("probe")).is_ok() {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+        let error = atomic_write(&path, b"new").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep");
+        overwrite_in_place(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.sh");
+        let link = dir.path().join("link.sh");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        atomic_write(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o750);
+    }
+}
@@ -0,0 +1,510 @@
+use eframe::egui;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::git::{self, Change, FileDiff, FileStatus, GitTracker, RepoStatus, Stash};
+use crate::notifications::Notifications;
+
+/// Something the panel needs the editor to do.
+pub enum PanelEvent {
+    Open(PathBuf),
+    /// The repository was changed and its status should be re-read.
+    RepoChanged,
+    /// Check out `branch` in the repository at `repo`, once unsaved changes are dealt with.
+    SwitchBranch { repo: PathBuf, branch: String },
+}
+
+/// Branches and stashes of one repository at one revision.
+struct Refs {
+    repo: PathBuf,
+    revision: u64,
+    branches: Vec<String>,
+    stashes: Vec<Stash>,
+}
+
+/// What the background threads report back.
+enum Update {
+    Refs(Refs),
+    /// A change to the repository finished. `what` names it for an error message.
+    Done { what: String, result: Result<(), String>, committed: bool },
+}
+
+/// State of the Source Control side panel. Branches and stashes are read in the background
+/// whenever the repository changes, and commits, staging and stashing run there too, since
+/// hooks or a signing prompt can hold git up for a long time. Diffs are read on demand and
+/// kept until the repository changes.
+pub struct SourceControl {
+    repo: Option<PathBuf>,
+    message: String,
+    amend: bool,
+    new_branch: String,
+    /// Files whose hunks are shown, as (path, staged).
+    expanded: HashSet<(PathBuf, bool)>,
+    diffs: HashMap<(PathBuf, bool), Result<FileDiff, String>>,
+    branches: Vec<String>,
+    stashes: Vec<Stash>,
+    /// The repository and revision the branches and stashes were last asked for.
+    refs_key: Option<(PathBuf, u64)>,
+    refs_loading: bool,
+    /// What the change to the repository in flight does, if one is running.
+    busy: Option<String>,
+    /// Changes that finished since the panel was last shown.
+    finished: Vec<(String, Result<(), String>)>,
+    /// The stash waiting for the user to confirm dropping it.
+    confirm_drop: Option<Stash>,
+    tx: Sender<Update>,
+    rx: Receiver<Update>,
+    ctx: egui::Context,
+}
+
+impl SourceControl {
+    pub fn new(ctx: &egui::Context) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            repo: None,
+            message: String::new(),
+            amend: false,
+            new_branch: String::new(),
+            expanded: HashSet::new(),
+            diffs: HashMap::new(),
+            branches: Vec::new(),
+            stashes: Vec::new(),
+            refs_key: None,
+            refs_loading: false,
+            busy: None,
+            finished: Vec::new(),
+            confirm_drop: None,
+            tx,
+            rx,
+            ctx: ctx.clone(),
+        }
+    }
+
+    /// Picks up what the background threads sent.
+    fn receive(&mut self) {
+        for update in self.rx.try_iter() {
+            match update {
+                Update::Refs(refs) => {
+                    if self.refs_key.as_ref().is_some_and(|(r, v)| *r == refs.repo && *v == refs.revision) {
+                        self.branches = refs.branches;
+                        self.stashes = refs.stashes;
+                        self.refs_loading = false;
+                    }
+                }
+                Update::Done { what, result, committed } => {
+                    if committed && result.is_ok() {
+                        self.message.clear();
+                        self.amend = false;
+                    }
+                    self.busy = None;
+                    self.finished.push((what, result));
+                }
+            }
+        }
+    }
+
+    /// Runs `change` on the repository in the background. Only one runs at a time, since
+    /// git locks the index anyway.
+    fn run(&mut self, what: &str, change: impl FnOnce() -> Result<(), String> + Send + 'static) {
+        self.run_change(what, false, change);
+    }
+
+    fn run_change(&mut self, what: &str, committed: bool, change: impl FnOnce() -> Result<(), String> + Send + 'static) {
+        if self.busy.is_some() {
+            return;
+        }
+        self.busy = Some(what.to_string());
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let what = what.to_string();
+        std::thread::spawn(move || {
+            let result = change();
+            if tx.send(Update::Done { what, result, committed }).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Picks up branches and stashes read in the background, and starts a new read if the
+    /// repository changed since the last one.
+    fn update_refs(&mut self, repo: &Path, revision: u64) {
+        self.receive();
+        let key = (repo.to_path_buf(), revision);
+        if self.refs_key.as_ref() == Some(&key) {
+            return;
+        }
+        if self.refs_key.as_ref().is_some_and(|(r, _)| r != repo) {
+            // Another repository; don't offer the old one's branches meanwhile
+            self.branches.clear();
+            self.stashes.clear();
+        }
+        self.refs_key = Some(key);
+        self.refs_loading = true;
+        self.diffs.clear();
+
+        let tx = self.tx.clone();
+        let ctx = self.ctx.clone();
+        let repo = repo.to_path_buf();
+        std::thread::spawn(move || {
+            let branches = git::branches(&repo).unwrap_or_default();
+            let stashes = git::stashes(&repo).unwrap_or_default();
+            if tx.send(Update::Refs(Refs { repo, revision, branches, stashes })).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, git: &GitTracker, notifications: &mut Notifications) -> Vec<PanelEvent> {
+        ui.heading("Source Control");
+        let repos = git.repos();
+        if repos.is_empty() {
+            ui.weak("No git repository in the open folders");
+            return Vec::new();
+        }
+        if !self.repo.as_ref().is_some_and(|r| repos.iter().any(|repo| &repo.root == r)) {
+            self.repo = Some(repos[0].root.clone());
+        }
+        if repos.len() > 1 {
+            let selected = self.repo.clone().unwrap_or_default();
+            egui::ComboBox::from_id_source("source_control_repo")
+                .selected_text(repo_name(&selected))
+                .show_ui(ui, |ui| {
+                    for repo in &repos {
+                        ui.selectable_value(&mut self.repo, Some(repo.root.clone()), repo_name(&repo.root));
+                    }
+                });
+        }
+        let Some(repo) = repos.iter().copied().find(|r| Some(&r.root) == self.repo.as_ref()) else {
+            return Vec::new();
+        };
+
+        self.update_refs(&repo.root, git.revision());
+
+        let mut changed = false;
+        for (what, result) in self.finished.drain(..) {
+            match result {
+                Ok(()) => changed = true,
+                Err(e) => notifications.error(format!("Failed to {}: {}", what, e)),
+            }
+        }
+        if let Some(what) = &self.busy {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.weak(format!("Running {}…", what));
+            });
+        }
+        let mut opened = None;
+        let switch_to = self.show_branches(ui, repo);
+        ui.separator();
+        self.show_commit_box(ui, repo);
+        ui.separator();
+
+        egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+            let staged: Vec<&Change> = repo.changes.iter().filter(|c| c.staged.is_some()).collect();
+            let unstaged: Vec<&Change> = repo.changes.iter().filter(|c| c.unstaged.is_some()).collect();
+            if staged.is_empty() && unstaged.is_empty() {
+                ui.weak("No changes");
+            }
+            if !staged.is_empty() {
+                ui.strong("Staged Changes");
+                for change in staged {
+                    if let Some(open) = self.show_change(ui, repo, change, true) {
+                        opened = Some(open);
+                    }
+                }
+            }
+            if !unstaged.is_empty() {
+                ui.strong("Changes");
+                for change in unstaged {
+                    if let Some(open) = self.show_change(ui, repo, change, false) {
+                        opened = Some(open);
+                    }
+                }
+            }
+            ui.separator();
+            self.show_stashes(ui, repo);
+        });
+        let mut events: Vec<PanelEvent> = opened.map(PanelEvent::Open).into_iter().collect();
+        if changed {
+            events.push(PanelEvent::RepoChanged);
+        }
+        if let Some(branch) = switch_to {
+            events.push(PanelEvent::SwitchBranch { repo: repo.root.clone(), branch });
+        }
+        events
+    }
+
+    /// The branch picker and new-branch box. Returns the branch picked to switch to.
+    fn show_branches(&mut self, ui: &mut egui::Ui, repo: &RepoStatus) -> Option<String> {
+        let mut switch_to = None;
+        ui.horizontal(|ui| {
+            ui.label("⎇");
+            let mut selected = repo.branch.clone();
+            egui::ComboBox::from_id_source("source_control_branch")
+                .selected_text(&repo.branch)
+                .show_ui(ui, |ui| {
+                    for branch in &self.branches {
+                        ui.selectable_value(&mut selected, branch.clone(), branch);
+                    }
+                });
+            if self.refs_loading {
+                ui.spinner();
+            }
+            if selected != repo.branch {
+                switch_to = Some(selected);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.new_branch).hint_text("New branch").desired_width(120.0));
+            let name = self.new_branch.trim().to_string();
+            if ui.add_enabled(!name.is_empty() && self.busy.is_none(), egui::Button::new("Create")).clicked() {
+                let root = repo.root.clone();
+                self.run("create branch", move || git::create_branch(&root, &name));
+                self.new_branch.clear();
+            }
+        });
+        switch_to
+    }
+
+    fn show_commit_box(&mut self, ui: &mut egui::Ui, repo: &RepoStatus) {
+        ui.add(
+            egui::TextEdit::multiline(&mut self.message)
+                .hint_text("Commit message")
+                .desired_rows(3)
+                .desired_width(f32::INFINITY),
+        );
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.amend, "Amend").changed() && self.amend && self.message.trim().is_empty() {
+                // Start from the commit being amended
+                self.message = git::last_commit_message(&repo.root).unwrap_or_default();
+            }
+            let has_staged = repo.changes.iter().any(|c| c.staged.is_some());
+            let can_commit = !self.message.trim().is_empty() && (has_staged || self.amend) && self.busy.is_none();
+            let label = if self.amend { "Amend Commit" } else { "Commit" };
+            if ui.add_enabled(can_commit, egui::Button::new(label)).clicked() {
+                let (root, message, amend) = (repo.root.clone(), self.message.clone(), self.amend);
+                // The message is only cleared once the commit has gone through
+                self.run_change("commit", true, move || git::commit(&root, &message, amend));
+            }
+        });
+    }
+
+    /// One changed file, with its hunks when expanded. Returns the file if it should be opened.
+    fn show_change(
+        &mut self,
+        ui: &mut egui::Ui,
+        repo: &RepoStatus,
+        change: &Change,
+        staged: bool,
+    ) -> Option<PathBuf> {
+        let status = if staged { change.staged } else { change.unstaged }?;
+        let key = (change.path.clone(), staged);
+        // Untracked files and folders have nothing to diff against
+        let has_hunks = !matches!(status, FileStatus::Untracked | FileStatus::Conflicted);
+        let expanded = self.expanded.contains(&key);
+        let mut open = None;
+
+        ui.horizontal(|ui| {
+            if has_hunks && ui.small_button(if expanded { "▼" } else { "▶" }).clicked() {
+                if expanded {
+                    self.expanded.remove(&key);
+                } else {
+                    self.expanded.insert(key.clone());
+                }
+            }
+            let name = change.path.strip_prefix(&repo.root).unwrap_or(&change.path).display().to_string();
+            let text = egui::RichText::new(name).color(status.color(ui.visuals()));
+            if ui.add(egui::Label::new(text).sense(egui::Sense::click())).on_hover_text(status.label()).clicked() {
+                open = Some(change.path.clone());
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.add_enabled_ui(self.busy.is_none(), |ui| {
+                    let (root, path) = (repo.root.clone(), change.path.clone());
+                    if staged {
+                        if ui.small_button("−").on_hover_text("Unstage").clicked() {
+                            self.run("unstage", move || git::unstage_path(&root, &path));
+                        }
+                    } else if ui.small_button("+").on_hover_text("Stage").clicked() {
+                        self.run("stage", move || git::stage_path(&root, &path));
+                    }
+                });
+            });
+        });
+
+        if has_hunks && expanded {
+            let diff = self
+                .diffs
+                .entry(key)
+                .or_insert_with(|| git::file_diff(&repo.root, &change.path, staged));
+            match diff {
+                Ok(diff) => {
+                    let mut apply = None;
+                    for (index, hunk) in diff.hunks.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.add_space(16.0);
+                            ui.monospace(&hunk.header);
+                            let label = if staged { "Unstage Hunk" } else { "Stage Hunk" };
+                            if ui.add_enabled(self.busy.is_none(), egui::Button::new(label).small()).clicked() {
+                                apply = Some(index);
+                            }
+                        });
+                        for line in &hunk.lines {
+                            let color = match line.chars().next() {
+                                Some('+') => FileStatus::Added.color(ui.visuals()),
+                                Some('-') => FileStatus::Deleted.color(ui.visuals()),
+                                _ => ui.visuals().text_color(),
+                            };
+                            ui.horizontal(|ui| {
+                                ui.add_space(16.0);
+                                ui.label(egui::RichText::new(line).monospace().color(color));
+                            });
+                        }
+                    }
+                    if let Some(index) = apply {
+                        let what = if staged { "unstage hunk" } else { "stage hunk" };
+                        let (root, diff) = (repo.root.clone(), diff.clone());
+                        self.run(what, move || git::apply_hunk(&root, &diff, index, staged));
+                    }
+                }
+                Err(e) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e.as_str());
+                }
+            }
+        }
+        open
+    }
+
+    fn show_stashes(&mut self, ui: &mut egui::Ui, repo: &RepoStatus) {
+        let idle = self.busy.is_none();
+        ui.horizontal(|ui| {
+            ui.strong("Stashes");
+            let has_changes = !repo.changes.is_empty();
+            if ui
+                .add_enabled(has_changes && idle, egui::Button::new("Stash Changes"))
+                .on_hover_text("Uses the commit message box as the stash message")
+                .clicked()
+            {
+                let (root, message) = (repo.root.clone(), self.message.clone());
+                self.run("stash", move || git::stash_push(&root, &message));
+            }
+        });
+        let mut command = None;
+        for stash in &self.stashes {
+            ui.horizontal(|ui| {
+                ui.label(&stash.message).on_hover_text(format!("{}: {}", stash.name(), stash.message));
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.add_enabled_ui(idle, |ui| {
+                        if ui.small_button("Drop").clicked() {
+                            self.confirm_drop = Some(stash.clone());
+                        }
+                        for (name, label) in [("pop", "Pop"), ("apply", "Apply")] {
+                            if ui.small_button(label).clicked() {
+                                command = Some((name, stash.clone()));
+                            }
+                        }
+                    });
+                });
+            });
+            if self.confirm_drop.as_ref().is_some_and(|s| s.id == stash.id) {
+                ui.horizontal(|ui| {
+                    ui.add_space(16.0);
+                    ui.colored_label(ui.visuals().warn_fg_color, "Drop this stash? Its changes will be lost.");
+                    if ui.add_enabled(idle, egui::Button::new("Drop").small()).clicked() {
+                        command = Some(("drop", stash.clone()));
+                    }
+                    if ui.small_button("Cancel").clicked() {
+                        self.confirm_drop = None;
+                    }
+                });
+            }
+        }
+        if let Some((name, stash)) = command {
+            self.confirm_drop = None;
+            let root = repo.root.clone();
+            self.run(&format!("{} stash", name), move || git::stash_command(&root, name, &stash));
+        }
+    }
+}
+
+fn repo_name(root: &Path) -> String {
+    root.file_name().map_or_else(|| root.display().to_string(), |n| n.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_refs(panel: &mut SourceControl, repo: &Path, revision: u64) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        panel.update_refs(repo, revision);
+        while panel.refs_loading && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            panel.update_refs(repo, revision);
+        }
+        assert!(!panel.refs_loading);
+    }
+
+    /// A fresh repository on branch `main` with one empty commit.
+    fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for args in [
+            &["init", "-q", "-b", "main"][..],
+            &["config", "user.name", "Test"],
+            &["config", "user.email", "test@example.com"],
+            &["config", "commit.gpgsign", "false"],
+            &["commit", "-q", "--allow-empty", "-m", "initial"],
+        ] {
+            git::git(dir.path(), args).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn reads_branches_and_stashes_in_the_background() {
+        let dir = repo();
+        let root = dir.path();
+        git::create_branch(root, "feature").unwrap();
+        git::switch_branch(root, "main").unwrap();
+        std::fs::write(root.join("wip.txt"), "work in progress").unwrap();
+        git::stash_push(root, "halfway there").unwrap();
+
+        let mut panel = SourceControl::new(&egui::Context::default());
+        wait_for_refs(&mut panel, root, 1);
+        assert_eq!(panel.branches, ["feature", "main"]);
+        assert_eq!(panel.stashes.len(), 1);
+        assert!(panel.stashes[0].message.ends_with("halfway there"));
+
+        // Nothing is re-read until the repository changes
+        git::create_branch(root, "later").unwrap();
+        panel.update_refs(root, 1);
+        assert!(!panel.refs_loading);
+        wait_for_refs(&mut panel, root, 2);
+        assert_eq!(panel.branches, ["feature", "later", "main"]);
+    }
+
+    #[test]
+    fn commits_in_the_background_one_change_at_a_time() {
+        let dir = repo();
+        let root = dir.path().to_path_buf();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        git::stage_path(&root, &root.join("a.txt")).unwrap();
+
+        let mut panel = SourceControl::new(&egui::Context::default());
+        panel.message = "Add a.txt".to_string();
+        let (commit_root, message) = (root.clone(), panel.message.clone());
+        panel.run_change("commit", true, move || git::commit(&commit_root, &message, false));
+        // Anything else waits until the commit is done
+        panel.run("stash", || Err("ran while a commit was running".to_string()));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while panel.busy.is_some() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            panel.receive();
+        }
+        assert_eq!(panel.finished, [("commit".to_string(), Ok(()))]);
+        assert!(panel.message.is_empty());
+        assert_eq!(git::last_commit_message(&root).unwrap(), "Add a.txt");
+    }
+}
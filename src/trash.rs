@@ -0,0 +1,206 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_ops;
+
+/// Something moved to the trash, with enough information to put it back.
+pub struct TrashedItem {
+    pub original: PathBuf,
+    pub trashed: PathBuf,
+    /// The freedesktop `.trashinfo` file describing it, if the platform uses one.
+    info: Option<PathBuf>,
+}
+
+impl TrashedItem {
+    /// Moves the item back to where it was deleted from.
+    pub fn restore(&self) -> io::Result<()> {
+        file_ops::rename(&self.trashed, &self.original)?;
+        if let Some(info) = &self.info {
+            let _ = fs::remove_file(info);
+        }
+        Ok(())
+    }
+}
+
+/// Moves `path` to the user's trash instead of deleting it.
+#[cfg(target_os = "macos")]
+pub fn move_to_trash(path: &Path) -> io::Result<TrashedItem> {
+    let trash = dirs::home_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?
+        .join(".Trash");
+    fs::create_dir_all(&trash)?;
+    // Made absolute without resolving symlinks, so a link is trashed rather than its target
+    let original = std::path::absolute(path)?;
+    let trashed = free_name(&trash, &original, |_| true)?;
+    file_ops::rename(&original, &trashed)?;
+    Ok(TrashedItem { original, trashed, info: None })
+}
+
+/// Moves `path` to the user's trash instead of deleting it, following the freedesktop.org
+/// trash specification so file managers can list and restore it too.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn move_to_trash(path: &Path) -> io::Result<TrashedItem> {
+    let trash = dirs::data_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?
+        .join("Trash");
+    move_to_trash_in(&trash, path)
+}
+
+/// Moves `path` into the freedesktop trash folder `trash`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn move_to_trash_in(trash: &Path, path: &Path) -> io::Result<TrashedItem> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let files = trash.join("files");
+    let info_dir = trash.join("info");
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info_dir)?;
+
+    let original = std::path::absolute(path)?;
+    // Creating the .trashinfo file with create_new reserves the name against other programs
+    let mut info_file = None;
+    let trashed = free_name(&files, &original, |candidate| {
+        let name = candidate.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let info = info_dir.join(format!("{}.trashinfo", name));
+        match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => {
+                info_file = Some((info, file));
+                true
+            }
+            Err(_) => false,
+        }
+    })?;
+    let (info, mut file) = info_file.expect("free_name only accepts names whose info file was created");
+
+    let result = write!(
+        file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&original),
+        deletion_date()
+    )
+    .and_then(|_| file_ops::rename(&original, &trashed));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&info);
+        return Err(e);
+    }
+    Ok(TrashedItem { original, trashed, info: Some(info) })
+}
+
+#[cfg(not(unix))]
+pub fn move_to_trash(_path: &Path) -> io::Result<TrashedItem> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "moving to the trash is not supported on this platform",
+    ))
+}
+
+/// The first "name", "name.2", "name.3", ... in `dir` that is unused and that `reserve` accepts.
+#[cfg(unix)]
+fn free_name(dir: &Path, original: &Path, mut reserve: impl FnMut(&Path) -> bool) -> io::Result<PathBuf> {
+    let name = original
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy()
+        .to_string();
+    for n in 1..10_000 {
+        let candidate = match n {
+            1 => dir.join(&name),
+            n => dir.join(format!("{}.{}", name, n)),
+        };
+        if fs::symlink_metadata(&candidate).is_err() && reserve(&candidate) {
+            return Ok(candidate);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free name in the trash"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn percent_encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut out = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// The current time as YYYY-MM-DDThh:mm:ss. The spec asks for local time, but without a
+/// time zone database UTC is the best we can do.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn deletion_date() -> String {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_name_skips_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join("a.txt.2"), "").unwrap();
+        let name = free_name(dir.path(), Path::new("/elsewhere/a.txt"), |_| true).unwrap();
+        assert_eq!(name, dir.path().join("a.txt.3"));
+        // A name `reserve` turns down is skipped too
+        let name = free_name(dir.path(), Path::new("b"), |p| !p.ends_with("b")).unwrap();
+        assert_eq!(name, dir.path().join("b.2"));
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn trashes_and_restores() {
+        let data = tempfile::tempdir().unwrap();
+        let work = tempfile::tempdir().unwrap();
+        let file = work.path().join("my notes.txt");
+        fs::write(&file, "keep me").unwrap();
+
+        let item = move_to_trash_in(&data.path().join("Trash"), &file).unwrap();
+        assert!(!file.exists());
+        assert_eq!(item.trashed, data.path().join("Trash/files/my notes.txt"));
+        let info = fs::read_to_string(data.path().join("Trash/info/my notes.txt.trashinfo")).unwrap();
+        assert!(info.contains(&format!("Path={}", percent_encode(&file))));
+        assert!(info.contains("%20notes.txt"));
+
+        item.restore().unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+        assert!(!data.path().join("Trash/info/my notes.txt.trashinfo").exists());
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn deletion_date_is_iso_like() {
+        let date = deletion_date();
+        assert_eq!(date.len(), 19);
+        assert_eq!(&date[4..5], "-");
+        assert_eq!(&date[10..11], "T");
+    }
+}
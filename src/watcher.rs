@@ -0,0 +1,89 @@
+use eframe::egui;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Watches the open folder and the directory of the open file for changes made by
+/// other programs, waking the UI whenever something happens. Watches are set up on a
+/// thread of their own, since watching a big folder recursively walks all of it.
+pub struct FsWatcher {
+    requests: Sender<Vec<(PathBuf, RecursiveMode)>>,
+    events: Receiver<notify::Event>,
+    watched: Vec<(PathBuf, RecursiveMode)>,
+}
+
+impl FsWatcher {
+    pub fn new(ctx: &egui::Context) -> notify::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let ctx = ctx.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if !matches!(event.kind, EventKind::Access(_)) && tx.send(event).is_ok() {
+                    ctx.request_repaint();
+                }
+            }
+        })?;
+        let (requests, queue) = mpsc::channel();
+        std::thread::spawn(move || watch_requested(watcher, queue));
+        Ok(Self { requests, events, watched: Vec::new() })
+    }
+
+    /// Replaces the set of watched paths. Paths that can't be watched are skipped.
+    pub fn set_watched(&mut self, paths: Vec<(PathBuf, RecursiveMode)>) {
+        if paths == self.watched {
+            return;
+        }
+        let _ = self.requests.send(paths.clone());
+        self.watched = paths;
+    }
+
+    /// Every path touched since the last call.
+    pub fn changed_paths(&mut self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.events.try_iter().flat_map(|event| event.paths).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+/// Applies each requested set of watches in turn, skipping to the latest when several
+/// are waiting. Ends, dropping the watcher, once the `FsWatcher` is gone.
+fn watch_requested(mut watcher: RecommendedWatcher, queue: Receiver<Vec<(PathBuf, RecursiveMode)>>) {
+    let mut watched: Vec<(PathBuf, RecursiveMode)> = Vec::new();
+    while let Ok(mut paths) = queue.recv() {
+        if let Some(latest) = queue.try_iter().last() {
+            paths = latest;
+        }
+        for (path, _) in &watched {
+            let _ = watcher.unwatch(path);
+        }
+        for (path, mode) in &paths {
+            let _ = watcher.watch(path, *mode);
+        }
+        watched = paths;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reports_changes_once_the_watch_thread_is_set_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut watcher = FsWatcher::new(&egui::Context::default()).unwrap();
+        watcher.set_watched(vec![(dir.path().to_path_buf(), RecursiveMode::Recursive)]);
+
+        // The watch is set up in the background, so keep touching the file until it shows up
+        let file = dir.path().join("a.txt");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut changed = Vec::new();
+        while changed.is_empty() && Instant::now() < deadline {
+            std::fs::write(&file, "a").unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            changed = watcher.changed_paths();
+        }
+        assert!(changed.iter().any(|p| p.ends_with("a.txt")), "{:?}", changed);
+    }
+}
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::file_tree::TreeFilter;
+use crate::format::FormatterConfig;
+use crate::recovery::AutosaveMode;
+use crate::save;
+
+pub const WORKSPACE_EXTENSION: &str = "hydroxite-workspace";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorkspaceFolder {
+    /// Relative to the workspace file on disk, absolute once loaded.
+    pub path: PathBuf,
+    /// Shown instead of the folder's own name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl WorkspaceFolder {
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.path
+                .file_name()
+                .map_or_else(|| self.path.display().to_string(), |n| n.to_string_lossy().to_string())
+        })
+    }
+}
+
+/// Settings a workspace carries with it. Anything left unset keeps the editor's own value.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct WorkspaceSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_filter: Option<TreeFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format_on_save: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatters: Option<FormatterConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autosave: Option<AutosaveMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autosave_delay_secs: Option<u64>,
+}
+
+/// The folders shown in the explorer. A folder opened on its own is a workspace without
+/// a file; one loaded from a `.hydroxite-workspace` file can hold several folders.
+#[derive(Default)]
+pub struct Workspace {
+    pub file: Option<PathBuf>,
+    pub folders: Vec<WorkspaceFolder>,
+    pub settings: WorkspaceSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorkspaceFile {
+    folders: Vec<WorkspaceFolder>,
+    #[serde(default)]
+    settings: WorkspaceSettings,
+}
+
+impl Workspace {
+    pub fn single(folder: PathBuf) -> Self {
+        Self { file: None, folders: vec![WorkspaceFolder { path: folder, name: None }], settings: Default::default() }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read(path)?;
+        let file: WorkspaceFile = serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let folders = file
+            .folders
+            .into_iter()
+            .map(|folder| WorkspaceFolder { path: normalize(&base.join(&folder.path)), name: folder.name })
+            .collect();
+        Ok(Self { file: Some(path.to_path_buf()), folders, settings: file.settings })
+    }
+
+    /// Writes the workspace to `path`, storing folders relative to it so the file can be
+    /// committed alongside the projects it lists.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        let base = std::path::absolute(path)?.parent().map(Path::to_path_buf).unwrap_or_default();
+        let file = WorkspaceFile {
+            folders: self
+                .folders
+                .iter()
+                .map(|folder| WorkspaceFolder { path: relative_to(&folder.path, &base), name: folder.name.clone() })
+                .collect(),
+            settings: self.settings.clone(),
+        };
+        let json = serde_json::to_vec_pretty(&file).map_err(io::Error::other)?;
+        save::atomic_write(path, &json)?;
+        self.file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folders.is_empty()
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &Path> {
+        self.folders.iter().map(|f| f.path.as_path())
+    }
+
+    /// The innermost workspace folder containing `path`.
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.roots()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+
+    /// The folder new files go in when nothing more specific was picked.
+    pub fn first_root(&self) -> Option<PathBuf> {
+        self.folders.first().map(|f| f.path.clone())
+    }
+
+    /// Adds `folder` unless it is already part of the workspace.
+    pub fn add_folder(&mut self, folder: PathBuf) -> bool {
+        if self.folders.iter().any(|f| f.path == folder) {
+            return false;
+        }
+        self.folders.push(WorkspaceFolder { path: folder, name: None });
+        true
+    }
+
+    pub fn remove_folder(&mut self, folder: &Path) {
+        self.folders.retain(|f| f.path != folder);
+    }
+
+    pub fn title(&self) -> Option<String> {
+        match (&self.file, self.folders.as_slice()) {
+            (Some(file), _) => file
+                .file_name()
+                .map(|n| n.to_string_lossy().trim_end_matches(&format!(".{}", WORKSPACE_EXTENSION)).to_string()),
+            (None, [folder]) => Some(folder.display_name()),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Removes `.` and `..` components without touching the filesystem, so a folder that
+/// doesn't exist yet still gets a sensible path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => {
+                out.pop();
+            }
+            // There's nothing above the root
+            Component::ParentDir if out.has_root() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `path` relative to `base`, going up with `..` where needed. Falls back to the absolute
+/// path when they share no root, e.g. on different Windows drives.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize(path);
+    let base = normalize(base);
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return path;
+    }
+    let mut out = PathBuf::new();
+    for _ in base.components().skip(common) {
+        out.push("..");
+    }
+    out.extend(path.components().skip(common));
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_dots() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("../x")), Path::new("../x"));
+        assert_eq!(normalize(Path::new("/..")), Path::new("/"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        let base = Path::new("/home/me/work");
+        assert_eq!(relative_to(Path::new("/home/me/work/app"), base), Path::new("app"));
+        assert_eq!(relative_to(Path::new("/home/me/lib"), base), Path::new("../lib"));
+        assert_eq!(relative_to(Path::new("/home/me/work"), base), Path::new("."));
+        assert_eq!(relative_to(Path::new("/opt/x"), base), Path::new("../../../opt/x"));
+        assert_eq!(relative_to(Path::new("/home/me/work/a/../b"), base), Path::new("b"));
+    }
+
+    #[test]
+    fn folders_round_trip_relative_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("ws").join(format!("team.{}", WORKSPACE_EXTENSION));
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        let mut workspace = Workspace::single(dir.path().join("ws").join("app"));
+        workspace.add_folder(dir.path().join("shared"));
+        workspace.folders[1].name = Some("Shared code".to_string());
+        workspace.settings.format_on_save = Some(true);
+        workspace.save(&file).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+        assert_eq!(json["folders"][0]["path"], "app");
+        assert_eq!(json["folders"][1]["path"], "../shared");
+        assert!(json["folders"][0].get("name").is_none());
+        assert!(json["settings"].get("autosave").is_none());
+
+        let loaded = Workspace::load(&file).unwrap();
+        let roots: Vec<&Path> = loaded.roots().collect();
+        assert_eq!(roots, [dir.path().join("ws").join("app"), dir.path().join("shared")]);
+        assert_eq!(loaded.folders[1].display_name(), "Shared code");
+        assert_eq!(loaded.settings.format_on_save, Some(true));
+        assert_eq!(loaded.title().as_deref(), Some("team"));
+    }
+
+    #[test]
+    fn root_of_picks_the_innermost_folder() {
+        let mut workspace = Workspace::single(PathBuf::from("/repo"));
+        workspace.add_folder(PathBuf::from("/repo/vendor/lib"));
+        assert!(!workspace.add_folder(PathBuf::from("/repo")));
+        assert_eq!(workspace.root_of(Path::new("/repo/vendor/lib/x.rs")), Some(Path::new("/repo/vendor/lib")));
+        assert_eq!(workspace.root_of(Path::new("/repo/src/main.rs")), Some(Path::new("/repo")));
+        assert_eq!(workspace.root_of(Path::new("/repository")), None);
+    }
+
+    #[test]
+    fn load_rejects_bad_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bad.hydroxite-workspace");
+        fs::write(&file, "{ not json").unwrap();
+        assert_eq!(Workspace::load(&file).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}